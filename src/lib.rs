@@ -0,0 +1,9435 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytes::{Buf, Bytes};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use futures::{
+    FutureExt, StreamExt, TryStreamExt,
+    future::{BoxFuture, Shared},
+};
+use hmac::{Hmac, Mac};
+use hyper::service::Service;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    Connection, DatabaseName, OptionalExtension, TransactionBehavior, functions::FunctionFlags,
+    params,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    cmp,
+    collections::{HashMap, VecDeque},
+    env, fs,
+    io::{self, Read},
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{broadcast, mpsc},
+};
+use tokio_util::io::ReaderStream;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+use warp::cors;
+use warp::{
+    Filter, Rejection, Reply,
+    http::{
+        HeaderValue, Method, StatusCode, Uri,
+        header::{
+            ACCEPT_LANGUAGE, ACCEPT_RANGES, CONNECTION, CONTENT_DISPOSITION, CONTENT_ENCODING,
+            CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LOCATION, VARY,
+        },
+    },
+    hyper::Body,
+    multipart::{FormData, Part},
+    reply::Response,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ModMetadata {
+    id: String,
+    title: String,
+    version: String,
+    thumbnail: String,
+    file_path: String,
+    description: String,
+    author: String,
+    tags: Vec<String>,
+    min_game_version: Option<String>,
+    source_url: Option<String>,
+    size_bytes: Option<u64>,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    sha256: Option<String>,
+    compression: String,
+    downloads: u64,
+}
+
+pub type DbConnection = r2d2::Pool<SqliteConnectionManager>;
+type PooledConnection = r2d2::PooledConnection<SqliteConnectionManager>;
+
+/// Logs an `info` event. The subscriber set up in `init_tracing` writes it to stderr
+/// and keeps it in `LOGS` for `/admin/logs/stream`.
+macro_rules! log_line {
+    ($($arg:tt)*) => {
+        tracing::info!($($arg)*)
+    };
+}
+
+const LOG_BUFFER_LINES: usize = 1000;
+
+static LOGS: LazyLock<LogBuffer> = LazyLock::new(LogBuffer::new);
+
+/// A bounded ring of recent log lines plus a channel announcing new ones, so a
+/// stream subscriber first sees the recent history and then follows along.
+struct LogBuffer {
+    recent: std::sync::Mutex<VecDeque<String>>,
+    updates: broadcast::Sender<String>,
+}
+
+impl LogBuffer {
+    fn new() -> Self {
+        LogBuffer {
+            recent: std::sync::Mutex::new(VecDeque::with_capacity(LOG_BUFFER_LINES)),
+            updates: broadcast::channel(256).0,
+        }
+    }
+
+    fn push(&self, line: String) {
+        eprintln!("{}", line);
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == LOG_BUFFER_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(line.clone());
+        // Sent while holding the lock so a subscriber never misses or repeats
+        // a line between taking its snapshot and subscribing.
+        let _ = self.updates.send(line);
+    }
+
+    fn subscribe(&self) -> (Vec<String>, broadcast::Receiver<String>) {
+        let recent = self.recent.lock().unwrap();
+        (recent.iter().cloned().collect(), self.updates.subscribe())
+    }
+}
+
+/// Collects one formatted tracing event and hands it to `LOGS` when dropped.
+struct LogWriter(Vec<u8>);
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.0);
+        let line = line.trim_end();
+        if !line.is_empty() {
+            LOGS.push(line.to_string());
+        }
+    }
+}
+
+/// Post-processing state of uploads whose hook runs in the background, keyed by
+/// mod id. Only the latest upload of each id is tracked.
+#[derive(Clone, Default)]
+struct ProcessingTracker {
+    statuses: Arc<std::sync::Mutex<HashMap<String, ProcessingStatus>>>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum ProcessingStatus {
+    Processing,
+    Complete,
+    Failed { error: String },
+}
+
+impl ProcessingTracker {
+    fn set(&self, id: &str, status: ProcessingStatus) {
+        self.statuses.lock().unwrap().insert(id.to_string(), status);
+    }
+
+    fn get(&self, id: &str) -> Option<ProcessingStatus> {
+        self.statuses.lock().unwrap().get(id).cloned()
+    }
+}
+
+/// Circuit breaker for the database. Repeated database errors flip it open,
+/// after which DB-backed routes answer 503 and `/metadata` is served from the
+/// last snapshot; the background probe closes it again once the database
+/// responds.
+#[derive(Clone, Default)]
+struct DbHealth {
+    state: Arc<DbHealthState>,
+}
+
+#[derive(Default)]
+struct DbHealthState {
+    failures: AtomicU32,
+    degraded: AtomicBool,
+    snapshot: std::sync::Mutex<Option<Arc<Vec<ModMetadata>>>>,
+}
+
+impl DbHealth {
+    fn is_degraded(&self) -> bool {
+        self.state.degraded.load(Ordering::Relaxed)
+    }
+
+    fn record_failure(&self, threshold: u32) {
+        if threshold == 0 {
+            return;
+        }
+        let failures = self.state.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= threshold && !self.state.degraded.swap(true, Ordering::Relaxed) {
+            log_line!(
+                "Database failed {} times in a row, entering degraded mode",
+                failures
+            );
+        }
+    }
+
+    fn record_success(&self) {
+        self.state.failures.store(0, Ordering::Relaxed);
+        if self.state.degraded.swap(false, Ordering::Relaxed) {
+            log_line!("Database recovered, leaving degraded mode");
+        }
+    }
+
+    fn set_snapshot(&self, mods: Vec<ModMetadata>) {
+        *self.state.snapshot.lock().unwrap() = Some(Arc::new(mods));
+    }
+
+    fn snapshot(&self) -> Option<Arc<Vec<ModMetadata>>> {
+        self.state.snapshot.lock().unwrap().clone()
+    }
+}
+
+/// The gzipped, unfiltered `/metadata` listing. It is built on the first
+/// request that accepts gzip and dropped whenever mod metadata changes. The
+/// generation keeps a listing built before a change from being stored after it.
+#[derive(Clone, Default)]
+struct MetadataCache {
+    state: Arc<std::sync::Mutex<MetadataCacheState>>,
+}
+
+#[derive(Default)]
+struct MetadataCacheState {
+    generation: u64,
+    gzip: Option<Bytes>,
+}
+
+impl MetadataCache {
+    fn get(&self) -> (u64, Option<Bytes>) {
+        let state = self.state.lock().unwrap();
+        (state.generation, state.gzip.clone())
+    }
+
+    fn store(&self, generation: u64, gzip: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            state.gzip = Some(gzip);
+        }
+    }
+
+    fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.gzip = None;
+    }
+}
+
+/// A change to the catalog, pushed to `/events` subscribers.
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum CatalogEvent {
+    Upserted { id: String },
+    Deleted { id: String },
+}
+
+impl CatalogEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            CatalogEvent::Upserted { .. } => "upserted",
+            CatalogEvent::Deleted { .. } => "deleted",
+        }
+    }
+}
+
+type RefetchResult = Result<serde_json::Value, RefetchError>;
+
+/// Why a refetch failed, cloneable so every request coalesced onto it gets the
+/// same answer.
+#[derive(Clone)]
+enum RefetchError {
+    Upstream(String),
+    File(String),
+    Db(String),
+}
+
+impl From<RefetchError> for Rejection {
+    fn from(error: RefetchError) -> Self {
+        match error {
+            RefetchError::Upstream(details) => warp::reject::custom(UpstreamError { details }),
+            RefetchError::File(details) => warp::reject::custom(FileError { details }),
+            RefetchError::Db(details) => warp::reject::custom(DbError { details }),
+        }
+    }
+}
+
+/// Refetches in flight, keyed by mod id. Concurrent refetches of one mod share
+/// a single fetch from its source, so a release spike hits the upstream once.
+/// Downloads read the local copy and never reach the upstream.
+/// Each refetch runs as its own task, so it finishes even if every waiting
+/// request goes away.
+#[derive(Clone, Default)]
+struct SingleFlight {
+    inflight: Arc<std::sync::Mutex<HashMap<String, Shared<BoxFuture<'static, RefetchResult>>>>>,
+}
+
+impl SingleFlight {
+    async fn run<Fut>(&self, key: &str, refetch: impl FnOnce() -> Fut) -> RefetchResult
+    where
+        Fut: Future<Output = RefetchResult> + Send + 'static,
+    {
+        let shared = self
+            .inflight
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert_with(|| {
+                let inflight = self.inflight.clone();
+                let key = key.to_string();
+                let refetch = refetch();
+                // The entry can only be removed once this lock is released,
+                // after it has been inserted.
+                let task = tokio::spawn(async move {
+                    let result = refetch.await;
+                    inflight.lock().unwrap().remove(&key);
+                    result
+                });
+                async move {
+                    task.await
+                        .unwrap_or_else(|e| Err(RefetchError::File(e.to_string())))
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+        shared.await
+    }
+}
+
+/// Fans catalog changes out to every open `/events` stream. Handlers publish
+/// once their change is committed; with nobody listening the event is dropped.
+#[derive(Clone)]
+struct CatalogEvents {
+    sender: broadcast::Sender<CatalogEvent>,
+}
+
+impl Default for CatalogEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CATALOG_EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl CatalogEvents {
+    fn publish(&self, event: CatalogEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<CatalogEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Operator-announced downtime. While a notice is set, every request without a
+/// valid admin key is answered with `503` and the notice's `Retry-After`.
+#[derive(Clone, Default)]
+struct Maintenance {
+    notice: Arc<std::sync::Mutex<Option<MaintenanceNotice>>>,
+}
+
+#[derive(Clone, Serialize)]
+struct MaintenanceNotice {
+    message: String,
+    retry_after_secs: u64,
+}
+
+impl Maintenance {
+    fn notice(&self) -> Option<MaintenanceNotice> {
+        self.notice.lock().unwrap().clone()
+    }
+
+    fn set(&self, notice: Option<MaintenanceNotice>) {
+        *self.notice.lock().unwrap() = notice;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    enable_metadata: bool,
+    pub enable_upload: bool,
+    enable_download: bool,
+    enable_readme: bool,
+    enable_setup: bool,
+    pub admin_key: Option<String>,
+    pub api_key: Option<String>,
+    pub post_upload_command: Option<String>,
+    post_upload_command_strict: bool,
+    pub post_upload_command_async: bool,
+    backup_interval: Option<Duration>,
+    backup_dir: PathBuf,
+    backup_retain: usize,
+    seed_dir: Option<PathBuf>,
+    pub mods_dir: PathBuf,
+    pub max_title_length: usize,
+    max_description_length: usize,
+    pub max_multipart_parts: usize,
+    pub max_file_bytes: u64,
+    pub max_thumbnail_bytes: u64,
+    pub max_import_bytes: u64,
+    pub overlong_fields: OverlongFieldMode,
+    pub json_case: JsonCase,
+    pub storage_format: StorageFormat,
+    db_pool_size: u32,
+    pub download_redirect_base: Option<String>,
+    pub download_mirrors: Vec<String>,
+    download_redirect_secret: Option<String>,
+    download_redirect_ttl: Duration,
+    pub audit_log: bool,
+    docs_url: String,
+    maintenance_message: String,
+    maintenance_retry_after: Duration,
+    pub storage_retry: RetryPolicy,
+    pub upload_rate: Option<RateLimit>,
+    pub download_bytes_per_sec: Option<u64>,
+    pub keep_versions: bool,
+    pub quarantine_uploads: bool,
+    pub auto_gzip: bool,
+    pub optimize_thumbnails: bool,
+    pub require_if_match: bool,
+    pub cors_read_origins: CorsOrigins,
+    pub cors_write_origins: CorsOrigins,
+    pub required_archive_entry: Option<String>,
+    pub degrade_after_failures: u32,
+    pub db_probe_interval: Duration,
+    idempotency_ttl: Duration,
+    reservation_ttl: Duration,
+    keep_alive: bool,
+    tcp_keepalive: Option<Duration>,
+    pub header_read_timeout: Duration,
+    request_timeout: Option<Duration>,
+}
+
+/// Bounded exponential backoff for storage operations that fail transiently.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Runs `operation` until it succeeds, fails with an error that is not
+    /// transient, or has been tried `attempts` times; the delay doubles after
+    /// each failed try. The last error is returned.
+    pub async fn run<T, F, Fut>(self, what: &str, mut operation: F) -> io::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
+        let mut delay = self.base_delay;
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    log_line!(
+                        "{} failed (attempt {} of {}), retrying in {:?}: {}",
+                        what,
+                        attempt,
+                        self.attempts,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A token bucket's refill rate and size.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+/// Per-client token buckets. Each request takes a token; an empty bucket
+/// tells the client how long until the next token is refilled.
+#[derive(Clone)]
+struct RateLimiter {
+    limit: RateLimit,
+    buckets: Arc<std::sync::Mutex<HashMap<String, TokenBucket>>>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Buckets kept before refilled ones are dropped; a full bucket is the same
+/// as no bucket.
+const MAX_RATE_LIMIT_BUCKETS: usize = 10_000;
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Arc::default(),
+        }
+    }
+
+    fn try_acquire(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let limit = self.limit;
+        let refill = |bucket: &mut TokenBucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst);
+            bucket.updated = now;
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_RATE_LIMIT_BUCKETS {
+            buckets.retain(|_, bucket| {
+                refill(bucket);
+                bucket.tokens < limit.burst
+            });
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(TokenBucket {
+            tokens: limit.burst,
+            updated: now,
+        });
+        refill(bucket);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.per_second,
+            ))
+        }
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// The origins one CORS policy accepts.
+#[derive(Debug, Clone)]
+pub enum CorsOrigins {
+    Any,
+    Only(Vec<String>),
+}
+
+/// How uploaded mod files are compressed on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageFormat {
+    Gzip,
+    Zstd,
+}
+
+impl StorageFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "gzip" => Some(StorageFormat::Gzip),
+            "zstd" => Some(StorageFormat::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The name recorded in a mod row's `compression`.
+    fn name(self) -> &'static str {
+        match self {
+            StorageFormat::Gzip => "gzip",
+            StorageFormat::Zstd => "zstd",
+        }
+    }
+}
+
+/// How field names are written in JSON responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonCase {
+    Snake,
+    Camel,
+}
+
+/// What to do with a text field longer than its configured limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlongFieldMode {
+    Reject,
+    Truncate,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            enable_metadata: env_flag("MOD_DB_ENABLE_METADATA", true),
+            enable_upload: env_flag("MOD_DB_ENABLE_UPLOAD", true),
+            enable_download: env_flag("MOD_DB_ENABLE_DOWNLOAD", true),
+            enable_readme: env_flag("MOD_DB_ENABLE_README", true),
+            enable_setup: env_flag("MOD_DB_ENABLE_SETUP", true),
+            admin_key: env::var("MOD_DB_ADMIN_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            api_key: env::var("MOD_DB_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            post_upload_command: env::var("MOD_DB_POST_UPLOAD_COMMAND")
+                .ok()
+                .filter(|command| !command.trim().is_empty()),
+            post_upload_command_strict: env_flag("MOD_DB_POST_UPLOAD_COMMAND_STRICT", false),
+            post_upload_command_async: env_flag("MOD_DB_POST_UPLOAD_COMMAND_ASYNC", false),
+            backup_interval: env_parse::<u64>("MOD_DB_BACKUP_INTERVAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            backup_dir: env::var("MOD_DB_BACKUP_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("backups")),
+            backup_retain: env_parse("MOD_DB_BACKUP_RETAIN").unwrap_or(7),
+            seed_dir: env::var("MOD_DB_SEED_DIR")
+                .ok()
+                .filter(|dir| !dir.trim().is_empty())
+                .map(PathBuf::from),
+            mods_dir: PathBuf::from("mods"),
+            max_title_length: env_parse("MOD_DB_MAX_TITLE_LENGTH").unwrap_or(200),
+            max_description_length: env_parse("MOD_DB_MAX_DESCRIPTION_LENGTH").unwrap_or(5000),
+            max_multipart_parts: env_parse("MOD_DB_MAX_MULTIPART_PARTS").unwrap_or(64),
+            max_file_bytes: env_parse("MOD_DB_MAX_FILE_BYTES").unwrap_or(MAX_UPLOAD_BYTES),
+            max_thumbnail_bytes: env_parse("MOD_DB_MAX_THUMBNAIL_BYTES")
+                .unwrap_or(MAX_THUMBNAIL_BYTES),
+            max_import_bytes: env_parse("MOD_DB_MAX_IMPORT_BYTES").unwrap_or(MAX_IMPORT_BYTES),
+            download_redirect_base: env::var("MOD_DB_DOWNLOAD_REDIRECT_BASE")
+                .ok()
+                .map(|base| base.trim_end_matches('/').to_string())
+                .filter(|base| !base.is_empty()),
+            download_mirrors: env_mirrors("MOD_DB_DOWNLOAD_MIRRORS"),
+            download_redirect_secret: env::var("MOD_DB_DOWNLOAD_REDIRECT_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            download_redirect_ttl: Duration::from_secs(
+                env_parse("MOD_DB_DOWNLOAD_REDIRECT_TTL_SECS").unwrap_or(300),
+            ),
+            audit_log: env_flag("MOD_DB_AUDIT_LOG", false),
+            docs_url: env::var("MOD_DB_DOCS_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty())
+                .unwrap_or_else(|| "/capabilities".to_string()),
+            maintenance_message: env::var("MOD_DB_MAINTENANCE_MESSAGE")
+                .ok()
+                .filter(|message| !message.trim().is_empty())
+                .unwrap_or_else(|| "The server is down for planned maintenance.".to_string()),
+            maintenance_retry_after: Duration::from_secs(
+                env_parse("MOD_DB_MAINTENANCE_RETRY_AFTER_SECS").unwrap_or(300),
+            ),
+            keep_versions: env_flag("MOD_DB_KEEP_VERSIONS", false),
+            quarantine_uploads: env_flag("MOD_DB_QUARANTINE_UPLOADS", false),
+            auto_gzip: env_flag("MOD_DB_AUTO_GZIP", false),
+            optimize_thumbnails: env_flag("MOD_DB_OPTIMIZE_THUMBNAILS", false),
+            require_if_match: env_flag("MOD_DB_REQUIRE_IF_MATCH", false),
+            storage_retry: RetryPolicy {
+                attempts: env_parse::<u32>("MOD_DB_STORAGE_RETRY_ATTEMPTS")
+                    .unwrap_or(3)
+                    .max(1),
+                base_delay: Duration::from_millis(
+                    env_parse("MOD_DB_STORAGE_RETRY_BASE_DELAY_MS").unwrap_or(100),
+                ),
+            },
+            upload_rate: env_parse::<f64>("MOD_DB_UPLOAD_RATE_PER_MINUTE")
+                .filter(|per_minute| *per_minute > 0.0)
+                .map(|per_minute| RateLimit {
+                    per_second: per_minute / 60.0,
+                    burst: env_parse::<f64>("MOD_DB_UPLOAD_BURST")
+                        .unwrap_or(per_minute)
+                        .max(1.0),
+                }),
+            download_bytes_per_sec: env_parse::<u64>("MOD_DB_DOWNLOAD_BYTES_PER_SEC")
+                .filter(|rate| *rate > 0),
+            cors_read_origins: env_cors_origins("MOD_DB_CORS_READ_ORIGINS"),
+            cors_write_origins: env_cors_origins("MOD_DB_CORS_WRITE_ORIGINS"),
+            required_archive_entry: env::var("MOD_DB_REQUIRED_ARCHIVE_ENTRY")
+                .ok()
+                .map(|entry| entry.trim().trim_start_matches("./").to_string())
+                .filter(|entry| !entry.is_empty()),
+            degrade_after_failures: env_parse("MOD_DB_DEGRADE_AFTER_FAILURES").unwrap_or(3),
+            db_probe_interval: Duration::from_secs(
+                env_parse::<u64>("MOD_DB_DB_PROBE_INTERVAL_SECS")
+                    .filter(|secs| *secs > 0)
+                    .unwrap_or(30),
+            ),
+            idempotency_ttl: Duration::from_secs(
+                env_parse("MOD_DB_IDEMPOTENCY_TTL_SECS").unwrap_or(24 * 60 * 60),
+            ),
+            reservation_ttl: Duration::from_secs(
+                env_parse::<u64>("MOD_DB_RESERVATION_TTL_SECS")
+                    .filter(|secs| *secs > 0)
+                    .unwrap_or(5 * 60),
+            ),
+            keep_alive: env_flag("MOD_DB_KEEP_ALIVE", true),
+            tcp_keepalive: Some(env_parse::<u64>("MOD_DB_TCP_KEEPALIVE_SECS").unwrap_or(60))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            header_read_timeout: Duration::from_secs(
+                env_parse::<u64>("MOD_DB_HEADER_READ_TIMEOUT_SECS")
+                    .filter(|secs| *secs > 0)
+                    .unwrap_or(30),
+            ),
+            request_timeout: Some(env_parse::<u64>("MOD_DB_REQUEST_TIMEOUT_SECS").unwrap_or(600))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            overlong_fields: match env::var("MOD_DB_OVERLONG_FIELDS").as_deref() {
+                Ok("truncate") => OverlongFieldMode::Truncate,
+                _ => OverlongFieldMode::Reject,
+            },
+            db_pool_size: env_parse::<u32>("MOD_DB_POOL_SIZE")
+                .filter(|size| *size > 0)
+                .unwrap_or(8),
+            json_case: match env::var("MOD_DB_JSON_CASE").as_deref() {
+                Ok("camel") => JsonCase::Camel,
+                _ => JsonCase::Snake,
+            },
+            storage_format: env::var("MOD_DB_STORAGE_FORMAT")
+                .ok()
+                .and_then(|format| StorageFormat::parse(&format))
+                .unwrap_or(StorageFormat::Gzip),
+        }
+    }
+
+    /// Limits both CORS policies to `origins`, overriding
+    /// `MOD_DB_CORS_READ_ORIGINS` and `MOD_DB_CORS_WRITE_ORIGINS`. An empty
+    /// list leaves them as configured.
+    pub fn restrict_cors_origins(&mut self, origins: &[String]) {
+        if origins.is_empty() {
+            return;
+        }
+        let origins = parse_cors_origins(origins.iter().map(String::as_str), "--cors-origin");
+        self.cors_read_origins = origins.clone();
+        self.cors_write_origins = origins;
+    }
+
+    /// Path of `name` inside the mods directory, as stored in `file_path`.
+    fn mod_path(&self, name: impl AsRef<Path>) -> String {
+        self.mods_dir.join(name).to_string_lossy().into_owned()
+    }
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(value) => matches!(
+            value.to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        ),
+        Err(_) => default,
+    }
+}
+
+/// Reads a comma-separated list of origins such as `https://example.com`, or
+/// `*` (the default) for any origin. Invalid entries are logged and skipped.
+fn env_cors_origins(name: &str) -> CorsOrigins {
+    let Ok(value) = env::var(name) else {
+        return CorsOrigins::Any;
+    };
+    parse_cors_origins(value.split(','), name)
+}
+
+/// Validates origins from `source`, keeping only scheme and authority. A `*`
+/// entry allows any origin.
+fn parse_cors_origins<'a>(origins: impl IntoIterator<Item = &'a str>, source: &str) -> CorsOrigins {
+    let origins: Vec<_> = origins
+        .into_iter()
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.contains(&"*") {
+        return CorsOrigins::Any;
+    }
+
+    let origins = origins
+        .into_iter()
+        .filter_map(|origin| {
+            let parsed = origin.parse::<Uri>().ok().filter(|uri| {
+                uri.path_and_query()
+                    .is_none_or(|path| path.as_str().is_empty() || path.as_str() == "/")
+            });
+            match parsed {
+                Some(uri) if uri.scheme().is_some() && uri.authority().is_some() => Some(format!(
+                    "{}://{}",
+                    uri.scheme_str().unwrap_or_default(),
+                    uri.authority().map(|a| a.as_str()).unwrap_or_default()
+                )),
+                _ => {
+                    log_line!("Ignoring invalid origin `{}` in {}", origin, source);
+                    None
+                }
+            }
+        })
+        .collect();
+    CorsOrigins::Only(origins)
+}
+
+/// Reads a comma-separated list of mirror URLs for `/mods/{id}/sources`.
+/// Each is either a template containing `{id}` or the base URL of another
+/// instance. Entries that aren't absolute http or https URLs are logged and
+/// skipped.
+fn env_mirrors(name: &str) -> Vec<String> {
+    let Ok(value) = env::var(name) else {
+        return Vec::new();
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|mirror| !mirror.is_empty())
+        .filter_map(|mirror| {
+            let template = if mirror.contains("{id}") {
+                mirror.to_string()
+            } else {
+                format!("{}/download/{{id}}", mirror.trim_end_matches('/'))
+            };
+            match parse_source_url(&template.replace("{id}", "id")) {
+                Ok(Some(_)) => Some(template),
+                _ => {
+                    log_line!("Ignoring invalid mirror `{}` in {}", mirror, name);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+const MAX_UPLOAD_BYTES: u64 = 1000 * 1024 * 1024;
+const MAX_THUMBNAIL_BYTES: u64 = 2 * 1024 * 1024;
+const MAX_IMPORT_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+const MAX_README_BYTES: usize = 256 * 1024;
+const MAX_CHUNK_BYTES: u64 = 100 * 1024 * 1024;
+const MAX_PATCH_BYTES: u64 = MAX_README_BYTES as u64 + 64 * 1024;
+const METADATA_STREAM_BUFFER: usize = 32;
+const METADATA_FLUSH_BYTES: usize = 16 * 1024;
+const DOWNLOAD_CHUNK_BYTES: usize = 64 * 1024;
+const MAX_BYTE_RANGES: usize = 64;
+const MAX_AUDIT_RESULTS: u32 = 500;
+const MAX_CHANGES_RESULTS: u32 = 1000;
+const MAX_MODPACK_ENTRIES: usize = 1000;
+const MAX_METADATA_BATCH_ENTRIES: usize = 1000;
+const MAX_VERIFY_ENTRIES: usize = 1000;
+const MAX_UPDATE_SIZE_ENTRIES: usize = 1000;
+const MAX_SEARCH_RESULTS: u32 = 200;
+const MAX_ANALYTICS_DAYS: i64 = 366;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+const MAX_SOURCE_URL_LENGTH: usize = 2048;
+const SOURCE_FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+const THUMBNAIL_OPTIMIZE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `/health` waits for a pooled connection before reporting the
+/// database as degraded.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Changes a slow `/events` subscriber may fall behind by before it lags.
+const CATALOG_EVENT_BUFFER: usize = 256;
+const MANIFEST_ENTRY: &str = "mod.json";
+const MAX_MANIFEST_BYTES: u64 = 64 * 1024;
+/// Listings point at `/thumbnail/{id}` instead of inlining the base64 image,
+/// which would make every listing grow with the thumbnails' size.
+const METADATA_COLUMNS: &str = "id, title, version, \
+     CASE WHEN thumbnail = '' THEN '' ELSE '/thumbnail/' || id END AS thumbnail, \
+     file_path, description, author, \
+     (SELECT group_concat(tag, ',' ORDER BY tag) FROM mod_tags WHERE mod_tags.mod_id = mods.id) AS tags, \
+     min_game_version, source_url, size_bytes, created_at, updated_at, sha256, compression, downloads";
+/// `METADATA_COLUMNS` for an upload held in `pending_uploads`, which is
+/// dated by when it was uploaded.
+const PENDING_COLUMNS: &str = "id, title, version, thumbnail, file_path, description, author, tags, \
+     min_game_version, source_url, size_bytes, NULL AS created_at, uploaded_at AS updated_at, \
+     sha256, compression, 0 AS downloads";
+/// Like `METADATA_COLUMNS` but with the stored thumbnail, for exports that
+/// must be restorable on their own.
+const EXPORT_COLUMNS: &str = "id, title, version, thumbnail, file_path, description, author, \
+     (SELECT group_concat(tag, ',' ORDER BY tag) FROM mod_tags WHERE mod_tags.mod_id = mods.id) AS tags, \
+     min_game_version, source_url, size_bytes, created_at, updated_at, sha256, compression, downloads";
+
+/// The optional `mod.json` at the top of an uploaded archive. Its fields fill in
+/// metadata the upload form leaves out.
+#[derive(Deserialize)]
+struct ModManifest {
+    title: Option<String>,
+    version: Option<String>,
+    author: Option<String>,
+    description: Option<String>,
+}
+
+struct ArchiveContents {
+    has_required_entry: bool,
+    manifest: Option<ModManifest>,
+}
+
+#[derive(Deserialize)]
+struct MetadataQuery {
+    q: Option<String>,
+    game_version: Option<String>,
+    tags: Option<String>,
+    /// Single-tag alias for `tags`, matching `/mods?tag=`.
+    tag: Option<String>,
+    tag_mode: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+/// The parsed filters of a `/metadata` request.
+struct MetadataFilter {
+    /// Folded `q`, matched against the title and the id.
+    text: Option<String>,
+    game_version: Option<semver::Version>,
+    tags: Vec<String>,
+    match_all_tags: bool,
+    /// Set when any paging parameter is given; the listing is then answered
+    /// as one page with the total instead of a bare array.
+    page: Option<MetadataPage>,
+}
+
+#[derive(Clone, Copy)]
+enum MetadataSort {
+    Title,
+    Version,
+    CreatedAt,
+}
+
+#[derive(Clone, Copy)]
+struct MetadataPage {
+    sort: MetadataSort,
+    descending: bool,
+    limit: u32,
+    offset: u32,
+}
+
+impl MetadataPage {
+    fn parse(query: &MetadataQuery) -> Result<Option<Self>, String> {
+        if query.sort.is_none()
+            && query.order.is_none()
+            && query.limit.is_none()
+            && query.offset.is_none()
+        {
+            return Ok(None);
+        }
+        // Sort names map to fixed columns so user input never reaches the SQL.
+        let (sort, default_descending) = match query.sort.as_deref().unwrap_or("title") {
+            "title" => (MetadataSort::Title, false),
+            "version" => (MetadataSort::Version, true),
+            "created_at" => (MetadataSort::CreatedAt, true),
+            other => {
+                return Err(format!(
+                    "invalid sort `{}`, expected `title`, `version`, or `created_at`",
+                    other
+                ));
+            }
+        };
+        let descending = match query.order.as_deref() {
+            None => default_descending,
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => {
+                return Err(format!(
+                    "invalid order `{}`, expected `asc` or `desc`",
+                    other
+                ));
+            }
+        };
+        Ok(Some(Self {
+            sort,
+            descending,
+            limit: query.limit.unwrap_or(50).min(MAX_SEARCH_RESULTS),
+            offset: query.offset.unwrap_or(0),
+        }))
+    }
+
+    fn order_by(&self) -> String {
+        let column = match self.sort {
+            MetadataSort::Title => "title_normalized",
+            MetadataSort::Version => "version COLLATE semver",
+            MetadataSort::CreatedAt => "COALESCE(created_at, 0)",
+        };
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        format!("{} {}, id", column, direction)
+    }
+
+    /// Sorts and slices an in-memory listing the way `order_by` and the
+    /// `LIMIT`/`OFFSET` of the page query would.
+    fn apply<'a>(&self, mut mods: Vec<&'a ModMetadata>) -> Vec<&'a ModMetadata> {
+        mods.sort_by(|a, b| {
+            let ordering = match self.sort {
+                MetadataSort::Title => fold_for_search(&a.title).cmp(&fold_for_search(&b.title)),
+                MetadataSort::Version => compare_versions(&a.version, &b.version),
+                MetadataSort::CreatedAt => {
+                    a.created_at.unwrap_or(0).cmp(&b.created_at.unwrap_or(0))
+                }
+            };
+            let ordering = if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            ordering.then_with(|| a.id.cmp(&b.id))
+        });
+        mods.into_iter()
+            .skip(self.offset as usize)
+            .take(self.limit as usize)
+            .collect()
+    }
+
+    fn body<T: Serialize>(&self, total: u64, items: &[T]) -> serde_json::Value {
+        json!({
+            "items": items,
+            "total": total,
+            "limit": self.limit,
+            "offset": self.offset,
+        })
+    }
+}
+
+impl MetadataFilter {
+    fn parse(query: &MetadataQuery) -> Result<Self, String> {
+        let game_version = match query.game_version.as_deref() {
+            Some(version) => Some(parse_game_version(version)?),
+            None => None,
+        };
+        let match_all_tags = match query.tag_mode.as_deref() {
+            None | Some("all") => true,
+            Some("any") => false,
+            Some(other) => {
+                return Err(format!(
+                    "invalid tag_mode `{}`, expected `all` or `any`",
+                    other
+                ));
+            }
+        };
+        let mut tags = query.tags.as_deref().map(parse_tags).unwrap_or_default();
+        for tag in query.tag.as_deref().map(parse_tags).unwrap_or_default() {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        Ok(Self {
+            text: query
+                .q
+                .as_deref()
+                .filter(|q| !q.trim().is_empty())
+                .map(fold_for_search),
+            game_version,
+            tags,
+            match_all_tags,
+            page: MetadataPage::parse(query)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_none() && self.game_version.is_none() && self.tags.is_empty()
+    }
+
+    /// Builds the `WHERE` clause for every filter, or an empty string when
+    /// there is none.
+    fn conditions(&self) -> (String, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        // LIKE already ignores ASCII case, which covers ids; titles are
+        // compared folded so accents are ignored too.
+        if let Some(text) = &self.text {
+            values.push(format!("%{}%", escape_like(text)));
+            conditions.push(format!(
+                "(title_normalized LIKE ?{0} ESCAPE '\\' OR id LIKE ?{0} ESCAPE '\\')",
+                values.len()
+            ));
+        }
+        if !self.tags.is_empty() {
+            let mut placeholders = Vec::new();
+            for tag in &self.tags {
+                values.push(tag.clone());
+                placeholders.push(format!("?{}", values.len()));
+            }
+            let mut matching = format!(
+                "id IN (SELECT mod_id FROM mod_tags WHERE tag IN ({})",
+                placeholders.join(", ")
+            );
+            if self.match_all_tags {
+                matching.push_str(&format!(
+                    " GROUP BY mod_id HAVING COUNT(*) = {}",
+                    self.tags.len()
+                ));
+            }
+            matching.push(')');
+            conditions.push(matching);
+        }
+        if let Some(version) = &self.game_version {
+            values.push(version.to_string());
+            conditions.push(format!(
+                "supports_game_version(min_game_version, ?{})",
+                values.len()
+            ));
+        }
+
+        if conditions.is_empty() {
+            (String::new(), values)
+        } else {
+            (format!(" WHERE {}", conditions.join(" AND ")), values)
+        }
+    }
+
+    fn sql(&self) -> (String, Vec<String>) {
+        let (conditions, values) = self.conditions();
+        (
+            format!("SELECT {} FROM mods{}", METADATA_COLUMNS, conditions),
+            values,
+        )
+    }
+
+    /// Applies every filter in memory, for listings not read from the database.
+    fn matches(&self, metadata: &ModMetadata) -> bool {
+        let has_tag = |tag: &String| metadata.tags.contains(tag);
+        self.text.as_deref().is_none_or(|text| {
+            fold_for_search(&metadata.title).contains(text)
+                || metadata.id.to_lowercase().contains(text)
+        }) && (self.tags.is_empty()
+            || if self.match_all_tags {
+                self.tags.iter().all(has_tag)
+            } else {
+                self.tags.iter().any(has_tag)
+            })
+            && self
+                .game_version
+                .as_ref()
+                .is_none_or(|version| supports_game_version(metadata, version))
+    }
+}
+
+#[derive(Deserialize)]
+struct FullSearchQuery {
+    q: String,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ModsQuery {
+    q: Option<String>,
+    tag: Option<String>,
+    author: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ModpackCheckRequest {
+    mods: Vec<ModpackEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModpackEntry {
+    id: String,
+    version: String,
+}
+
+#[derive(Serialize)]
+struct ModpackStatus {
+    id: String,
+    installed_version: String,
+    latest_version: Option<String>,
+    status: &'static str,
+}
+
+#[derive(Deserialize)]
+struct MetadataBatchRequest {
+    ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct VerifyFilesRequest {
+    files: Vec<VerifyFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct VerifyFileEntry {
+    id: String,
+    checksum: String,
+}
+
+#[derive(Serialize)]
+struct VerifyFileStatus {
+    id: String,
+    status: &'static str,
+    expected_checksum: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UpdateSizeRequest {
+    mods: Vec<UpdateSizeEntry>,
+}
+
+#[derive(Deserialize)]
+struct UpdateSizeEntry {
+    id: String,
+    have_version: Option<String>,
+}
+
+#[derive(Serialize)]
+struct UpdateSizeStatus {
+    id: String,
+    status: &'static str,
+    version: Option<String>,
+    bytes: u64,
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+    message: Option<String>,
+    retry_after_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ProtectedRequest {
+    protected: bool,
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    action: Option<String>,
+    target_id: Option<String>,
+    identity: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    after: Option<i64>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ChangeEntry {
+    cursor: i64,
+    id: String,
+    action: String,
+    changed_at: i64,
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    id: i64,
+    created_at: i64,
+    identity: String,
+    action: String,
+    target_id: String,
+    details: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnalyticsQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PopularQuery {
+    window: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct PopularMod {
+    id: String,
+    title: String,
+    version: String,
+    downloads: i64,
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    version: Option<String>,
+    verify: Option<bool>,
+}
+
+/// A stored version of a mod. Versions kept before their metadata was
+/// recorded have only the version, upload time, and checksum.
+#[derive(Serialize)]
+struct ModVersion {
+    version: String,
+    uploaded_at: Option<i64>,
+    latest: bool,
+    title: Option<String>,
+    description: Option<String>,
+    author: Option<String>,
+    tags: Vec<String>,
+    min_game_version: Option<String>,
+    source_url: Option<String>,
+    size_bytes: Option<u64>,
+    sha256: Option<String>,
+    compression: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LatestQuery {
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct RelatedQuery {
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct RelatedMod {
+    #[serde(flatten)]
+    metadata: ModMetadata,
+    shared_tags: u32,
+    same_author: bool,
+}
+
+#[derive(Deserialize)]
+struct SetupQuery {
+    #[serde(default)]
+    reset: bool,
+    #[serde(default)]
+    confirm: bool,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Deserialize)]
+struct RecompressQuery {
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReadmeQuery {
+    format: Option<String>,
+    version: Option<String>,
+}
+
+#[derive(Debug)]
+struct DbError {
+    details: String,
+}
+impl warp::reject::Reject for DbError {}
+
+#[derive(Debug)]
+struct UploadError {
+    details: String,
+}
+impl warp::reject::Reject for UploadError {}
+
+#[derive(Debug)]
+struct FileError {
+    details: String,
+}
+impl warp::reject::Reject for FileError {}
+
+#[derive(Debug)]
+struct QueryError {
+    details: String,
+}
+impl warp::reject::Reject for QueryError {}
+
+#[derive(Debug)]
+struct GoneError {
+    details: String,
+}
+impl warp::reject::Reject for GoneError {}
+
+#[derive(Debug)]
+struct UnavailableError {
+    details: String,
+}
+impl warp::reject::Reject for UnavailableError {}
+
+#[derive(Debug)]
+struct RateLimitError {
+    retry_after: Duration,
+}
+impl warp::reject::Reject for RateLimitError {}
+
+#[derive(Debug)]
+struct MaintenanceError {
+    message: String,
+    retry_after_secs: u64,
+}
+impl warp::reject::Reject for MaintenanceError {}
+
+/// A missing mod. Unlike `warp::reject::not_found()` this is not outranked by a
+/// neighbouring route's method mismatch, so it still answers 404.
+#[derive(Debug)]
+struct NotFoundError {
+    details: String,
+}
+impl warp::reject::Reject for NotFoundError {}
+
+#[derive(Debug)]
+struct ValidationError {
+    details: String,
+}
+impl warp::reject::Reject for ValidationError {}
+
+#[derive(Debug)]
+struct AuthError {
+    details: String,
+}
+impl warp::reject::Reject for AuthError {}
+
+#[derive(Debug)]
+struct LockedError {
+    details: String,
+}
+
+impl warp::reject::Reject for LockedError {}
+
+#[derive(Debug)]
+struct ConflictError {
+    details: String,
+}
+
+impl warp::reject::Reject for ConflictError {}
+
+#[derive(Debug)]
+struct PreconditionFailedError {
+    details: String,
+}
+impl warp::reject::Reject for PreconditionFailedError {}
+
+#[derive(Debug)]
+struct PreconditionRequiredError {
+    details: String,
+}
+impl warp::reject::Reject for PreconditionRequiredError {}
+
+#[derive(Debug)]
+struct UpstreamError {
+    details: String,
+}
+impl warp::reject::Reject for UpstreamError {}
+
+#[derive(Debug)]
+struct HookError {
+    details: String,
+}
+impl warp::reject::Reject for HookError {}
+
+#[derive(Debug)]
+struct PayloadTooLargeError {
+    details: String,
+}
+impl warp::reject::Reject for PayloadTooLargeError {}
+
+/// Sets up logging to stderr and to the buffer behind `/admin/logs/stream`,
+/// filtered by `RUST_LOG` (default `info`).
+pub fn init_tracing() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .with_ansi(false)
+        .with_writer(|| LogWriter(Vec::new()))
+        .init();
+}
+
+/// Opens the database at `db_path`, prepares the storage directories, starts
+/// the background jobs `config` asks for, and serves on `addr` until the
+/// server fails.
+pub async fn run(config: Config, db_path: &Path, thumbnails_dir: &Path, addr: SocketAddr) {
+    if let Some(dir) = db_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).expect("Failed to create database directory");
+    }
+    let db = open_database(db_path, config.db_pool_size).expect("Failed to open database");
+    setup_db(&db.get().expect("Failed to open database")).expect("Failed to setup database");
+
+    fs::create_dir_all(thumbnails_dir).expect("Failed to create thumbnails directory");
+    fs::create_dir_all(&config.mods_dir).expect("Failed to create mods directory");
+
+    if let Some(seed_dir) = &config.seed_dir
+        && let Err(e) = seed_from_dir(&db, &config, seed_dir).await
+    {
+        log_line!(
+            "Failed to read seed directory {}: {}",
+            seed_dir.display(),
+            e
+        );
+    }
+
+    if let Some(interval) = config.backup_interval {
+        fs::create_dir_all(&config.backup_dir).expect("Failed to create backup directory");
+        tokio::spawn(run_backup_schedule(
+            db.clone(),
+            interval,
+            config.backup_dir.clone(),
+            config.backup_retain,
+        ));
+    }
+
+    let (addr, server) = bind_server(addr, db, Arc::new(config));
+    log_line!("Server started on {}", addr);
+    if let Err(e) = server.await {
+        log_line!("Server error: {}", e);
+    }
+}
+
+/// Binds `addr` and serves `app_service` on it with the connection settings
+/// from `config`, returning the bound address and the server to await. Panics
+/// if `addr` can't be bound.
+pub fn bind_server(
+    addr: SocketAddr,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> (SocketAddr, impl Future<Output = Result<(), hyper::Error>>) {
+    let service = app_service(db, config.clone());
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let service = service.clone();
+        async move { Ok::<_, std::convert::Infallible>(service) }
+    });
+
+    let server = hyper::Server::bind(&addr)
+        .tcp_keepalive(config.tcp_keepalive)
+        .http1_keepalive(config.keep_alive)
+        .http1_header_read_timeout(config.header_read_timeout)
+        .serve(make_service);
+    (server.local_addr(), server)
+}
+
+/// Builds every route, with `config` deciding which are enabled and how they
+/// behave. Starts the database probe when the circuit breaker is on, so it
+/// must be called from within a Tokio runtime.
+pub fn build_routes(
+    db: DbConnection,
+    config: Arc<Config>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let health = DbHealth::default();
+    if config.degrade_after_failures > 0 {
+        tokio::spawn(run_db_probe(
+            db.clone(),
+            health.clone(),
+            config.db_probe_interval,
+            config.degrade_after_failures,
+        ));
+    }
+    let health_filter = {
+        let health = health.clone();
+        warp::any().map(move || health.clone())
+    };
+
+    let db_filter = warp::any().map(move || db.clone());
+    let upload_limiter = config.upload_rate.map(RateLimiter::new);
+    let maintenance = Maintenance::default();
+    let maintenance_filter = {
+        let maintenance = maintenance.clone();
+        warp::any().map(move || maintenance.clone())
+    };
+    let metadata_cache = MetadataCache::default();
+    let cache_filter = warp::any().map(move || metadata_cache.clone());
+    let processing = ProcessingTracker::default();
+    let processing_filter = warp::any().map(move || processing.clone());
+    let refetches = SingleFlight::default();
+    let refetches_filter = warp::any().map(move || refetches.clone());
+    let catalog_events = CatalogEvents::default();
+    let events_filter = warp::any().map(move || catalog_events.clone());
+    let config_filter = {
+        let config = config.clone();
+        warp::any().map(move || config.clone())
+    };
+
+    let get_metadata = warp::path("metadata")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<MetadataQuery>())
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(db_filter.clone())
+        .and(health_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(handle_get_metadata);
+
+    let metadata_batch = warp::path!("metadata" / "batch")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::json::<MetadataBatchRequest>())
+        .and(db_filter.clone())
+        .and_then(handle_metadata_batch);
+
+    let full_search = warp::path!("search" / "full")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<FullSearchQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_full_search);
+
+    let list_mods = warp::path("mods")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<ModsQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_list_mods);
+
+    let changes = warp::path("changes")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<ChangesQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_changes);
+
+    let modpack_check = warp::path!("modpack" / "check")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::json::<ModpackCheckRequest>())
+        .and(db_filter.clone())
+        .and_then(handle_modpack_check);
+
+    let verify_files = warp::path("verify-files")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::json::<VerifyFilesRequest>())
+        .and(db_filter.clone())
+        .and_then(handle_verify_files);
+
+    let update_size = warp::path("update-size")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::json::<UpdateSizeRequest>())
+        .and(db_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_update_size);
+
+    let patch_metadata = warp::path!("metadata" / String)
+        .and(route_enabled(config.enable_upload))
+        .and(warp::patch())
+        .and(require_api_key(config.clone()))
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::bytes())
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_patch_metadata);
+
+    let upload = warp::path("upload")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_upload))
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(upload_rate_limit(upload_limiter, config.clone()))
+        .and(upload_options(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(processing_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        // Leaves room for the thumbnail and the text fields next to the
+        // largest file, so oversized parts get a 413 naming the limit.
+        .and(
+            warp::multipart::form().max_length(
+                config
+                    .max_file_bytes
+                    .saturating_add(config.max_thumbnail_bytes)
+                    .saturating_add(MAX_PATCH_BYTES),
+            ),
+        )
+        .and_then(handle_upload);
+
+    let reserve = warp::path!("mods" / String / "reserve")
+        .and(route_enabled(config.enable_upload))
+        .and(warp::post())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(warp::header::optional::<String>("x-reservation-token"))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_reserve);
+
+    let refetch = warp::path!("mods" / String / "refetch")
+        .and(route_enabled(config.enable_upload))
+        .and(warp::post())
+        .and(require_admin_key(config.clone()))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and(refetches_filter)
+        .and_then(handle_refetch);
+
+    let delete = warp::path!("delete" / String)
+        .and(route_enabled(config.enable_upload))
+        .and(warp::delete())
+        .and(require_api_key(config.clone()))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_delete);
+
+    let upload_status = warp::path!("upload" / String / "status")
+        .and(route_enabled(config.enable_upload))
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and(processing_filter.clone())
+        .and_then(handle_upload_status);
+
+    let upload_range = warp::path!("download" / String)
+        .and(route_enabled(config.enable_upload))
+        .and(warp::put())
+        .and(require_api_key(config.clone()))
+        .and(range_upload_headers(config.clone()))
+        .and(warp::body::content_length_limit(MAX_CHUNK_BYTES))
+        .and(warp::body::bytes())
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_upload_range);
+
+    let download = warp::path!("download" / String)
+        .and(route_enabled(config.enable_download))
+        .and(warp::get())
+        .and(warp::query::<DownloadQuery>())
+        .and(download_headers())
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(handle_download);
+
+    // `/download/{id}/{version}` is `/download/{id}?version=` as a path, for
+    // clients and caches that ignore the query string.
+    let download_version = warp::path!("download" / String / String)
+        .and(route_enabled(config.enable_download))
+        .and(warp::get())
+        .and(warp::query::<DownloadQuery>())
+        .map(|id, version, query: DownloadQuery| {
+            (
+                id,
+                DownloadQuery {
+                    version: Some(version),
+                    ..query
+                },
+            )
+        })
+        .untuple_one()
+        .and(download_headers())
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and_then(handle_download);
+
+    let thumbnail = warp::path!("thumbnail" / String)
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(handle_thumbnail);
+
+    let readme = warp::path!("readme" / String)
+        .and(route_enabled(config.enable_readme))
+        .and(warp::get())
+        .and(warp::query::<ReadmeQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_readme);
+
+    let health_check = warp::path("health")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(handle_health);
+
+    let capabilities = warp::path("capabilities")
+        .and(warp::get())
+        .and(config_filter.clone())
+        .and_then(handle_capabilities);
+
+    let export_metadata = warp::path!("admin" / "export-metadata")
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_export_metadata);
+
+    let missing_thumbnails = warp::path!("admin" / "missing-thumbnails")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_missing_thumbnails);
+
+    let recompute_sizes = warp::path!("admin" / "recompute-sizes")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_recompute_sizes);
+
+    let export_catalog = warp::path!("admin" / "export")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_export_catalog);
+
+    let import_catalog = warp::path!("admin" / "import")
+        .and(warp::post())
+        .and(require_admin_key(config.clone()))
+        .and(warp::query::<ImportQuery>())
+        .and(identity_filter(config.clone()))
+        .and(warp::body::stream())
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_import_catalog);
+
+    let recompress = warp::path!("admin" / "recompress")
+        .and(warp::post())
+        .and(warp::query::<RecompressQuery>())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_recompress);
+
+    let pending = warp::path!("admin" / "pending")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(processing_filter.clone())
+        .and_then(handle_pending);
+
+    let approve = warp::path!("admin" / "approve" / String)
+        .and(warp::post())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_approve);
+
+    let reject = warp::path!("admin" / "reject" / String)
+        .and(warp::post())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_reject);
+
+    let set_protected = warp::path!("admin" / "mods" / String / "protected")
+        .and(warp::put())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::json::<ProtectedRequest>())
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_set_protected);
+
+    let set_maintenance = warp::path!("admin" / "maintenance")
+        .and(warp::put())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(warp::body::content_length_limit(MAX_PATCH_BYTES))
+        .and(warp::body::json::<MaintenanceRequest>())
+        .and(config_filter.clone())
+        .and(maintenance_filter)
+        .and_then(handle_set_maintenance);
+
+    let catalog_stream = warp::path("events")
+        .and(warp::path::end())
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(events_filter.clone())
+        .and_then(handle_catalog_events);
+
+    let log_stream = warp::path!("admin" / "logs" / "stream")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(config_filter.clone())
+        .and_then(handle_log_stream);
+
+    let audit = warp::path!("admin" / "audit")
+        .and(warp::get())
+        .and(warp::query::<AuditQuery>())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_audit);
+
+    let latest = warp::path!("mods" / "latest")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<LatestQuery>())
+        .and(warp::header::optional::<String>("accept"))
+        .and(db_filter.clone())
+        .and_then(handle_latest);
+
+    let sources = warp::path!("mods" / String / "sources")
+        .and(route_enabled(config.enable_download))
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and_then(handle_sources);
+
+    let versions = warp::path!("mods" / String / "versions")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(handle_versions);
+
+    let related = warp::path!("mods" / String / "related")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<RelatedQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_related);
+
+    let author_stats = warp::path!("authors" / String / "stats")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(handle_author_stats);
+
+    let analytics = warp::path!("mods" / String / "analytics.csv")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<AnalyticsQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_analytics_csv);
+
+    let popular = warp::path!("stats" / "popular")
+        .and(route_enabled(config.enable_metadata))
+        .and(warp::get())
+        .and(warp::query::<PopularQuery>())
+        .and(db_filter.clone())
+        .and_then(handle_popular);
+
+    let setup = warp::path("setup")
+        .and(route_enabled(config.enable_setup))
+        .and(warp::get())
+        .and(warp::query::<SetupQuery>())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(identity_filter(config.clone()))
+        .and(db_filter.clone())
+        .and(config_filter.clone())
+        .and(cache_filter.clone())
+        .and(events_filter.clone())
+        .and_then(handle_setup);
+
+    let read_cors = cors_policy(&config.cors_read_origins)
+        .allow_headers(vec![
+            "Content-Type",
+            "If-None-Match",
+            "If-Range",
+            "Range",
+            "X-Admin-Key",
+        ])
+        .allow_methods(vec!["GET", "HEAD"]);
+    let write_cors = cors_policy(&config.cors_write_origins)
+        .allow_headers(vec![
+            "Content-Type",
+            "Content-Range",
+            "Idempotency-Key",
+            "If-Match",
+            "X-Reservation-Token",
+            "X-Admin-Key",
+            "X-API-Key",
+        ])
+        .allow_methods(vec!["POST", "PUT", "PATCH", "DELETE"]);
+
+    // Grouped and boxed so the combined filter's types stay shallow enough
+    // for the compiler.
+    let catalog_routes = full_search
+        .or(metadata_batch)
+        .or(list_mods)
+        .or(changes)
+        .or(modpack_check)
+        .or(verify_files)
+        .or(update_size)
+        .or(popular)
+        .or(author_stats)
+        .or(analytics)
+        .or(related)
+        .or(latest)
+        .or(versions)
+        .or(sources)
+        .or(download)
+        .or(download_version)
+        .or(readme)
+        .or(thumbnail)
+        .boxed();
+    let publish_routes = patch_metadata
+        .or(upload)
+        .or(reserve)
+        .or(refetch)
+        .or(delete)
+        .or(upload_status)
+        .or(upload_range)
+        .boxed();
+    let admin_routes = export_metadata
+        .or(export_catalog)
+        .or(import_catalog)
+        .or(missing_thumbnails)
+        .or(recompute_sizes)
+        .or(recompress)
+        .or(pending)
+        .or(approve)
+        .or(reject)
+        .or(set_protected)
+        .or(audit)
+        .or(setup)
+        .boxed();
+    let db_routes = catalog_routes.or(publish_routes).or(admin_routes);
+
+    // Health checks come before the maintenance gate and the circuit breaker
+    // so orchestrators see the database itself rather than a 503.
+    let app = health_check
+        .or(maintenance_gate(maintenance, config.clone()).and(
+            get_metadata
+                .or(capabilities)
+                .or(set_maintenance)
+                .or(log_stream)
+                .or(catalog_stream)
+                .or(db_available(health.clone()).and(db_routes)),
+        ))
+        .recover({
+            let config = config.clone();
+            move |err| handle_rejection(err, config.clone(), health.clone())
+        })
+        .map(Reply::into_response)
+        .then({
+            let json_case = config.json_case;
+            move |response| apply_json_case(response, json_case)
+        });
+
+    cors_class(false)
+        .and(app.clone())
+        .with(read_cors)
+        .or(cors_class(true).and(app).with(write_cors))
+        .with(warp::log::custom(|info| {
+            tracing::info!(
+                target: "request",
+                method = %info.method(),
+                path = info.path(),
+                status = info.status().as_u16(),
+                elapsed = ?info.elapsed(),
+            );
+        }))
+}
+
+/// Wraps `build_routes` in the service `run` serves: error messages follow
+/// the request's `Accept-Language`, and `MOD_DB_REQUEST_TIMEOUT_SECS` applies.
+pub fn app_service(
+    db: DbConnection,
+    config: Arc<Config>,
+) -> impl Service<
+    hyper::Request<Body>,
+    Response = Response,
+    Error = std::convert::Infallible,
+    Future = impl Future<Output = Result<Response, std::convert::Infallible>> + Send,
+> + Clone
++ Send {
+    let request_timeout = config.request_timeout;
+    let service = warp::service(build_routes(db, config));
+    hyper::service::service_fn(move |request: hyper::Request<Body>| {
+        let locale = Locale::negotiate(
+            request
+                .headers()
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok()),
+        );
+        let response = service.clone().call(request);
+        LOCALE.scope(locale, async move {
+            match request_timeout {
+                Some(limit) => match tokio::time::timeout(limit, response).await {
+                    Ok(response) => response,
+                    Err(_) => Ok(request_timeout_response()),
+                },
+                None => response.await,
+            }
+        })
+    })
+}
+
+/// Sent when reading a request and producing its response head takes longer
+/// than `MOD_DB_REQUEST_TIMEOUT_SECS`, e.g. an upload body that stalls.
+fn request_timeout_response() -> Response {
+    let body = json!({
+        "code": StatusCode::REQUEST_TIMEOUT.as_u16(),
+        "message": localize("Request Timeout")
+    });
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = StatusCode::REQUEST_TIMEOUT;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+        .headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    response
+}
+
+async fn run_backup_schedule(
+    db: DbConnection,
+    interval: Duration,
+    backup_dir: PathBuf,
+    retain: usize,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately; skip it so the first backup happens
+    // one interval after startup.
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let db = db.clone();
+        let backup_dir = backup_dir.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let conn = db.get()?;
+            backup_database(&conn, &backup_dir, retain)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(path)) => log_line!("Backed up database to {}", path.display()),
+            Ok(Err(e)) => log_line!("Database backup failed: {}", e),
+            Err(e) => log_line!("Database backup task failed: {}", e),
+        }
+    }
+}
+
+/// Writes an online backup of `conn` to a timestamped file in `backup_dir` and
+/// removes the oldest backups beyond `retain`. This is one cycle of
+/// `MOD_DB_BACKUP_INTERVAL_SECS`.
+pub fn backup_database(
+    conn: &Connection,
+    backup_dir: &Path,
+    retain: usize,
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = backup_dir.join(format!("mods-{}.db", timestamp));
+    conn.backup(DatabaseName::Main, &path, None)?;
+
+    let mut backups = fs::read_dir(backup_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("mods-") && name.ends_with(".db"))
+        })
+        .collect::<Vec<_>>();
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(retain);
+    for old in &backups[..excess] {
+        if let Err(e) = fs::remove_file(old) {
+            log_line!("Failed to remove old backup {}: {}", old.display(), e);
+        }
+    }
+    Ok(path)
+}
+
+fn route_enabled(enabled: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+fn cors_policy(origins: &CorsOrigins) -> cors::Builder {
+    match origins {
+        CorsOrigins::Any => cors().allow_any_origin(),
+        CorsOrigins::Only(origins) => cors().allow_origins(origins.iter().map(String::as_str)),
+    }
+}
+
+/// Passes requests that fall under the write CORS policy when `writes` is set,
+/// and under the read policy otherwise. GET and HEAD are reads, every other
+/// method is a write, and a preflight is judged by the method it asks about.
+fn cors_class(writes: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<Method>(
+            "access-control-request-method",
+        ))
+        .and_then(
+            move |method: Method, requested: Option<Method>| async move {
+                let method = match (method, requested) {
+                    (Method::OPTIONS, Some(requested)) => requested,
+                    (method, _) => method,
+                };
+                let is_write = !matches!(method, Method::GET | Method::HEAD | Method::OPTIONS);
+                if is_write == writes {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// Requires the `X-API-Key` header to match `MOD_DB_API_KEY` on routes that
+/// change mods. Without a configured key the routes stay open.
+fn require_api_key(config: Arc<Config>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and_then(move |provided: Option<String>| {
+            let authorized = match (config.api_key.as_deref(), provided.as_deref()) {
+                (None, _) => Ok(()),
+                (Some(expected), Some(provided)) if expected == provided => Ok(()),
+                _ => Err(warp::reject::custom(AuthError {
+                    details: "missing or invalid X-API-Key".to_string(),
+                })),
+            };
+            async move { authorized }
+        })
+        .untuple_one()
+}
+
+/// Requires `X-Admin-Key` as a filter, for handlers that already take too
+/// many arguments to check it themselves.
+fn require_admin_key(config: Arc<Config>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-admin-key")
+        .and_then(move |provided: Option<String>| {
+            let authorized = authorize_admin(&config, provided.as_deref());
+            async move { authorized }
+        })
+        .untuple_one()
+}
+
+/// Takes a token from the client's upload bucket, rejecting with `429` and the
+/// time until the next refill when it is empty. Clients are told apart by the
+/// same identity the audit log records.
+fn upload_rate_limit(
+    limiter: Option<RateLimiter>,
+    config: Arc<Config>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    identity_filter(config)
+        .and_then(move |identity: String| {
+            let acquired = limiter
+                .as_ref()
+                .map_or(Ok(()), |limiter| limiter.try_acquire(&identity));
+            async move {
+                acquired.map_err(|retry_after| warp::reject::custom(RateLimitError { retry_after }))
+            }
+        })
+        .untuple_one()
+}
+
+/// Turns requests away while maintenance is on, unless they carry a valid
+/// admin key so operators can keep working.
+fn maintenance_gate(
+    maintenance: Maintenance,
+    config: Arc<Config>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-admin-key")
+        .and_then(move |admin_key: Option<String>| {
+            let notice = maintenance.notice();
+            let admin = authorize_admin(&config, admin_key.as_deref()).is_ok();
+            async move {
+                match notice {
+                    Some(notice) if !admin => Err(warp::reject::custom(MaintenanceError {
+                        message: notice.message,
+                        retry_after_secs: notice.retry_after_secs,
+                    })),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Rejects with `503 Service Unavailable` while the database circuit is open.
+fn db_available(health: DbHealth) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let degraded = health.is_degraded();
+            async move {
+                if degraded {
+                    Err(warp::reject::custom(UnavailableError {
+                        details: "the database is unavailable".to_string(),
+                    }))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Periodically reads the full metadata listing. A success closes the circuit
+/// and refreshes the snapshot served while degraded; a failure counts towards
+/// opening it. The read runs on the blocking pool, so waiting for a busy
+/// connection never stalls the runtime.
+async fn run_db_probe(db: DbConnection, health: DbHealth, interval: Duration, threshold: u32) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let db = db.clone();
+        let probed = tokio::task::spawn_blocking(move || {
+            let conn = db.get().map_err(|e| e.to_string())?;
+            load_all_metadata(&conn, METADATA_COLUMNS).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|probed| probed);
+        match probed {
+            Ok(mods) => {
+                health.set_snapshot(mods);
+                health.record_success();
+            }
+            Err(e) => {
+                log_line!("Database probe failed: {}", e);
+                health.record_failure(threshold);
+            }
+        }
+    }
+}
+
+/// Opens a pool of connections to the database. WAL mode lets readers run
+/// alongside a writer, and the busy timeout makes writers wait for each other
+/// instead of failing.
+pub fn open_database(path: &Path, size: u32) -> Result<DbConnection, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000;")?;
+        register_functions(conn)
+    });
+    r2d2::Pool::builder().max_size(size).build(manager)
+}
+
+/// Opens a database that lives only in memory, for tests. The pool holds a
+/// single connection that is never recycled, since each in-memory connection
+/// is a database of its own.
+pub fn open_memory_database() -> Result<DbConnection, r2d2::Error> {
+    let manager = SqliteConnectionManager::memory().with_init(|conn| register_functions(conn));
+    r2d2::Pool::builder()
+        .max_size(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .build(manager)
+}
+
+/// Checks out a pooled connection, waiting for one to be returned if all are
+/// in use.
+fn checkout(db: &DbConnection) -> Result<PooledConnection, Rejection> {
+    db.get().map_err(|e| {
+        warp::reject::custom(DbError {
+            details: e.to_string(),
+        })
+    })
+}
+
+/// Runs `query` on a checked-out connection on the blocking thread pool, so
+/// waiting for a connection or for SQLite never stalls the async runtime.
+/// Handlers do their database work through this.
+async fn with_conn<T, F>(db: &DbConnection, query: F) -> Result<T, Rejection>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut PooledConnection) -> Result<T, Rejection> + Send + 'static,
+{
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || query(&mut checkout(&db)?))
+        .await
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })?
+}
+
+/// Registers the SQL functions the queries rely on, so search can fold every
+/// column the same way `fold_for_search` does in Rust.
+fn register_functions(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.create_scalar_function(
+        "fold_for_search",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            Ok(ctx
+                .get::<Option<String>>(0)?
+                .map(|text| fold_for_search(&text)))
+        },
+    )?;
+    conn.create_scalar_function(
+        "supports_game_version",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let min = ctx.get::<Option<String>>(0)?;
+            let game_version = parse_game_version(&ctx.get::<String>(1)?)
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            Ok(min_game_version_allows(min.as_deref(), &game_version))
+        },
+    )?;
+    conn.create_collation("semver", compare_versions)
+}
+
+type Migration = fn(&Connection) -> Result<(), rusqlite::Error>;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how
+/// many a database has had, so each runs once. Only ever append to this list.
+/// Databases from before versioning start at 0 with any subset of these
+/// changes, so each one only creates what is missing.
+const MIGRATIONS: &[Migration] = &[
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mods (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                version TEXT NOT NULL,
+                thumbnail TEXT NOT NULL,
+                file_path TEXT NOT NULL
+            )",
+        )
+    },
+    |conn| add_column(conn, "mods", "readme", "TEXT NOT NULL DEFAULT ''"),
+    |conn| {
+        add_column(conn, "mods", "title_normalized", "TEXT NOT NULL DEFAULT ''")?;
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_mods_title_normalized ON mods (title_normalized)",
+        )?;
+        backfill_normalized_titles(conn)
+    },
+    |conn| {
+        add_column(conn, "mods", "description", "TEXT NOT NULL DEFAULT ''")?;
+        add_column(conn, "mods", "author", "TEXT NOT NULL DEFAULT ''")
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS deleted_mods (
+                id TEXT PRIMARY KEY,
+                deleted_at INTEGER NOT NULL
+            )",
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                created_at INTEGER NOT NULL,
+                identity TEXT NOT NULL,
+                action TEXT NOT NULL,
+                target_id TEXT NOT NULL,
+                details TEXT
+            )",
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS download_log (
+                mod_id TEXT NOT NULL,
+                downloaded_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_download_log_time
+                ON download_log (downloaded_at, mod_id)",
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS partial_uploads (
+                mod_id TEXT PRIMARY KEY,
+                total INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS partial_upload_ranges (
+                mod_id TEXT NOT NULL,
+                start INTEGER NOT NULL,
+                end INTEGER NOT NULL
+            )",
+        )
+    },
+    |conn| add_column(conn, "mods", "min_game_version", "TEXT"),
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS idempotency_keys (
+                key TEXT PRIMARY KEY,
+                status INTEGER NOT NULL,
+                location TEXT,
+                content_type TEXT,
+                body BLOB NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mod_versions (
+                id TEXT NOT NULL,
+                version TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                uploaded_at INTEGER NOT NULL,
+                PRIMARY KEY (id, version)
+            )",
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS reservations (
+                id TEXT PRIMARY KEY,
+                token TEXT NOT NULL,
+                identity TEXT NOT NULL,
+                expires_at INTEGER NOT NULL
+            )",
+        )
+    },
+    |conn| add_column(conn, "mods", "protected", "INTEGER NOT NULL DEFAULT 0"),
+    |conn| add_column(conn, "mods", "thumbnail_mime", "TEXT"),
+    |conn| add_column(conn, "mods", "server_gzipped", "INTEGER NOT NULL DEFAULT 0"),
+    |conn| add_column(conn, "mods", "source_url", "TEXT"),
+    |conn| {
+        add_column(conn, "mods", "size_bytes", "INTEGER")?;
+        add_column(conn, "mods", "created_at", "INTEGER")?;
+        add_column(conn, "mods", "updated_at", "INTEGER")
+    },
+    |conn| {
+        add_column(conn, "mods", "sha256", "TEXT")?;
+        add_column(conn, "mod_versions", "sha256", "TEXT")
+    },
+    // Unlike the audit log this is always written: mirrors page through it
+    // by `seq`, which AUTOINCREMENT never reuses.
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mod_changes (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                mod_id TEXT NOT NULL,
+                action TEXT NOT NULL,
+                changed_at INTEGER NOT NULL
+            )",
+        )
+    },
+    |conn| {
+        add_column(conn, "mods", "thumbnail_original_bytes", "INTEGER")?;
+        add_column(conn, "mods", "thumbnail_bytes", "INTEGER")
+    },
+    |conn| add_column(conn, "mods", "compression", "TEXT NOT NULL DEFAULT 'gzip'"),
+    // Uploads held for review stay `pending` until an admin approves them.
+    |conn| {
+        add_column(
+            conn,
+            "mods",
+            "moderation",
+            "TEXT NOT NULL DEFAULT 'approved'",
+        )
+    },
+    |conn| {
+        if add_column_if_missing(conn, "mods", "downloads", "INTEGER NOT NULL DEFAULT 0")? {
+            conn.execute(
+                "UPDATE mods SET downloads =
+                    (SELECT COUNT(*) FROM download_log WHERE download_log.mod_id = mods.id)",
+                [],
+            )?;
+        }
+        Ok(())
+    },
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mod_tags (
+                mod_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (mod_id, tag)
+            );
+            CREATE INDEX IF NOT EXISTS idx_mod_tags_tag ON mod_tags (tag)",
+        )
+    },
+    // The search index covers tags, so it is built once they exist.
+    |conn| {
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS mods_fts USING fts5(
+                mod_id UNINDEXED,
+                title,
+                author,
+                description,
+                tags,
+                tokenize = 'unicode61 remove_diacritics 2'
+            )",
+        )?;
+        rebuild_search_index(conn)
+    },
+    // Held uploads wait in a queue of their own, so a listed mod keeps its
+    // approved revision until an update to it is approved. Uploads that were
+    // held in `mods` itself move there.
+    |conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pending_uploads (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                version TEXT NOT NULL,
+                thumbnail TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                readme TEXT NOT NULL,
+                description TEXT NOT NULL,
+                author TEXT NOT NULL,
+                tags TEXT,
+                min_game_version TEXT,
+                source_url TEXT,
+                sha256 TEXT,
+                size_bytes INTEGER,
+                server_gzipped INTEGER NOT NULL,
+                compression TEXT NOT NULL,
+                thumbnail_original_bytes INTEGER,
+                thumbnail_bytes INTEGER,
+                identity TEXT NOT NULL,
+                uploaded_at INTEGER NOT NULL
+            )",
+        )?;
+        if !has_column(conn, "mods", "moderation")? {
+            return Ok(());
+        }
+        conn.execute_batch(
+            "INSERT OR REPLACE INTO pending_uploads
+            SELECT id, title, version, thumbnail, file_path, readme, description, author,
+                (SELECT group_concat(tag, ',' ORDER BY tag) FROM mod_tags WHERE mod_tags.mod_id = mods.id),
+                min_game_version, source_url, sha256, size_bytes, server_gzipped, compression,
+                thumbnail_original_bytes, thumbnail_bytes, 'unknown', COALESCE(updated_at, 0)
+            FROM mods WHERE moderation = 'pending';
+            DELETE FROM mod_versions WHERE EXISTS (
+                SELECT 1 FROM pending_uploads
+                WHERE pending_uploads.id = mod_versions.id AND pending_uploads.version = mod_versions.version
+            );
+            DELETE FROM mod_tags WHERE mod_id IN (SELECT id FROM mods WHERE moderation = 'pending');
+            DELETE FROM mods WHERE moderation = 'pending';
+            ALTER TABLE mods DROP COLUMN moderation;",
+        )?;
+        rebuild_search_index(conn)
+    },
+    // Each kept version describes itself. Versions kept earlier only learn
+    // their metadata if they are the latest one.
+    |conn| {
+        for (column, definition) in [
+            ("title", "TEXT"),
+            ("description", "TEXT"),
+            ("readme", "TEXT"),
+            ("author", "TEXT"),
+            ("tags", "TEXT"),
+            ("min_game_version", "TEXT"),
+            ("source_url", "TEXT"),
+            ("thumbnail", "TEXT"),
+            ("size_bytes", "INTEGER"),
+            ("compression", "TEXT"),
+        ] {
+            add_column(conn, "mod_versions", column, definition)?;
+        }
+        conn.execute_batch(
+            "UPDATE mod_versions SET title = mods.title, description = mods.description,
+                readme = mods.readme, author = mods.author,
+                tags = (SELECT group_concat(tag, ',' ORDER BY tag) FROM mod_tags WHERE mod_tags.mod_id = mods.id),
+                min_game_version = mods.min_game_version, source_url = mods.source_url,
+                thumbnail = mods.thumbnail, size_bytes = mods.size_bytes, compression = mods.compression
+            FROM mods WHERE mods.id = mod_versions.id AND mods.version = mod_versions.version",
+        )
+    },
+];
+
+/// Brings the schema up to date, running each pending migration in its own
+/// transaction together with the version bump.
+pub fn setup_db(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let applied: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if applied > MIGRATIONS.len() as i64 {
+        log_line!(
+            "Database schema version {} is newer than this server's {}",
+            applied,
+            MIGRATIONS.len()
+        );
+        return Ok(());
+    }
+
+    for (version, migrate) in (1..).zip(MIGRATIONS).skip(applied as usize) {
+        let tx = conn.unchecked_transaction()?;
+        migrate(&tx)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+const SEARCH_INDEX_SELECT: &str = "SELECT id, title, author, description,
+        (SELECT group_concat(tag, ' ') FROM mod_tags WHERE mod_tags.mod_id = mods.id)
+    FROM mods";
+
+fn rebuild_search_index(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM mods_fts", [])?;
+    conn.execute(
+        &format!(
+            "INSERT INTO mods_fts (mod_id, title, author, description, tags) {}",
+            SEARCH_INDEX_SELECT
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Refreshes the full-text index entry for one mod. Call after any change to
+/// its title, author, description, or tags; a missing row just drops the entry.
+fn sync_search_index(conn: &Connection, id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM mods_fts WHERE mod_id = ?1", params![id])?;
+    conn.execute(
+        &format!(
+            "INSERT INTO mods_fts (mod_id, title, author, description, tags) {} WHERE id = ?1",
+            SEARCH_INDEX_SELECT
+        ),
+        params![id],
+    )?;
+    Ok(())
+}
+
+fn backfill_normalized_titles(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let rows = conn
+        .prepare("SELECT id, title FROM mods WHERE title_normalized = '' AND title <> ''")?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (id, title) in rows {
+        conn.execute(
+            "UPDATE mods SET title_normalized = ?1 WHERE id = ?2",
+            params![fold_for_search(&title), id],
+        )?;
+    }
+    Ok(())
+}
+
+fn reset_db(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let file_paths = conn
+        .prepare(
+            "SELECT file_path FROM mods UNION SELECT file_path FROM mod_versions
+            UNION SELECT file_path FROM pending_uploads",
+        )?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    // The change feed survives a reset so mirrors see every mod go away.
+    conn.execute(
+        "INSERT INTO mod_changes (mod_id, action, changed_at)
+        SELECT id, 'delete', ?1 FROM mods",
+        params![unix_now()],
+    )?;
+    conn.execute("DROP TABLE IF EXISTS reservations", [])?;
+    conn.execute("DROP TABLE IF EXISTS pending_uploads", [])?;
+    conn.execute("DROP TABLE IF EXISTS mod_versions", [])?;
+    conn.execute("DROP TABLE IF EXISTS idempotency_keys", [])?;
+    conn.execute("DROP TABLE IF EXISTS partial_upload_ranges", [])?;
+    conn.execute("DROP TABLE IF EXISTS partial_uploads", [])?;
+    conn.execute("DROP TABLE IF EXISTS download_log", [])?;
+    conn.execute("DROP TABLE IF EXISTS deleted_mods", [])?;
+    conn.execute("DROP TABLE IF EXISTS mods_fts", [])?;
+    conn.execute("DROP TABLE IF EXISTS mod_tags", [])?;
+    conn.execute("DROP TABLE IF EXISTS mods", [])?;
+    conn.pragma_update(None, "user_version", 0)?;
+
+    for file_path in file_paths {
+        if let Err(e) = fs::remove_file(&file_path) {
+            log_line!("Failed to remove {} during reset: {}", file_path, e);
+        }
+    }
+    Ok(())
+}
+
+/// The conditional and range request headers a download acts on.
+struct DownloadHeaders {
+    if_none_match: Option<String>,
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+fn download_headers() -> impl Filter<Extract = (DownloadHeaders,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .map(|if_none_match, range, if_range| DownloadHeaders {
+            if_none_match,
+            range,
+            if_range,
+        })
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    if_not_exists: Option<bool>,
+}
+
+/// Who is uploading, and the optional request headers and query flags the
+/// upload acts on.
+struct UploadOptions {
+    identity: String,
+    idempotency_key: Option<String>,
+    reservation_token: Option<String>,
+    if_match: Option<String>,
+    if_not_exists: bool,
+}
+
+fn upload_options(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (UploadOptions,), Error = Rejection> + Clone {
+    identity_filter(config)
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(warp::header::optional::<String>("x-reservation-token"))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::query::<UploadQuery>())
+        .map(
+            |identity, idempotency_key, reservation_token, if_match, query: UploadQuery| {
+                UploadOptions {
+                    identity,
+                    idempotency_key,
+                    reservation_token,
+                    if_match,
+                    if_not_exists: query.if_not_exists.unwrap_or(false),
+                }
+            },
+        )
+}
+
+/// The headers a chunk of a resumable upload carries, and who sent it.
+struct RangeUploadHeaders {
+    content_range: String,
+    reservation_token: Option<String>,
+    identity: String,
+}
+
+fn range_upload_headers(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (RangeUploadHeaders,), Error = Rejection> + Clone {
+    warp::header::<String>("content-range")
+        .and(warp::header::optional::<String>("x-reservation-token"))
+        .and(identity_filter(config))
+        .map(
+            |content_range, reservation_token, identity| RangeUploadHeaders {
+                content_range,
+                reservation_token,
+                identity,
+            },
+        )
+}
+
+/// Resolves who is making a request for auditing: `admin` when a valid admin
+/// key is presented, otherwise the anonymous remote address.
+fn identity_filter(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-admin-key")
+        .and(warp::addr::remote())
+        .map(move |admin_key: Option<String>, addr: Option<SocketAddr>| {
+            if authorize_admin(&config, admin_key.as_deref()).is_ok() {
+                "admin".to_string()
+            } else {
+                match addr {
+                    Some(addr) => format!("anonymous@{}", addr.ip()),
+                    None => "anonymous".to_string(),
+                }
+            }
+        })
+}
+
+/// Appends an entry to the audit log when it is enabled. Callers run this on
+/// the same connection as the mutation it describes, right after it succeeds.
+fn record_audit(
+    conn: &Connection,
+    config: &Config,
+    identity: &str,
+    action: &str,
+    target_id: &str,
+    details: Option<&str>,
+) -> Result<(), rusqlite::Error> {
+    if !config.audit_log {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO audit_log (created_at, identity, action, target_id, details)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![unix_now(), identity, action, target_id, details],
+    )?;
+    Ok(())
+}
+
+/// Appends a mutation to the `/changes` feed. `action` is one of `create`,
+/// `update` or `delete`.
+fn record_change(conn: &Connection, id: &str, action: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO mod_changes (mod_id, action, changed_at) VALUES (?1, ?2, ?3)",
+        params![id, action, unix_now()],
+    )?;
+    Ok(())
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn authorize_admin(config: &Config, provided: Option<&str>) -> Result<(), Rejection> {
+    match (config.admin_key.as_deref(), provided) {
+        (Some(expected), Some(provided)) if expected == provided => Ok(()),
+        (None, _) => Err(warp::reject::custom(AuthError {
+            details: "admin operations are disabled; set MOD_DB_ADMIN_KEY".to_string(),
+        })),
+        _ => Err(warp::reject::custom(AuthError {
+            details: "missing or invalid X-Admin-Key".to_string(),
+        })),
+    }
+}
+
+/// Picks the rejection for an id with no `mods` row: `410 Gone` if it was
+/// deleted (and has a tombstone), plain `404 Not Found` if it never existed.
+fn mod_not_found(conn: &Connection, id: &str) -> Rejection {
+    let deleted = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM deleted_mods WHERE id = ?1)",
+            params![id],
+            |row| row.get(0),
+        )
+        .unwrap_or(false);
+
+    if deleted {
+        warp::reject::custom(GoneError {
+            details: format!("mod `{}` has been deleted", id),
+        })
+    } else {
+        warp::reject::custom(NotFoundError {
+            details: format!("mod `{}` does not exist", id),
+        })
+    }
+}
+
+/// `add_column_if_missing` for migrations that don't care whether it ran.
+fn add_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), rusqlite::Error> {
+    add_column_if_missing(conn, table, column, definition).map(|_| ())
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<bool, rusqlite::Error> {
+    let exists = has_column(conn, table, column)?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(!exists)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(columns.iter().any(|name| name == column))
+}
+
+async fn handle_get_metadata(
+    query: MetadataQuery,
+    accept: Option<String>,
+    accept_encoding: Option<String>,
+    db: DbConnection,
+    health: DbHealth,
+    cache: MetadataCache,
+) -> Result<impl Reply, Rejection> {
+    let filter = MetadataFilter::parse(&query)
+        .map_err(|details| warp::reject::custom(QueryError { details }))?;
+    if health.is_degraded() {
+        return degraded_metadata(&health, &filter, accept.as_deref());
+    }
+    if let Some(page) = filter.page {
+        let (total, items) = tokio::task::spawn_blocking(move || {
+            let conn = db.get().map_err(|e| e.to_string())?;
+            load_metadata_page(&conn, &filter, &page).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|loaded| loaded)
+        .map_err(|details| warp::reject::custom(DbError { details }))?;
+        return Ok(metadata_reply(&page.body(total, &items), accept.as_deref()));
+    }
+    if accept.as_deref().is_some_and(accepts_msgpack) {
+        let mods = tokio::task::spawn_blocking(move || {
+            let conn = db.get().map_err(|e| e.to_string())?;
+            load_filtered_metadata(&conn, &filter).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|loaded| loaded)
+        .map_err(|details| warp::reject::custom(DbError { details }))?;
+        return Ok(metadata_reply(&mods, accept.as_deref()));
+    }
+    if filter.is_empty() && accept_encoding.as_deref().is_some_and(accepts_gzip) {
+        return gzipped_metadata(db, cache).await;
+    }
+
+    let (tx, mut rx) = mpsc::channel::<Result<Bytes, io::Error>>(METADATA_STREAM_BUFFER);
+
+    tokio::task::spawn_blocking(move || {
+        let streamed = db
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|conn| stream_metadata_rows(&conn, &filter, &tx).map_err(|e| e.to_string()));
+        if let Err(e) = streamed {
+            let _ = tx.blocking_send(Err(io::Error::other(e)));
+        }
+    });
+
+    // The opening bracket is only sent once the query is running, so a failure
+    // to prepare it can still be reported as a regular error response.
+    let first = match rx.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e)) => {
+            return Err(warp::reject::custom(DbError {
+                details: e.to_string(),
+            }));
+        }
+        None => Bytes::from_static(b"[]"),
+    };
+
+    let rest = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    let stream = futures::stream::once(async move { Ok(first) }).chain(rest);
+
+    // A streamed body has no known length, so hyper frames it with
+    // `Transfer-Encoding: chunked` and writes each flushed batch as it arrives.
+    let mut response = Response::new(Body::wrap_stream(stream));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept, accept-encoding"));
+    Ok(response)
+}
+
+/// Whether an `Accept-Encoding` header allows gzip, i.e. lists it without `q=0`.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    header_lists(accept_encoding, |name| {
+        name.eq_ignore_ascii_case("gzip") || name == "*"
+    })
+}
+
+/// Whether an `Accept` header explicitly asks for MessagePack. Wildcards keep
+/// the JSON default.
+fn accepts_msgpack(accept: &str) -> bool {
+    header_lists(accept, |name| {
+        name.eq_ignore_ascii_case("application/msgpack")
+            || name.eq_ignore_ascii_case("application/x-msgpack")
+    })
+}
+
+/// Whether a comma-separated header lists a value matching `wanted` without
+/// refusing it through `q=0`.
+fn header_lists(header: &str, wanted: impl Fn(&str) -> bool) -> bool {
+    header.split(',').any(|item| {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let refused = params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                == Some(0.0)
+        });
+        wanted(name) && !refused
+    })
+}
+
+/// Serializes metadata as MessagePack when the `Accept` header asks for it,
+/// otherwise as JSON. MessagePack maps keep the JSON field names.
+fn metadata_reply<T: Serialize>(value: &T, accept: Option<&str>) -> Response {
+    if !accept.is_some_and(accepts_msgpack) {
+        let mut response = warp::reply::json(value).into_response();
+        response
+            .headers_mut()
+            .insert(VARY, HeaderValue::from_static("accept"));
+        return response;
+    }
+    match rmp_serde::to_vec_named(value) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            let headers = response.headers_mut();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/msgpack"),
+            );
+            headers.insert(VARY, HeaderValue::from_static("accept"));
+            response
+        }
+        Err(e) => {
+            log_line!("Failed to encode MessagePack: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Serves the full listing from the gzip cache, compressing it on a miss.
+async fn gzipped_metadata(db: DbConnection, cache: MetadataCache) -> Result<Response, Rejection> {
+    let (generation, cached) = cache.get();
+    let gzip = match cached {
+        Some(gzip) => gzip,
+        None => {
+            let gzip = tokio::task::spawn_blocking(move || {
+                let conn = db.get()?;
+                let mods = load_all_metadata(&conn, METADATA_COLUMNS)?;
+                gzip_json(&mods)
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|built| built.map_err(|e| e.to_string()))
+            .map_err(|details| warp::reject::custom(DbError { details }))?;
+            cache.store(generation, gzip.clone());
+            gzip
+        }
+    };
+
+    let mut response = Response::new(Body::from(gzip));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.insert(VARY, HeaderValue::from_static("accept, accept-encoding"));
+    Ok(response)
+}
+
+fn gzip_json<T: Serialize>(value: &T) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut encoder, value)?;
+    Ok(Bytes::from(encoder.finish()?))
+}
+
+/// Answers `/metadata` from the probe's last snapshot while the database is
+/// unavailable, applying the same filters as the live query.
+fn degraded_metadata(
+    health: &DbHealth,
+    filter: &MetadataFilter,
+    accept: Option<&str>,
+) -> Result<Response, Rejection> {
+    let snapshot = health.snapshot().ok_or_else(|| {
+        warp::reject::custom(UnavailableError {
+            details: "the database is unavailable and no cached metadata exists".to_string(),
+        })
+    })?;
+    let mods = snapshot
+        .iter()
+        .filter(|metadata| filter.matches(metadata))
+        .collect::<Vec<_>>();
+
+    let mut response = match filter.page {
+        Some(page) => {
+            let total = mods.len() as u64;
+            metadata_reply(&page.body(total, &page.apply(mods)), accept)
+        }
+        None => metadata_reply(&mods, accept),
+    };
+    response
+        .headers_mut()
+        .insert("x-degraded", HeaderValue::from_static("true"));
+    Ok(response)
+}
+
+/// Writes the metadata table to `tx` as a JSON array, flushing a chunk every
+/// `METADATA_FLUSH_BYTES` so the full listing never has to be held in memory
+/// and stream-parsing clients see rows while the query is still running.
+fn stream_metadata_rows(
+    conn: &Connection,
+    filter: &MetadataFilter,
+    tx: &mpsc::Sender<Result<Bytes, io::Error>>,
+) -> Result<(), rusqlite::Error> {
+    let (sql, values) = filter.sql();
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(values))?;
+
+    if tx.blocking_send(Ok(Bytes::from_static(b"["))).is_err() {
+        return Ok(());
+    }
+
+    let mut first = true;
+    let mut chunk = Vec::with_capacity(METADATA_FLUSH_BYTES);
+    while let Some(row) = rows.next()? {
+        let metadata = row_to_metadata(row)?;
+        if !first {
+            chunk.push(b',');
+        }
+        first = false;
+        serde_json::to_writer(&mut chunk, &metadata)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+
+        if chunk.len() >= METADATA_FLUSH_BYTES {
+            let flushed = std::mem::replace(&mut chunk, Vec::with_capacity(METADATA_FLUSH_BYTES));
+            if tx.blocking_send(Ok(Bytes::from(flushed))).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    chunk.push(b']');
+    let _ = tx.blocking_send(Ok(Bytes::from(chunk)));
+    Ok(())
+}
+
+/// Loads the rows `stream_metadata_rows` would send, for encodings that can't
+/// be written incrementally.
+fn load_filtered_metadata(
+    conn: &Connection,
+    filter: &MetadataFilter,
+) -> Result<Vec<ModMetadata>, rusqlite::Error> {
+    let (sql, values) = filter.sql();
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_map(rusqlite::params_from_iter(values), row_to_metadata)?
+        .collect()
+}
+
+/// Loads one page of the filtered listing and the number of rows matching
+/// the filters across all pages.
+fn load_metadata_page(
+    conn: &Connection,
+    filter: &MetadataFilter,
+    page: &MetadataPage,
+) -> Result<(u64, Vec<ModMetadata>), rusqlite::Error> {
+    let (conditions, values) = filter.conditions();
+    let total = conn.query_row(
+        &format!("SELECT COUNT(*) FROM mods{}", conditions),
+        rusqlite::params_from_iter(values.iter()),
+        |row| row.get(0),
+    )?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM mods{} ORDER BY {} LIMIT {} OFFSET {}",
+        METADATA_COLUMNS,
+        conditions,
+        page.order_by(),
+        page.limit,
+        page.offset
+    ))?;
+    let items = stmt
+        .query_map(rusqlite::params_from_iter(values), row_to_metadata)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((total, items))
+}
+
+/// Folds text for search comparisons: compatibility-decomposes it, drops
+/// combining marks so "Pokémon" matches "pokemon", and lowercases the rest.
+fn fold_for_search(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn row_to_metadata(row: &rusqlite::Row) -> Result<ModMetadata, rusqlite::Error> {
+    Ok(ModMetadata {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        version: row.get(2)?,
+        thumbnail: row.get(3)?,
+        file_path: row.get(4)?,
+        description: row.get(5)?,
+        author: row.get(6)?,
+        tags: row
+            .get::<_, Option<String>>(7)?
+            .map(|tags| tags.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+        min_game_version: row.get(8)?,
+        source_url: row.get(9)?,
+        size_bytes: row.get(10)?,
+        created_at: row.get(11)?,
+        updated_at: row.get(12)?,
+        sha256: row.get(13)?,
+        compression: row.get(14)?,
+        downloads: row.get(15)?,
+    })
+}
+
+/// Checks that a source URL is an absolute `http` or `https` URL with a host.
+/// Blank input clears the URL.
+fn parse_source_url(value: &str) -> Result<Option<String>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if value.len() > MAX_SOURCE_URL_LENGTH {
+        return Err(format!(
+            "source_url exceeds {} bytes",
+            MAX_SOURCE_URL_LENGTH
+        ));
+    }
+    let uri = value
+        .parse::<Uri>()
+        .map_err(|e| format!("invalid source_url `{}`: {}", value, e))?;
+    let has_host = uri.host().is_some_and(|host| !host.is_empty());
+    if !matches!(uri.scheme_str(), Some("http" | "https")) || !has_host {
+        return Err(format!(
+            "invalid source_url `{}`: expected an absolute http or https URL",
+            value
+        ));
+    }
+    Ok(Some(value.to_string()))
+}
+
+/// Parses a game build as semver, accepting the short `1.20` form as `1.20.0`.
+fn parse_game_version(version: &str) -> Result<semver::Version, String> {
+    parse_version_lenient(version)
+        .map_err(|e| format!("invalid game version `{}`: {}", version.trim(), e))
+}
+
+/// Parses semver the way people write versions: an optional leading `v`, and
+/// missing minor or patch numbers taken as zero.
+fn parse_version_lenient(version: &str) -> Result<semver::Version, semver::Error> {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    let core_end = version.find(['-', '+']).unwrap_or(version.len());
+    let (core, suffix) = version.split_at(core_end);
+    let padding = match core.matches('.').count() {
+        0 => ".0.0",
+        1 => ".0",
+        _ => "",
+    };
+    semver::Version::parse(&format!("{}{}{}", core, padding, suffix))
+}
+
+fn supports_game_version(metadata: &ModMetadata, game_version: &semver::Version) -> bool {
+    min_game_version_allows(metadata.min_game_version.as_deref(), game_version)
+}
+
+/// A mod without a minimum game version runs on every build.
+fn min_game_version_allows(min: Option<&str>, game_version: &semver::Version) -> bool {
+    min.and_then(|min| parse_game_version(min).ok())
+        .is_none_or(|min| min <= *game_version)
+}
+
+/// Splits a comma-separated tag list into trimmed, lowercased, de-duplicated tags.
+fn parse_tags(raw: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for tag in raw.split(',') {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+fn replace_tags(conn: &Connection, id: &str, tags: &[String]) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM mod_tags WHERE mod_id = ?1", params![id])?;
+    for tag in tags {
+        conn.execute(
+            "INSERT OR IGNORE INTO mod_tags (mod_id, tag) VALUES (?1, ?2)",
+            params![id, tag],
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs an upload, or replays the stored outcome when the `Idempotency-Key` was
+/// already used within `MOD_DB_IDEMPOTENCY_TTL_SECS`. Only successful outcomes
+/// are stored, so a failed upload can be retried with the same key.
+async fn handle_upload(
+    headers: UploadOptions,
+    db: DbConnection,
+    config: Arc<Config>,
+    processing: ProcessingTracker,
+    cache: MetadataCache,
+    events: CatalogEvents,
+    form: FormData,
+) -> Result<Response, Rejection> {
+    let Some(key) = headers.idempotency_key.clone() else {
+        return process_upload(headers, db, config, processing, cache, events, form).await;
+    };
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(warp::reject::custom(UploadError {
+            details: format!(
+                "Idempotency-Key must be 1 to {} bytes",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            ),
+        }));
+    }
+
+    let expires_before = unix_now() - config.idempotency_ttl.as_secs() as i64;
+    let stored = with_conn(&db, {
+        let key = key.clone();
+        move |conn| {
+            conn.execute(
+                "DELETE FROM idempotency_keys WHERE created_at < ?1",
+                params![expires_before],
+            )
+            .and_then(|_| {
+                conn.query_row(
+                    "SELECT status, location, content_type, body FROM idempotency_keys WHERE key = ?1",
+                    params![key],
+                    |row| {
+                        Ok((
+                            row.get::<_, u16>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                            row.get::<_, Option<String>>(2)?,
+                            row.get::<_, Vec<u8>>(3)?,
+                        ))
+                    },
+                )
+                .optional()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+    if let Some((status, location, content_type, body)) = stored {
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+        let headers = response.headers_mut();
+        for (name, value) in [(LOCATION, location), (CONTENT_TYPE, content_type)] {
+            if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers.insert("idempotent-replay", HeaderValue::from_static("true"));
+        return Ok(response);
+    }
+
+    let response =
+        process_upload(headers, db.clone(), config, processing, cache, events, form).await?;
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.map_err(|e| {
+        warp::reject::custom(UploadError {
+            details: e.to_string(),
+        })
+    })?;
+    let header = |name| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let (location, content_type) = (header(LOCATION), header(CONTENT_TYPE));
+    let (status, stored_body) = (parts.status.as_u16(), body.to_vec());
+    let stored = with_conn(&db, {
+        let key = key.clone();
+        move |conn| {
+            Ok(conn.execute(
+                "INSERT OR REPLACE INTO idempotency_keys (key, status, location, content_type, body, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![key, status, location, content_type, stored_body, unix_now()],
+            ))
+        }
+    })
+    .await?;
+    if let Err(e) = stored {
+        log_line!("Failed to store idempotency key {}: {}", key, e);
+    }
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+async fn process_upload(
+    headers: UploadOptions,
+    db: DbConnection,
+    config: Arc<Config>,
+    processing: ProcessingTracker,
+    cache: MetadataCache,
+    events: CatalogEvents,
+    mut form: FormData,
+) -> Result<Response, Rejection> {
+    let mut mod_metadata = ModMetadata {
+        id: String::new(),
+        title: String::new(),
+        version: String::new(),
+        thumbnail: String::new(),
+        file_path: String::new(),
+        description: String::new(),
+        author: String::new(),
+        tags: Vec::new(),
+        min_game_version: None,
+        source_url: None,
+        size_bytes: None,
+        created_at: None,
+        updated_at: None,
+        sha256: None,
+        compression: String::new(),
+        downloads: 0,
+    };
+    let mut readme = String::new();
+    let mut manifest: Option<ModManifest> = None;
+    let mut file_data: Option<Bytes> = None;
+    let mut sha256: Option<String> = None;
+    let mut size_bytes: Option<i64> = None;
+    let mut server_gzipped = false;
+    let mut thumbnail_sizes: (Option<i64>, Option<i64>) = (None, None);
+    let mut parts = 0;
+
+    while let Ok(Some(part)) = form.try_next().await {
+        parts += 1;
+        if parts > config.max_multipart_parts {
+            return Err(warp::reject::custom(UploadError {
+                details: format!(
+                    "upload has more than {} form parts",
+                    config.max_multipart_parts
+                ),
+            }));
+        }
+        match part.name() {
+            "id" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.id = value;
+            }
+            "title" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.title = value;
+            }
+            "version" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.version = value;
+            }
+            "thumbnail" => {
+                let data = read_part_to_bytes(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                if data.len() as u64 > config.max_thumbnail_bytes {
+                    return Err(warp::reject::custom(PayloadTooLargeError {
+                        details: format!(
+                            "thumbnail is larger than {} bytes",
+                            config.max_thumbnail_bytes
+                        ),
+                    }));
+                }
+                let original_len = data.len() as i64;
+                let data = if config.optimize_thumbnails {
+                    tokio::task::spawn_blocking(move || optimize_thumbnail(data))
+                        .await
+                        .map_err(|e| {
+                            warp::reject::custom(UploadError {
+                                details: e.to_string(),
+                            })
+                        })?
+                } else {
+                    data
+                };
+                thumbnail_sizes = (Some(original_len), Some(data.len() as i64));
+                let base64_thumbnail = BASE64_STANDARD.encode(&data);
+                mod_metadata.thumbnail = base64_thumbnail;
+            }
+            "file" => {
+                let data = read_part_to_bytes(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                if data.len() as u64 > config.max_file_bytes {
+                    return Err(warp::reject::custom(PayloadTooLargeError {
+                        details: format!("file is larger than {} bytes", config.max_file_bytes),
+                    }));
+                }
+                let prepared = prepare_mod_file(data, &config)
+                    .await
+                    .map_err(|details| warp::reject::custom(UploadError { details }))?;
+                manifest = prepared.manifest;
+                server_gzipped = prepared.server_gzipped;
+                mod_metadata.compression = prepared.compression.to_string();
+                sha256 = Some(prepared.sha256);
+                size_bytes = Some(prepared.data.len() as i64);
+                // The path depends on the id and version, which may arrive in
+                // later parts or the manifest, so the file is stored once the
+                // whole form has been read.
+                file_data = Some(Bytes::from(prepared.data));
+            }
+            "description" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.description = value;
+            }
+            "author" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.author = value;
+            }
+            "tags" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                for tag in parse_tags(&value) {
+                    if !mod_metadata.tags.contains(&tag) {
+                        mod_metadata.tags.push(tag);
+                    }
+                }
+            }
+            "readme" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                if value.len() > MAX_README_BYTES {
+                    return Err(warp::reject::custom(UploadError {
+                        details: format!("readme exceeds {} bytes", MAX_README_BYTES),
+                    }));
+                }
+                readme = value;
+            }
+            "min_game_version" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.min_game_version = if value.trim().is_empty() {
+                    None
+                } else {
+                    Some(
+                        parse_game_version(&value)
+                            .map_err(|details| warp::reject::custom(UploadError { details }))?
+                            .to_string(),
+                    )
+                };
+            }
+            "source_url" => {
+                let value = read_part_to_string(part).await.map_err(|e| {
+                    warp::reject::custom(UploadError {
+                        details: e.to_string(),
+                    })
+                })?;
+                mod_metadata.source_url = parse_source_url(&value)
+                    .map_err(|details| warp::reject::custom(UploadError { details }))?;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(manifest) = manifest {
+        for (field, value) in [
+            (&mut mod_metadata.title, manifest.title),
+            (&mut mod_metadata.version, manifest.version),
+            (&mut mod_metadata.author, manifest.author),
+            (&mut mod_metadata.description, manifest.description),
+        ] {
+            if field.is_empty()
+                && let Some(value) = value
+            {
+                *field = value;
+            }
+        }
+    }
+
+    let missing = [
+        ("id", &mod_metadata.id),
+        ("title", &mod_metadata.title),
+        ("version", &mod_metadata.version),
+    ]
+    .into_iter()
+    .filter(|(_, value)| value.trim().is_empty())
+    .map(|(field, _)| field)
+    .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(warp::reject::custom(UploadError {
+            details: format!("missing required field(s): {}", missing.join(", ")),
+        }));
+    }
+    validate_mod_id(&mod_metadata.id).map_err(warp::reject::custom)?;
+    enforce_field_lengths(&config, &mut mod_metadata)
+        .map_err(|details| warp::reject::custom(UploadError { details }))?;
+    check_reservation(&db, &mod_metadata.id, headers.reservation_token.as_deref()).await?;
+    if headers.if_not_exists {
+        refuse_if_exists(&db, &mod_metadata.id).await?;
+    }
+    check_upload_precondition(&db, &config, &mod_metadata.id, headers.if_match.as_deref()).await?;
+
+    // The file is staged next to its final path and only moved into place once
+    // the metadata is committed, so a failed write neither orphans the new file
+    // nor replaces the previous good one.
+    let mut staged = None;
+    if let Some(data) = file_data {
+        let file_path = upload_file_path(&config, &mod_metadata)
+            .map_err(|details| warp::reject::custom(UploadError { details }))?;
+        // Identical bytes are stored once, with the row pointing at the file
+        // that already holds them. A held upload is deduplicated on approval.
+        let duplicate = match sha256.clone() {
+            Some(checksum) if !config.quarantine_uploads => {
+                with_conn(&db, move |conn| {
+                    find_stored_duplicate(conn, &checksum).map_err(|e| {
+                        warp::reject::custom(DbError {
+                            details: e.to_string(),
+                        })
+                    })
+                })
+                .await?
+            }
+            _ => None,
+        };
+        if let Some(existing) = duplicate {
+            log_line!(
+                "Mod {} version {} has the same file as {}",
+                mod_metadata.id,
+                mod_metadata.version,
+                existing
+            );
+            mod_metadata.file_path = existing;
+        } else {
+            // A held upload waits apart from the listed file until approved.
+            let file_path = if config.quarantine_uploads {
+                held_file_path(&config, &mod_metadata.id, &mod_metadata.compression)
+            } else {
+                let (id, version) = (mod_metadata.id.clone(), mod_metadata.version.clone());
+                let replaces_mod_row = !config.keep_versions;
+                with_conn(&db, move |conn| {
+                    unshared_file_path(conn, file_path, &id, &version, replaces_mod_row).map_err(
+                        |e| {
+                            warp::reject::custom(DbError {
+                                details: e.to_string(),
+                            })
+                        },
+                    )
+                })
+                .await?
+            };
+            let staged_path = format!("{}.upload", file_path);
+            let dir = Path::new(&file_path).parent().unwrap_or(&config.mods_dir);
+            let stored = match fs::create_dir_all(dir) {
+                Ok(()) => {
+                    config
+                        .storage_retry
+                        .run("Storing upload", || {
+                            tokio::fs::write(&staged_path, data.clone())
+                        })
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = stored {
+                discard_upload_file(&staged_path).await;
+                return Err(warp::reject::custom(UploadError {
+                    details: e.to_string(),
+                }));
+            }
+            staged = Some(staged_path);
+            mod_metadata.file_path = file_path;
+        }
+    }
+
+    let written = with_conn(&db, {
+        let (config, identity) = (config.clone(), headers.identity.clone());
+        let (mod_metadata, readme, sha256) = (mod_metadata.clone(), readme.clone(), sha256.clone());
+        move |conn| {
+            let columns = UploadColumns {
+                readme: &readme,
+                sha256: sha256.as_deref(),
+                size_bytes,
+                server_gzipped,
+                thumbnail_sizes,
+            };
+            if config.quarantine_uploads {
+                conn.transaction().and_then(|tx| {
+                    let (listed, replaced) = hold_upload(&tx, &identity, &mod_metadata, &columns)?;
+                    tx.commit()?;
+                    Ok((listed, false, replaced))
+                })
+            } else {
+                write_upload_metadata(conn, &config, &identity, &mod_metadata, &columns)
+                    .map(|(exists, is_latest)| (exists, is_latest, None))
+            }
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await;
+    let (exists, is_latest, replaced) = match written {
+        Ok(written) => written,
+        Err(rejection) => {
+            if let Some(staged_path) = &staged {
+                discard_upload_file(staged_path).await;
+            }
+            return Err(rejection);
+        }
+    };
+    if let Some(staged_path) = &staged {
+        let moved = config
+            .storage_retry
+            .run("Storing upload", || {
+                tokio::fs::rename(staged_path, &mod_metadata.file_path)
+            })
+            .await;
+        if let Err(e) = moved {
+            discard_upload_file(staged_path).await;
+            return Err(warp::reject::custom(UploadError {
+                details: e.to_string(),
+            }));
+        }
+    }
+    if let Some(replaced) = &replaced {
+        discard_upload_file(replaced).await;
+    }
+    cache.invalidate();
+    // Held uploads are announced when they are approved.
+    if !config.quarantine_uploads {
+        events.publish(CatalogEvent::Upserted {
+            id: mod_metadata.id.clone(),
+        });
+    }
+    // Lets the client send its next re-upload with `If-Match`.
+    let etag = sha256
+        .as_deref()
+        .filter(|_| is_latest)
+        .and_then(|checksum| HeaderValue::from_str(&strong_etag(checksum)).ok());
+    log_line!(
+        "{} mod {} version {}",
+        if exists { "Updated" } else { "Created" },
+        mod_metadata.id,
+        mod_metadata.version
+    );
+
+    if let Some(command) = config.post_upload_command.clone()
+        && config.post_upload_command_async
+    {
+        let status_url = format!("/upload/{}/status", mod_metadata.id);
+        processing.set(&mod_metadata.id, ProcessingStatus::Processing);
+        tokio::spawn(async move {
+            let id = mod_metadata.id;
+            let status = match run_post_upload_command(&command, &id, &mod_metadata.file_path).await
+            {
+                Ok(()) => ProcessingStatus::Complete,
+                Err(e) => {
+                    log_line!("Post-upload command failed for {}: {}", id, e);
+                    ProcessingStatus::Failed { error: e }
+                }
+            };
+            processing.set(&id, status);
+        });
+
+        let reply = warp::reply::with_status(
+            warp::reply::json(&json!({
+                "status": "processing",
+                "status_url": status_url,
+            })),
+            StatusCode::ACCEPTED,
+        );
+        let mut response = warp::reply::with_header(reply, "Location", status_url).into_response();
+        if let Some(etag) = etag {
+            response.headers_mut().insert(ETAG, etag);
+        }
+        return Ok(response);
+    }
+
+    if let Some(command) = &config.post_upload_command
+        && let Err(e) =
+            run_post_upload_command(command, &mod_metadata.id, &mod_metadata.file_path).await
+    {
+        log_line!("Post-upload command failed for {}: {}", mod_metadata.id, e);
+        if config.post_upload_command_strict {
+            return Err(warp::reject::custom(HookError { details: e }));
+        }
+    }
+
+    // An older version, like a held upload, leaves the mod row describing
+    // another one, so it is answered from the upload itself; it has no
+    // thumbnail of its own.
+    let stored = if is_latest {
+        with_conn(&db, move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM mods WHERE id = ?1", METADATA_COLUMNS),
+                params![mod_metadata.id],
+                row_to_metadata,
+            )
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        })
+        .await?
+    } else {
+        ModMetadata {
+            thumbnail: String::new(),
+            size_bytes: size_bytes.map(|size| size as u64),
+            sha256,
+            ..mod_metadata
+        }
+    };
+    let status = if exists {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    let mut response = warp::reply::with_status(warp::reply::json(&stored), status).into_response();
+    if let Some(etag) = etag {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    Ok(response)
+}
+
+/// Claims an id for `MOD_DB_RESERVATION_TTL_SECS`. While the claim holds,
+/// uploads under the id must carry the returned token; presenting it again
+/// here renews the claim.
+async fn handle_reserve(
+    id: String,
+    admin_key: Option<String>,
+    reservation_token: Option<String>,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    if id.trim().is_empty() {
+        return Err(warp::reject::custom(ValidationError {
+            details: "id must not be empty".to_string(),
+        }));
+    }
+
+    let now = unix_now();
+    let expires_at = now + config.reservation_ttl.as_secs() as i64;
+    let token = with_conn(&db, {
+        let (id, config) = (id.clone(), config.clone());
+        move |conn| {
+            // Taken as a write transaction up front so two publishers can't
+            // both see the id as free.
+            let tx = conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            let held: Option<String> = tx
+                .query_row(
+                    "SELECT token FROM reservations WHERE id = ?1 AND expires_at > ?2",
+                    params![id, now],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            let token = match held {
+                Some(held) if reservation_token.as_deref() == Some(held.as_str()) => held,
+                Some(_) => {
+                    return Err(warp::reject::custom(ConflictError {
+                        details: format!("`{}` is reserved by another publisher", id),
+                    }));
+                }
+                None => to_hex(&rand::random::<[u8; 16]>()),
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO reservations (id, token, identity, expires_at)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![id, token, identity, expires_at],
+            )
+            .and_then(|_| record_audit(&tx, &config, &identity, "reserve", &id, None))
+            .and_then(|_| tx.commit())
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+            Ok(token)
+        }
+    })
+    .await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({
+            "id": id,
+            "token": token,
+            "expires_at": expires_at,
+        })),
+        StatusCode::CREATED,
+    ))
+}
+
+/// Rejects a write to a reserved id unless it carries the reservation's token.
+async fn check_reservation(
+    db: &DbConnection,
+    id: &str,
+    token: Option<&str>,
+) -> Result<(), Rejection> {
+    let held: Option<String> = with_conn(db, {
+        let id = id.to_string();
+        move |conn| {
+            conn.query_row(
+                "SELECT token FROM reservations WHERE id = ?1 AND expires_at > ?2",
+                params![id, unix_now()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+    match held {
+        Some(held) if token != Some(held.as_str()) => Err(warp::reject::custom(ConflictError {
+            details: format!(
+                "`{}` is reserved; send its X-Reservation-Token to upload",
+                id
+            ),
+        })),
+        _ => Ok(()),
+    }
+}
+
+/// Removes a mod, its stored files, and everything recorded about it, leaving
+/// a tombstone so later lookups answer `410 Gone`. The files are unlinked
+/// before the rows are deleted, so a failed unlink leaves the mod in place to
+/// be retried rather than a row pointing at nothing or a file nobody owns.
+async fn handle_delete(
+    id: String,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    with_conn(&db, {
+        let (id, config) = (id.clone(), config.clone());
+        move |conn| {
+            let file_path: String = conn
+                .query_row(
+                    "SELECT file_path FROM mods WHERE id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?
+                .ok_or_else(|| mod_not_found(conn, &id))?;
+            refuse_if_protected(conn, Some(&id))?;
+            remove_mod(conn, &config, &identity, &id, file_path)
+        }
+    })
+    .await?;
+    cache.invalidate();
+    events.publish(CatalogEvent::Deleted { id: id.clone() });
+    log_line!("Deleted mod {}", id);
+
+    Ok(StatusCode::OK)
+}
+
+/// Removes a mod's files, except those other mods share, and then its rows,
+/// along with any update held for review. It leaves a tombstone so the id
+/// answers `410 Gone`.
+fn remove_mod(
+    conn: &mut Connection,
+    config: &Config,
+    identity: &str,
+    id: &str,
+    file_path: String,
+) -> Result<(), Rejection> {
+    let mut files = conn
+        .prepare(
+            "SELECT file_path FROM mod_versions WHERE id = ?1
+            UNION SELECT file_path FROM pending_uploads WHERE id = ?1",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })?;
+    files.push(file_path);
+    files.push(config.mod_path(format!("{}.gz.part", id)));
+    // Files that other mods were deduplicated onto stay for them.
+    files.retain(|file| !file_is_shared(conn, file, id));
+    for file in &files {
+        match fs::remove_file(file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(warp::reject::custom(FileError {
+                    details: format!("failed to remove {}: {}", file, e),
+                }));
+            }
+            _ => {}
+        }
+    }
+    // Versioned files live in a directory of their own, which is empty now.
+    let _ = fs::remove_dir(config.mods_dir.join(id));
+
+    conn.transaction()
+        .and_then(|tx| {
+            for table in ["mod_versions", "reservations", "pending_uploads", "mods"] {
+                tx.execute(&format!("DELETE FROM {} WHERE id = ?1", table), params![id])?;
+            }
+            for table in ["mod_tags", "partial_upload_ranges", "partial_uploads"] {
+                tx.execute(
+                    &format!("DELETE FROM {} WHERE mod_id = ?1", table),
+                    params![id],
+                )?;
+            }
+            sync_search_index(&tx, id)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO deleted_mods (id, deleted_at) VALUES (?1, ?2)",
+                params![id, unix_now()],
+            )?;
+            record_change(&tx, id, "delete")?;
+            record_audit(&tx, config, identity, "delete", id, None)?;
+            tx.commit()
+        })
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })
+}
+
+/// Whether `version` sorts before `other` as semver. Versions that are not
+/// semver are never considered older, so such an upload becomes the latest.
+fn is_older_version(version: &str, other: &str) -> bool {
+    match (parse_version_lenient(version), parse_version_lenient(other)) {
+        (Ok(version), Ok(other)) => version < other,
+        _ => false,
+    }
+}
+
+/// Orders versions as semver. Versions that are not semver sort after every
+/// semver one, and as plain text among themselves.
+fn compare_versions(version: &str, other: &str) -> cmp::Ordering {
+    match (parse_version_lenient(version), parse_version_lenient(other)) {
+        (Ok(version), Ok(other)) => version.cmp(&other),
+        (Ok(_), Err(_)) => cmp::Ordering::Less,
+        (Err(_), Ok(_)) => cmp::Ordering::Greater,
+        (Err(_), Err(_)) => version.cmp(other),
+    }
+}
+
+/// Backs `?if_not_exists=true`, for publishing flows that must never replace
+/// an existing mod.
+async fn refuse_if_exists(db: &DbConnection, id: &str) -> Result<(), Rejection> {
+    let exists: bool = with_conn(db, {
+        let id = id.to_string();
+        move |conn| {
+            conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)",
+                params![id],
+                |row| row.get(0),
+            )
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+    if exists {
+        return Err(warp::reject::custom(ConflictError {
+            details: format!("mod `{}` already exists", id),
+        }));
+    }
+    Ok(())
+}
+
+/// Guards re-uploads against lost updates: an `If-Match` header must name the
+/// ETag of the file currently stored, and with `MOD_DB_REQUIRE_IF_MATCH` the
+/// header is mandatory whenever the id already exists.
+async fn check_upload_precondition(
+    db: &DbConnection,
+    config: &Config,
+    id: &str,
+    if_match: Option<&str>,
+) -> Result<(), Rejection> {
+    if if_match.is_none() && !config.require_if_match {
+        return Ok(());
+    }
+    let stored = with_conn(db, {
+        let id = id.to_string();
+        move |conn| {
+            conn.query_row(
+                "SELECT sha256, file_path FROM mods WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+    let current = match stored {
+        Some((Some(checksum), _)) => Some(checksum),
+        Some((None, file_path)) if !file_path.is_empty() => {
+            Some(compute_checksum(db, id, file_path).await?)
+        }
+        _ => None,
+    }
+    .map(|checksum| strong_etag(&checksum));
+
+    match (if_match, &current) {
+        (None, None) => Ok(()),
+        (None, Some(_)) => Err(warp::reject::custom(PreconditionRequiredError {
+            details: format!("re-uploading `{}` requires an If-Match header", id),
+        })),
+        (Some(if_match), current) if if_match_allows(if_match, current.as_deref()) => Ok(()),
+        (Some(_), Some(current)) => Err(warp::reject::custom(PreconditionFailedError {
+            details: format!("`{}` has changed, its file's ETag is now {}", id, current),
+        })),
+        (Some(_), None) => Err(warp::reject::custom(PreconditionFailedError {
+            details: format!("`{}` has no stored file to match", id),
+        })),
+    }
+}
+
+/// Rejects ids that could escape the mods directory once used in a file name.
+/// Ids are limited to ASCII letters, digits, `-`, and `_`.
+fn validate_mod_id(id: &str) -> Result<(), UploadError> {
+    if id.is_empty() {
+        return Err(UploadError {
+            details: "mod id must not be empty".to_string(),
+        });
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        return Err(UploadError {
+            details: format!(
+                "invalid mod id `{}`, only letters, digits, `-`, and `_` are allowed",
+                id
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Whether `value` can be used as a single path component under the mods directory.
+fn is_safe_path_segment(value: &str) -> bool {
+    !value.is_empty()
+        && value != "."
+        && value != ".."
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+'))
+}
+
+/// Downloads a mod's file again from its `source_url` and, once it passes the
+/// same checks as an upload, replaces the stored file and checksum.
+/// Concurrent refetches of the mod wait for the one in flight and share its
+/// answer.
+async fn handle_refetch(
+    id: String,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+    refetches: SingleFlight,
+) -> Result<impl Reply, Rejection> {
+    let (source_url, file_path, previous_sha256, version) = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            conn.query_row(
+                "SELECT source_url, file_path, sha256, version FROM mods WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<String>>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?
+            .ok_or_else(|| mod_not_found(conn, &id))
+        }
+    })
+    .await?;
+    let Some(source_url) = source_url else {
+        return Err(warp::reject::custom(ConflictError {
+            details: format!("mod `{}` has no source_url", id),
+        }));
+    };
+    if file_path.is_empty() {
+        validate_mod_id(&id).map_err(warp::reject::custom)?;
+    }
+
+    let source = RefetchSource {
+        id: id.clone(),
+        identity,
+        source_url,
+        file_path,
+        previous_sha256,
+        version,
+    };
+    let refetched = refetches
+        .run(&id, move || {
+            refetch_source(source, db, config, cache, events)
+        })
+        .await?;
+    Ok(warp::reply::json(&refetched))
+}
+
+/// The row a refetch replaces the file of.
+struct RefetchSource {
+    id: String,
+    identity: String,
+    source_url: String,
+    file_path: String,
+    previous_sha256: Option<String>,
+    version: String,
+}
+
+async fn refetch_source(
+    source: RefetchSource,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> RefetchResult {
+    let RefetchSource {
+        id,
+        identity,
+        source_url,
+        file_path,
+        previous_sha256,
+        version,
+    } = source;
+    let data = fetch_source(&source_url, config.max_file_bytes)
+        .await
+        .map_err(RefetchError::Upstream)?;
+    let prepared = prepare_mod_file(data, &config).await.map_err(|details| {
+        RefetchError::Upstream(format!(
+            "{} served an unusable file: {}",
+            source_url, details
+        ))
+    })?;
+
+    let file_path = if file_path.is_empty() {
+        config.mod_path(format!("{}.{}", id, file_extension(prepared.compression)))
+    } else {
+        file_path
+    };
+    // Refetches answer with their own errors, so they run their queries on
+    // the blocking pool directly rather than through `with_conn`.
+    let file_path = {
+        let (db, id) = (db.clone(), id.clone());
+        tokio::task::spawn_blocking(move || {
+            let conn = db.get().map_err(|e| e.to_string())?;
+            unshared_file_path(&conn, file_path, &id, &version, true).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|found| found)
+        .map_err(RefetchError::Db)?
+    };
+    // Written beside the stored file and renamed over it, so downloads never
+    // see a partly written file.
+    let staging_path = format!("{}.refetch", file_path);
+    let data = Bytes::from(prepared.data);
+    config
+        .storage_retry
+        .run("Storing refetched file", || {
+            tokio::fs::write(&staging_path, data.clone())
+        })
+        .await
+        .and_then(|_| fs::rename(&staging_path, &file_path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&staging_path);
+            RefetchError::File(e.to_string())
+        })?;
+
+    let size_bytes = data.len() as i64;
+    let changed = previous_sha256.as_deref() != Some(prepared.sha256.as_str());
+    let recorded = {
+        let (db, config, id, source_url) =
+            (db.clone(), config.clone(), id.clone(), source_url.clone());
+        let sha256 = prepared.sha256.clone();
+        let (server_gzipped, compression) = (prepared.server_gzipped, prepared.compression);
+        tokio::task::spawn_blocking(move || {
+            let conn = db.get().map_err(|e| e.to_string())?;
+            conn.execute(
+                "UPDATE mods SET file_path = ?1, sha256 = ?2, size_bytes = ?3, server_gzipped = ?4, updated_at = ?5, compression = ?6 WHERE id = ?7",
+                params![
+                    file_path,
+                    sha256,
+                    size_bytes,
+                    server_gzipped,
+                    unix_now(),
+                    compression,
+                    id
+                ],
+            )
+            .and_then(|_| {
+                conn.execute(
+                    "UPDATE mod_versions SET sha256 = ?1 WHERE id = ?2 AND file_path = ?3",
+                    params![sha256, id, file_path],
+                )
+            })
+            .and_then(|_| record_change(&conn, &id, "update"))
+            .and_then(|_| record_audit(&conn, &config, &identity, "refetch", &id, Some(&source_url)))
+            .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|recorded| recorded)
+    };
+    recorded.map_err(RefetchError::Db)?;
+    cache.invalidate();
+    events.publish(CatalogEvent::Upserted { id: id.clone() });
+    log_line!("Refetched mod {} from {}", id, source_url);
+
+    Ok(json!({
+        "id": id,
+        "source_url": source_url,
+        "sha256": prepared.sha256,
+        "size_bytes": size_bytes,
+        "changed": changed,
+    }))
+}
+
+/// Fetches a mod file from its source, refusing anything larger than an upload
+/// could be.
+async fn fetch_source(url: &str, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let client = reqwest::Client::builder()
+        .timeout(SOURCE_FETCH_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("fetching {} failed: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("{} answered {}", url, response.status()));
+    }
+    let too_large = || format!("{} served more than {} bytes", url, max_bytes);
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes)
+    {
+        return Err(too_large());
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("reading {} failed: {}", url, e))?
+    {
+        if (data.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+async fn handle_upload_status(
+    id: String,
+    db: DbConnection,
+    processing: ProcessingTracker,
+) -> Result<impl Reply, Rejection> {
+    if let Some(status) = processing.get(&id) {
+        return Ok(warp::reply::json(&status));
+    }
+
+    // Uploads finished before a restart, or without background processing,
+    // are not tracked and are complete once their row exists.
+    with_conn(&db, move |conn| {
+        let exists: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)",
+                params![id],
+                |row| row.get(0),
+            )
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+        if exists {
+            Ok(())
+        } else {
+            Err(mod_not_found(conn, &id))
+        }
+    })
+    .await?;
+    Ok(warp::reply::json(&ProcessingStatus::Complete))
+}
+
+/// Runs the configured post-upload command, substituting `{id}` and `{path}`
+/// in each whitespace-separated argument of the template.
+async fn run_post_upload_command(template: &str, id: &str, file_path: &str) -> Result<(), String> {
+    let mut args = template
+        .split_whitespace()
+        .map(|arg| arg.replace("{id}", id).replace("{path}", file_path));
+    let program = args
+        .next()
+        .ok_or_else(|| "post-upload command is empty".to_string())?;
+
+    let status = tokio::process::Command::new(&program)
+        .args(args)
+        .status()
+        .await
+        .map_err(|e| format!("failed to run {}: {}", program, e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", program, status))
+    }
+}
+
+/// Searches title, author, tags, and description through the FTS5 index,
+/// ranking with BM25 weighted towards title, then author and tags, then
+/// description.
+async fn handle_full_search(
+    query: FullSearchQuery,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    let Some(fts_query) = fts_prefix_query(&query.q) else {
+        return Err(warp::reject::custom(QueryError {
+            details: "`q` must not be empty".to_string(),
+        }));
+    };
+    let limit = query.limit.unwrap_or(50).min(MAX_SEARCH_RESULTS);
+
+    let sql = format!(
+        "SELECT {} FROM mods
+        JOIN (
+            SELECT mod_id, bm25(mods_fts, 0.0, 8.0, 4.0, 1.0, 2.0) AS rank
+            FROM mods_fts
+            WHERE mods_fts MATCH ?1
+        ) AS hits ON hits.mod_id = mods.id
+        ORDER BY hits.rank, mods.title
+        LIMIT ?2",
+        METADATA_COLUMNS
+    );
+
+    let mods = with_conn(&db, move |conn| {
+        conn.prepare(&sql)
+            .and_then(|mut stmt| {
+                stmt.query_map(params![fts_query, limit], row_to_metadata)?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+    })
+    .await?;
+
+    Ok(warp::reply::json(&mods))
+}
+
+/// Lists mods with search, tag and author filters, sorting, and paging combined
+/// in one query. `total` counts every match, not just the returned page.
+async fn handle_list_mods(query: ModsQuery, db: DbConnection) -> Result<impl Reply, Rejection> {
+    let invalid = |details: String| warp::reject::custom(QueryError { details });
+    let sort = query.sort.as_deref().unwrap_or("title");
+    let (sort_column, default_order) = match sort {
+        "title" => ("mods.title_normalized", "asc"),
+        "author" => ("mods.author COLLATE NOCASE", "asc"),
+        "downloads" => ("mods.downloads", "desc"),
+        "size" => ("COALESCE(mods.size_bytes, 0)", "desc"),
+        other => {
+            return Err(invalid(format!(
+                "invalid sort `{}`, expected `title`, `author`, `downloads`, or `size`",
+                other
+            )));
+        }
+    };
+    let direction = match query.order.as_deref().unwrap_or(default_order) {
+        "asc" => "ASC",
+        "desc" => "DESC",
+        other => {
+            return Err(invalid(format!(
+                "invalid order `{}`, expected `asc` or `desc`",
+                other
+            )));
+        }
+    };
+
+    let mut conditions = Vec::new();
+    let mut values: Vec<rusqlite::types::Value> = Vec::new();
+    if let Some(q) = query.q.as_deref().filter(|q| !q.trim().is_empty()) {
+        let Some(fts_query) = fts_prefix_query(q) else {
+            return Err(invalid("`q` must contain a word".to_string()));
+        };
+        values.push(fts_query.into());
+        conditions.push(format!(
+            "mods.id IN (SELECT mod_id FROM mods_fts WHERE mods_fts MATCH ?{})",
+            values.len()
+        ));
+    }
+    let tags = query.tag.as_deref().map(parse_tags).unwrap_or_default();
+    if !tags.is_empty() {
+        let mut placeholders = Vec::new();
+        for tag in &tags {
+            values.push(tag.clone().into());
+            placeholders.push(format!("?{}", values.len()));
+        }
+        conditions.push(format!(
+            "mods.id IN (SELECT mod_id FROM mod_tags WHERE tag IN ({}) \
+             GROUP BY mod_id HAVING COUNT(*) = {})",
+            placeholders.join(", "),
+            tags.len()
+        ));
+    }
+    if let Some(author) = query
+        .author
+        .as_deref()
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+    {
+        values.push(author.to_string().into());
+        conditions.push(format!("mods.author = ?{} COLLATE NOCASE", values.len()));
+    }
+    let filter = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
+
+    let limit = query.limit.unwrap_or(50).min(MAX_SEARCH_RESULTS);
+    let offset = query.offset.unwrap_or(0);
+    let count_sql = format!("SELECT COUNT(*) FROM mods{}", filter);
+    let page_sql = format!(
+        "SELECT {} FROM mods{} ORDER BY {} {}, mods.id LIMIT ?{} OFFSET ?{}",
+        METADATA_COLUMNS,
+        filter,
+        sort_column,
+        direction,
+        values.len() + 1,
+        values.len() + 2
+    );
+
+    let (total, items) = with_conn(&db, move |conn| {
+        let db_error = |e: rusqlite::Error| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        };
+        let total: u64 = conn
+            .query_row(
+                &count_sql,
+                rusqlite::params_from_iter(values.iter()),
+                |row| row.get(0),
+            )
+            .map_err(db_error)?;
+        values.push(i64::from(limit).into());
+        values.push(i64::from(offset).into());
+        let items = conn
+            .prepare(&page_sql)
+            .and_then(|mut stmt| {
+                stmt.query_map(rusqlite::params_from_iter(values), row_to_metadata)?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(db_error)?;
+        Ok((total, items))
+    })
+    .await?;
+
+    Ok(warp::reply::json(&json!({
+        "items": items,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    })))
+}
+
+/// Turns free text into an FTS5 query matching every word as a prefix. Words
+/// are quoted so user input can never be parsed as FTS5 syntax.
+fn fts_prefix_query(text: &str) -> Option<String> {
+    let terms = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Compares a client's installed modpack against the catalog in one call. A mod
+/// is reported as outdated when the stored version is newer as semver, or,
+/// when either version isn't semver, whenever the two differ.
+async fn handle_modpack_check(
+    request: ModpackCheckRequest,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    if request.mods.len() > MAX_MODPACK_ENTRIES {
+        return Err(warp::reject::custom(ValidationError {
+            details: format!(
+                "a modpack check accepts at most {} mods",
+                MAX_MODPACK_ENTRIES
+            ),
+        }));
+    }
+
+    let statuses = with_conn(&db, move |conn| {
+        let mut statuses = Vec::with_capacity(request.mods.len());
+        for entry in request.mods {
+            let latest_version: Option<String> = conn
+                .query_row(
+                    "SELECT version FROM mods WHERE id = ?1",
+                    params![entry.id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+
+            let status = match &latest_version {
+                None => "unknown",
+                Some(latest)
+                    if parse_version_lenient(latest).is_ok()
+                        && parse_version_lenient(&entry.version).is_ok() =>
+                {
+                    if is_older_version(&entry.version, latest) {
+                        "update_available"
+                    } else {
+                        "up_to_date"
+                    }
+                }
+                Some(latest) if *latest == entry.version => "up_to_date",
+                Some(_) => "update_available",
+            };
+            statuses.push(ModpackStatus {
+                id: entry.id,
+                installed_version: entry.version,
+                latest_version,
+                status,
+            });
+        }
+        Ok(statuses)
+    })
+    .await?;
+
+    let count = |status: &str| statuses.iter().filter(|s| s.status == status).count();
+    Ok(warp::reply::json(&json!({
+        "summary": {
+            "total": statuses.len(),
+            "up_to_date": count("up_to_date"),
+            "updates_available": count("update_available"),
+            "unknown": count("unknown"),
+        },
+        "mods": statuses,
+    })))
+}
+
+/// Returns the metadata of the listed mods, in the order they were asked for.
+/// Ids with no mod are left out rather than failing the request.
+async fn handle_metadata_batch(
+    request: MetadataBatchRequest,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    if request.ids.len() > MAX_METADATA_BATCH_ENTRIES {
+        return Err(warp::reject::custom(ValidationError {
+            details: format!(
+                "a metadata batch accepts at most {} ids",
+                MAX_METADATA_BATCH_ENTRIES
+            ),
+        }));
+    }
+
+    let mods = with_conn(&db, move |conn| {
+        load_metadata_by_ids(conn, &request.ids).map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })
+    })
+    .await?;
+    Ok(warp::reply::json(&mods))
+}
+
+fn load_metadata_by_ids(
+    conn: &Connection,
+    ids: &[String],
+) -> Result<Vec<ModMetadata>, rusqlite::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = (1..=ids.len())
+        .map(|index| format!("?{}", index))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM mods WHERE id IN ({})",
+        METADATA_COLUMNS, placeholders
+    ))?;
+    let mut found = stmt
+        .query_map(rusqlite::params_from_iter(ids), row_to_metadata)?
+        .map(|row| row.map(|metadata| (metadata.id.clone(), metadata)))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    // Removing each match keeps ids listed twice from showing up twice.
+    Ok(ids.iter().filter_map(|id| found.remove(id)).collect())
+}
+
+/// Compares client-side checksums against the stored SHA-256 of each mod's
+/// current file. Checksums of files stored before hashing was added are
+/// computed on first use and kept.
+async fn handle_verify_files(
+    request: VerifyFilesRequest,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    if request.files.len() > MAX_VERIFY_ENTRIES {
+        return Err(warp::reject::custom(ValidationError {
+            details: format!(
+                "a verification accepts at most {} files",
+                MAX_VERIFY_ENTRIES
+            ),
+        }));
+    }
+
+    let ids = request
+        .files
+        .iter()
+        .map(|entry| entry.id.clone())
+        .collect::<Vec<_>>();
+    let stored = with_conn(&db, move |conn| {
+        let mut stored: HashMap<String, Option<(Option<String>, String)>> = HashMap::new();
+        for id in ids {
+            if stored.contains_key(&id) {
+                continue;
+            }
+            let row = conn
+                .query_row(
+                    "SELECT sha256, file_path FROM mods WHERE id = ?1",
+                    params![id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            stored.insert(id, row);
+        }
+        Ok(stored)
+    })
+    .await?;
+
+    let mut checksums = HashMap::new();
+    for (id, row) in stored {
+        let Some((checksum, file_path)) = row else {
+            continue;
+        };
+        let checksum = match checksum {
+            Some(checksum) => checksum,
+            None => compute_checksum(&db, &id, file_path).await?,
+        };
+        checksums.insert(id, checksum);
+    }
+
+    let statuses = request
+        .files
+        .into_iter()
+        .map(|entry| {
+            let expected_checksum = checksums.get(&entry.id).cloned();
+            let status = match &expected_checksum {
+                None => "unknown",
+                Some(expected) if expected.eq_ignore_ascii_case(entry.checksum.trim()) => "match",
+                Some(_) => "outdated",
+            };
+            VerifyFileStatus {
+                id: entry.id,
+                status,
+                expected_checksum,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let count = |status: &str| statuses.iter().filter(|s| s.status == status).count();
+    Ok(warp::reply::json(&json!({
+        "summary": {
+            "total": statuses.len(),
+            "match": count("match"),
+            "outdated": count("outdated"),
+            "unknown": count("unknown"),
+        },
+        "files": statuses,
+    })))
+}
+
+/// Sums the bytes a client would download to bring the listed mods up to
+/// date. Mods are stored as whole files, so an outdated mod costs its full
+/// file size. Sizes of files stored before sizes were recorded are read from
+/// disk on first use and kept.
+async fn handle_update_size(
+    request: UpdateSizeRequest,
+    db: DbConnection,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    if request.mods.len() > MAX_UPDATE_SIZE_ENTRIES {
+        return Err(warp::reject::custom(ValidationError {
+            details: format!(
+                "an update size check accepts at most {} mods",
+                MAX_UPDATE_SIZE_ENTRIES
+            ),
+        }));
+    }
+
+    let (statuses, filled) = with_conn(&db, move |conn| {
+        let mut statuses = Vec::with_capacity(request.mods.len());
+        let mut filled = Vec::new();
+        for entry in request.mods {
+            let row: Option<(String, String, Option<i64>)> = conn
+                .query_row(
+                    "SELECT version, file_path, size_bytes FROM mods WHERE id = ?1",
+                    params![entry.id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            let Some((version, file_path, size_bytes)) = row else {
+                statuses.push(UpdateSizeStatus {
+                    id: entry.id,
+                    status: "unknown",
+                    version: None,
+                    bytes: 0,
+                });
+                continue;
+            };
+            if entry.have_version.as_deref().map(str::trim) == Some(version.as_str()) {
+                statuses.push(UpdateSizeStatus {
+                    id: entry.id,
+                    status: "current",
+                    version: Some(version),
+                    bytes: 0,
+                });
+                continue;
+            }
+
+            let bytes = match size_bytes {
+                Some(size) => size as u64,
+                None => {
+                    let size = fs::metadata(&file_path).map(|m| m.len()).map_err(|e| {
+                        warp::reject::custom(FileError {
+                            details: e.to_string(),
+                        })
+                    })?;
+                    match conn.execute(
+                        "UPDATE mods SET size_bytes = ?1 WHERE id = ?2",
+                        params![size as i64, entry.id],
+                    ) {
+                        Ok(_) => filled.push(entry.id.clone()),
+                        Err(e) => log_line!("Failed to store file size of {}: {}", entry.id, e),
+                    }
+                    size
+                }
+            };
+            statuses.push(UpdateSizeStatus {
+                id: entry.id,
+                status: "update",
+                version: Some(version),
+                bytes,
+            });
+        }
+        Ok((statuses, filled))
+    })
+    .await?;
+    if !filled.is_empty() {
+        cache.invalidate();
+    }
+    for id in filled {
+        events.publish(CatalogEvent::Upserted { id });
+    }
+
+    Ok(warp::reply::json(&json!({
+        "total_bytes": statuses.iter().map(|s| s.bytes).sum::<u64>(),
+        "mods": statuses,
+    })))
+}
+
+/// Hashes a mod's stored file for a row saved without a checksum, and keeps the
+/// result so the file is only hashed once.
+async fn compute_checksum(
+    db: &DbConnection,
+    id: &str,
+    file_path: String,
+) -> Result<String, Rejection> {
+    let checksum = tokio::task::spawn_blocking(move || sha256_file(&file_path))
+        .await
+        .map_err(io::Error::other)
+        .and_then(|hashed| hashed)
+        .map_err(|e| {
+            warp::reject::custom(FileError {
+                details: e.to_string(),
+            })
+        })?;
+    with_conn(db, {
+        let (id, checksum) = (id.to_string(), checksum.clone());
+        move |conn| {
+            if let Err(e) = conn.execute(
+                "UPDATE mods SET sha256 = ?1 WHERE id = ?2",
+                params![checksum, id],
+            ) {
+                log_line!("Failed to store checksum of {}: {}", id, e);
+            }
+            Ok(())
+        }
+    })
+    .await?;
+    Ok(checksum)
+}
+
+fn sha256_file(path: &str) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Ranks mods by downloads within a trailing window such as `7d` or `24h`.
+async fn handle_popular(query: PopularQuery, db: DbConnection) -> Result<impl Reply, Rejection> {
+    let window = query.window.as_deref().unwrap_or("7d");
+    let window = parse_window(window).ok_or_else(|| {
+        warp::reject::custom(QueryError {
+            details: format!("invalid window `{}`, expected e.g. `7d` or `12h`", window),
+        })
+    })?;
+    let since = unix_now() - window.as_secs() as i64;
+    let limit = query.limit.unwrap_or(10).min(MAX_SEARCH_RESULTS);
+
+    let mods = with_conn(&db, move |conn| {
+        conn.prepare(
+            "SELECT mods.id, mods.title, mods.version, COUNT(*) AS downloads
+            FROM download_log
+            JOIN mods ON mods.id = download_log.mod_id
+            WHERE download_log.downloaded_at >= ?1
+            GROUP BY mods.id
+            ORDER BY downloads DESC, mods.title
+            LIMIT ?2",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![since, limit], |row| {
+                Ok(PopularMod {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    version: row.get(2)?,
+                    downloads: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })
+    })
+    .await?;
+
+    Ok(warp::reply::json(&mods))
+}
+
+/// Totals an author's mods, all-time downloads, and stored bytes. Files stored
+/// before sizes were recorded count as zero bytes until their size is known.
+async fn handle_author_stats(author: String, db: DbConnection) -> Result<impl Reply, Rejection> {
+    let author = percent_encoding::percent_decode_str(&author)
+        .decode_utf8()
+        .map_err(|e| {
+            warp::reject::custom(QueryError {
+                details: format!("invalid author: {}", e),
+            })
+        })?
+        .into_owned();
+
+    let (mods, total_bytes, downloads): (u64, u64, u64) = with_conn(&db, {
+        let author = author.clone();
+        move |conn| {
+            conn.query_row(
+                "SELECT
+                    COUNT(*),
+                    COALESCE(SUM(size_bytes), 0),
+                    (SELECT COUNT(*) FROM download_log
+                        JOIN mods ON mods.id = download_log.mod_id
+                        WHERE mods.author = ?1)
+                FROM mods WHERE author = ?1",
+                params![author],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+    if mods == 0 {
+        return Err(warp::reject::custom(NotFoundError {
+            details: format!("no mods by author `{}`", author),
+        }));
+    }
+
+    Ok(warp::reply::json(&json!({
+        "author": author,
+        "mods": mods,
+        "downloads": downloads,
+        "total_bytes": total_bytes,
+    })))
+}
+
+/// Exports a mod's daily download counts between two UTC dates, both
+/// inclusive, as CSV. Days without downloads are listed with a zero count so
+/// the rows can be charted as-is.
+async fn handle_analytics_csv(
+    id: String,
+    query: AnalyticsQuery,
+    db: DbConnection,
+) -> Result<Response, Rejection> {
+    let invalid = |details: String| warp::reject::custom(QueryError { details });
+    let parse = |name: &str, value: &Option<String>| match value.as_deref() {
+        Some(value) => parse_utc_date(value)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("invalid {} `{}`, expected YYYY-MM-DD", name, value))),
+        None => Ok(None),
+    };
+    let to = parse("to", &query.to)?.unwrap_or(unix_now().div_euclid(86400));
+    let from = parse("from", &query.from)?.unwrap_or(to - 29);
+    if from > to {
+        return Err(invalid("`from` must not be after `to`".to_string()));
+    }
+    if to - from >= MAX_ANALYTICS_DAYS {
+        return Err(invalid(format!(
+            "the range may span at most {} days",
+            MAX_ANALYTICS_DAYS
+        )));
+    }
+
+    let (start, end) = from
+        .checked_mul(86400)
+        .zip(to.checked_add(1).and_then(|day| day.checked_mul(86400)))
+        .ok_or_else(|| invalid("the range is out of bounds".to_string()))?;
+
+    let counts = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            let db_error = |e: rusqlite::Error| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            };
+            let exists: bool = conn
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(db_error)?;
+            if !exists {
+                return Err(mod_not_found(conn, &id));
+            }
+            let counts = conn
+                .prepare(
+                    "SELECT downloaded_at / 86400 AS day, COUNT(*) FROM download_log
+                    WHERE mod_id = ?1 AND downloaded_at >= ?2 AND downloaded_at < ?3
+                    GROUP BY day",
+                )
+                .and_then(|mut stmt| {
+                    stmt.query_map(params![id, start, end], |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, u64>(1)?))
+                    })?
+                    .collect::<Result<HashMap<_, _>, _>>()
+                })
+                .map_err(db_error)?;
+            Ok(counts)
+        }
+    })
+    .await?;
+
+    let mut csv = String::from("date,downloads\n");
+    for day in from..=to {
+        csv.push_str(&format!(
+            "{},{}\n",
+            format_utc_date(day),
+            counts.get(&day).copied().unwrap_or(0)
+        ));
+    }
+
+    let mut response = Response::new(Body::from(csv));
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    if let Ok(disposition) =
+        HeaderValue::from_str(&format!("attachment; filename=\"{}-downloads.csv\"", id))
+    {
+        headers.insert("content-disposition", disposition);
+    }
+    Ok(response)
+}
+
+/// Parses `YYYY-MM-DD` into days since the Unix epoch. Years before the epoch
+/// or past 9999 are refused.
+fn parse_utc_date(value: &str) -> Option<i64> {
+    let mut parts = value.trim().splitn(3, '-');
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?.parse::<i64>().ok()?;
+    let day = parts.next()?.parse::<i64>().ok()?;
+    if !(1970..=9999).contains(&year) {
+        return None;
+    }
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month_days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=month_days).contains(&day) {
+        return None;
+    }
+    // Days from civil, counting years from March so leap days fall last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era.checked_mul(146097)?
+        .checked_add(day_of_era)?
+        .checked_sub(719468)
+}
+
+/// Formats days since the Unix epoch as `YYYY-MM-DD`.
+fn format_utc_date(days: i64) -> String {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Picks the highest semver version among mods with the given title, compared
+/// the same way title search folds case and accents. Versions that are not
+/// semver are never picked.
+async fn handle_latest(
+    query: LatestQuery,
+    accept: Option<String>,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    let candidates = with_conn(&db, {
+        let title = query.title.clone();
+        move |conn| {
+            let candidates = conn
+                .prepare(&format!(
+                    "SELECT {} FROM mods WHERE title_normalized = ?1",
+                    METADATA_COLUMNS
+                ))
+                .and_then(|mut stmt| {
+                    stmt.query_map(params![fold_for_search(title.trim())], row_to_metadata)?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            Ok(candidates)
+        }
+    })
+    .await?;
+
+    let latest = candidates
+        .into_iter()
+        .filter_map(|metadata| {
+            parse_version_lenient(&metadata.version)
+                .ok()
+                .map(|version| (version, metadata))
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, metadata)| metadata)
+        .ok_or_else(|| {
+            warp::reject::custom(NotFoundError {
+                details: format!("no mod titled `{}` has a semver version", query.title),
+            })
+        })?;
+
+    Ok(metadata_reply(&latest, accept.as_deref()))
+}
+
+/// Lists every URL the mod's file can be downloaded from: this server's
+/// `/download/{id}` first, then each configured mirror.
+async fn handle_sources(
+    id: String,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            let exists: bool = conn
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            if !exists {
+                return Err(mod_not_found(conn, &id));
+            }
+            Ok(())
+        }
+    })
+    .await?;
+
+    let encoded_id =
+        percent_encoding::utf8_percent_encode(&id, percent_encoding::NON_ALPHANUMERIC).to_string();
+    let mut sources = vec![json!({
+        "url": format!("/download/{}", encoded_id),
+        "kind": "local",
+    })];
+    sources.extend(config.download_mirrors.iter().map(|mirror| {
+        json!({
+            "url": mirror.replace("{id}", &encoded_id),
+            "kind": "mirror",
+        })
+    }));
+
+    Ok(warp::reply::json(&json!({
+        "id": id,
+        "sources": sources,
+    })))
+}
+
+/// Lists the stored versions of a mod, newest upload first, each with the
+/// metadata it was uploaded with. The latest version is described by the mod
+/// row, so edits to it show; a latest version uploaded before versions were
+/// kept appears without an upload time.
+async fn handle_versions(id: String, db: DbConnection) -> Result<impl Reply, Rejection> {
+    let (latest, mut versions) = with_conn(&db, move |conn| {
+        let latest = conn
+            .query_row(
+                &format!("SELECT {} FROM mods WHERE id = ?1", METADATA_COLUMNS),
+                params![id],
+                row_to_metadata,
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?
+            .ok_or_else(|| mod_not_found(conn, &id))?;
+
+        let versions = conn
+            .prepare(
+                "SELECT version, uploaded_at, title, description, author, tags, min_game_version, source_url, size_bytes, sha256, compression
+                FROM mod_versions WHERE id = ?1
+                ORDER BY uploaded_at DESC, version DESC",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map(params![id], |row| {
+                    let version: String = row.get(0)?;
+                    Ok(ModVersion {
+                        latest: version == latest.version,
+                        version,
+                        uploaded_at: row.get(1)?,
+                        title: row.get(2)?,
+                        description: row.get(3)?,
+                        author: row.get(4)?,
+                        tags: row
+                            .get::<_, Option<String>>(5)?
+                            .map(|tags| tags.split(',').map(str::to_string).collect())
+                            .unwrap_or_default(),
+                        min_game_version: row.get(6)?,
+                        source_url: row.get(7)?,
+                        size_bytes: row.get(8)?,
+                        sha256: row.get(9)?,
+                        compression: row.get(10)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+        Ok((latest, versions))
+    })
+    .await?;
+    let described = ModVersion {
+        version: latest.version,
+        uploaded_at: None,
+        latest: true,
+        title: Some(latest.title),
+        description: Some(latest.description),
+        author: Some(latest.author),
+        tags: latest.tags,
+        min_game_version: latest.min_game_version,
+        source_url: latest.source_url,
+        size_bytes: latest.size_bytes,
+        sha256: latest.sha256,
+        compression: Some(latest.compression),
+    };
+    match versions.iter_mut().find(|version| version.latest) {
+        Some(version) => {
+            *version = ModVersion {
+                uploaded_at: version.uploaded_at,
+                ..described
+            }
+        }
+        None => versions.insert(0, described),
+    }
+
+    Ok(warp::reply::json(&versions))
+}
+
+/// Lists mods that share tags or the author with `id`, most overlap first. Each
+/// shared tag counts once and a shared author counts as one more.
+async fn handle_related(
+    id: String,
+    query: RelatedQuery,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    let limit = query.limit.unwrap_or(10).min(MAX_SEARCH_RESULTS);
+
+    let related = with_conn(&db, move |conn| {
+        let author: String = conn
+            .query_row(
+                "SELECT author FROM mods WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?
+            .ok_or_else(|| mod_not_found(conn, &id))?;
+
+        let sql = format!(
+            "WITH shared AS (
+                SELECT other.mod_id, COUNT(*) AS shared_tags
+                FROM mod_tags own
+                JOIN mod_tags other ON other.tag = own.tag AND other.mod_id != own.mod_id
+                WHERE own.mod_id = ?1
+                GROUP BY other.mod_id
+            )
+            SELECT {}, COALESCE(shared.shared_tags, 0) AS shared_tags,
+                (?2 != '' AND mods.author = ?2) AS same_author
+            FROM mods
+            LEFT JOIN shared ON shared.mod_id = mods.id
+            WHERE mods.id != ?1 AND (shared_tags > 0 OR same_author)
+            ORDER BY shared_tags + same_author DESC, shared_tags DESC, mods.title
+            LIMIT ?3",
+            METADATA_COLUMNS
+        );
+        let related = conn
+            .prepare(&sql)
+            .and_then(|mut stmt| {
+                stmt.query_map(params![id, author, limit], |row| {
+                    Ok(RelatedMod {
+                        metadata: row_to_metadata(row)?,
+                        shared_tags: row.get("shared_tags")?,
+                        same_author: row.get("same_author")?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+        Ok(related)
+    })
+    .await?;
+
+    Ok(warp::reply::json(&related))
+}
+
+/// Parses a window like `30m`, `12h`, `7d`, or `2w`.
+fn parse_window(window: &str) -> Option<Duration> {
+    let window = window.trim();
+    let unit = window.chars().last()?;
+    let amount = &window[..window.len() - unit.len_utf8()];
+    let amount: u64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+    let unit_secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(unit_secs).map(Duration::from_secs)
+}
+
+/// Applies an RFC 7386 JSON Merge Patch to a mod's editable metadata: keys that
+/// are absent are left alone, `null` clears optional fields, and any other value
+/// replaces the stored one.
+async fn handle_patch_metadata(
+    id: String,
+    body: Bytes,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    // Parsed by hand because clients send `application/merge-patch+json`,
+    // which warp's JSON filter does not accept.
+    let patch = serde_json::from_slice::<serde_json::Value>(&body).map_err(|e| {
+        warp::reject::custom(ValidationError {
+            details: format!("invalid merge patch: {}", e),
+        })
+    })?;
+    let serde_json::Value::Object(patch) = patch else {
+        return Err(warp::reject::custom(ValidationError {
+            details: "merge patch must be a JSON object".to_string(),
+        }));
+    };
+
+    let (mut metadata, mut readme) = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {}, readme FROM mods WHERE id = ?1",
+                    METADATA_COLUMNS
+                ),
+                params![id],
+                |row| Ok((row_to_metadata(row)?, row.get::<_, String>("readme")?)),
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?
+            .ok_or_else(|| mod_not_found(conn, &id))
+        }
+    })
+    .await?;
+
+    for (key, value) in &patch {
+        match key.as_str() {
+            "title" => metadata.title = required_patch_string(key, value)?,
+            "version" => metadata.version = required_patch_string(key, value)?,
+            "description" => metadata.description = optional_patch_string(key, value)?,
+            "author" => metadata.author = optional_patch_string(key, value)?,
+            "min_game_version" => {
+                let value = optional_patch_string(key, value)?;
+                metadata.min_game_version = if value.trim().is_empty() {
+                    None
+                } else {
+                    Some(
+                        parse_game_version(&value)
+                            .map_err(|details| warp::reject::custom(ValidationError { details }))?
+                            .to_string(),
+                    )
+                };
+            }
+            "source_url" => {
+                metadata.source_url = parse_source_url(&optional_patch_string(key, value)?)
+                    .map_err(|details| warp::reject::custom(ValidationError { details }))?;
+            }
+            "tags" => {
+                metadata.tags = match value {
+                    serde_json::Value::Null => Vec::new(),
+                    serde_json::Value::Array(values) => {
+                        let mut raw = Vec::new();
+                        for value in values {
+                            let serde_json::Value::String(tag) = value else {
+                                return Err(warp::reject::custom(ValidationError {
+                                    details: "`tags` must be an array of strings or null"
+                                        .to_string(),
+                                }));
+                            };
+                            raw.push(tag.as_str());
+                        }
+                        parse_tags(&raw.join(","))
+                    }
+                    _ => {
+                        return Err(warp::reject::custom(ValidationError {
+                            details: "`tags` must be an array of strings or null".to_string(),
+                        }));
+                    }
+                }
+            }
+            "readme" => {
+                readme = optional_patch_string(key, value)?;
+                if readme.len() > MAX_README_BYTES {
+                    return Err(warp::reject::custom(ValidationError {
+                        details: format!("readme exceeds {} bytes", MAX_README_BYTES),
+                    }));
+                }
+            }
+            _ => {
+                return Err(warp::reject::custom(ValidationError {
+                    details: format!("field `{}` cannot be patched", key),
+                }));
+            }
+        }
+    }
+    enforce_field_lengths(&config, &mut metadata)
+        .map_err(|details| warp::reject::custom(ValidationError { details }))?;
+    metadata.updated_at = Some(unix_now());
+
+    with_conn(&db, {
+        let (id, identity, config) = (id.clone(), identity.clone(), config.clone());
+        let (metadata, fields) = (metadata.clone(), patch.keys().cloned().collect::<Vec<_>>().join(","));
+        move |conn| {
+            conn.execute(
+                "UPDATE mods SET title = ?1, version = ?2, description = ?3, readme = ?4, title_normalized = ?5, author = ?6, min_game_version = ?7, source_url = ?8, updated_at = ?9 WHERE id = ?10",
+                params![
+                    metadata.title,
+                    metadata.version,
+                    metadata.description,
+                    readme,
+                    fold_for_search(&metadata.title),
+                    metadata.author,
+                    metadata.min_game_version,
+                    metadata.source_url,
+                    metadata.updated_at,
+                    id
+                ],
+            )
+            .and_then(|_| replace_tags(conn, &id, &metadata.tags))
+            .and_then(|_| sync_search_index(conn, &id))
+            .and_then(|_| record_change(conn, &id, "update"))
+            .and_then(|_| record_audit(conn, &config, &identity, "patch", &id, Some(&fields)))
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+    cache.invalidate();
+    events.publish(CatalogEvent::Upserted { id });
+
+    Ok(warp::reply::json(&metadata))
+}
+
+/// Applies the configured title and description limits, either rejecting an
+/// overlong field or truncating it with an ellipsis.
+fn enforce_field_lengths(config: &Config, metadata: &mut ModMetadata) -> Result<(), String> {
+    enforce_length(
+        "title",
+        &mut metadata.title,
+        config.max_title_length,
+        config.overlong_fields,
+    )?;
+    enforce_length(
+        "description",
+        &mut metadata.description,
+        config.max_description_length,
+        config.overlong_fields,
+    )
+}
+
+fn enforce_length(
+    field: &str,
+    value: &mut String,
+    max: usize,
+    mode: OverlongFieldMode,
+) -> Result<(), String> {
+    let length = value.chars().count();
+    if length <= max {
+        return Ok(());
+    }
+
+    match mode {
+        OverlongFieldMode::Reject => Err(format!(
+            "{} is {} characters, the limit is {}",
+            field, length, max
+        )),
+        OverlongFieldMode::Truncate => {
+            let mut truncated = value
+                .chars()
+                .take(max.saturating_sub(1))
+                .collect::<String>();
+            if max > 0 {
+                truncated.push('…');
+            }
+            log_line!("Truncated {} from {} to {} characters", field, length, max);
+            *value = truncated;
+            Ok(())
+        }
+    }
+}
+
+fn required_patch_string(key: &str, value: &serde_json::Value) -> Result<String, Rejection> {
+    match value {
+        serde_json::Value::String(value) if !value.is_empty() => Ok(value.clone()),
+        _ => Err(warp::reject::custom(ValidationError {
+            details: format!("`{}` must be a non-empty string", key),
+        })),
+    }
+}
+
+fn optional_patch_string(key: &str, value: &serde_json::Value) -> Result<String, Rejection> {
+    match value {
+        serde_json::Value::Null => Ok(String::new()),
+        serde_json::Value::String(value) => Ok(value.clone()),
+        _ => Err(warp::reject::custom(ValidationError {
+            details: format!("`{}` must be a string or null", key),
+        })),
+    }
+}
+
+/// Accepts one `Content-Range` chunk of a mod file. Chunks may arrive in any
+/// order; they are written at their offset into a staging file, and once every
+/// byte of the declared total has been received the staging file replaces the
+/// mod's stored file.
+async fn handle_upload_range(
+    id: String,
+    headers: RangeUploadHeaders,
+    body: Bytes,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    let RangeUploadHeaders {
+        content_range,
+        reservation_token,
+        identity,
+    } = headers;
+    validate_mod_id(&id).map_err(warp::reject::custom)?;
+    let (start, end, total) = parse_content_range(&content_range).ok_or_else(|| {
+        warp::reject::custom(UploadError {
+            details: format!("invalid Content-Range `{}`", content_range),
+        })
+    })?;
+    if total > config.max_file_bytes {
+        return Err(warp::reject::custom(PayloadTooLargeError {
+            details: format!("total size exceeds {} bytes", config.max_file_bytes),
+        }));
+    }
+    if body.len() as u64 != end - start + 1 {
+        return Err(warp::reject::custom(UploadError {
+            details: format!(
+                "body is {} bytes but Content-Range covers {}",
+                body.len(),
+                end - start + 1
+            ),
+        }));
+    }
+    check_reservation(&db, &id, reservation_token.as_deref()).await?;
+
+    with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            let exists: bool = conn
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            if !exists {
+                return Err(mod_not_found(conn, &id));
+            }
+
+            let staged_total: Option<u64> = conn
+                .query_row(
+                    "SELECT total FROM partial_uploads WHERE mod_id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            match staged_total {
+                Some(staged) if staged != total => {
+                    return Err(warp::reject::custom(UploadError {
+                        details: format!(
+                            "an upload of {} bytes is already in progress for `{}`",
+                            staged, id
+                        ),
+                    }));
+                }
+                Some(_) => {}
+                None => {
+                    conn.execute(
+                        "INSERT INTO partial_uploads (mod_id, total) VALUES (?1, ?2)",
+                        params![id, total],
+                    )
+                    .map_err(|e| {
+                        warp::reject::custom(DbError {
+                            details: e.to_string(),
+                        })
+                    })?;
+                }
+            }
+            Ok(())
+        }
+    })
+    .await?;
+
+    let staging_path = config.mod_path(format!("{}.gz.part", id));
+    config
+        .storage_retry
+        .run("Storing upload chunk", || {
+            write_chunk(&staging_path, start, total, &body)
+        })
+        .await
+        .map_err(|e| {
+            warp::reject::custom(FileError {
+                details: e.to_string(),
+            })
+        })?;
+
+    let received = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            conn.execute(
+                "INSERT INTO partial_upload_ranges (mod_id, start, end) VALUES (?1, ?2, ?3)",
+                params![id, start, end],
+            )
+            .and_then(|_| received_bytes(conn, &id))
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+        }
+    })
+    .await?;
+
+    if received < total {
+        let reply = warp::reply::json(&json!({
+            "received": received,
+            "total": total,
+            "complete": false,
+        }));
+        return Ok(warp::reply::with_status(reply, StatusCode::ACCEPTED));
+    }
+
+    // The assembled file goes through the same format check, hashing, and
+    // deduplication as a form upload. A file that fails them is dropped along
+    // with its ranges so the client can start over.
+    let db_error = |e: rusqlite::Error| {
+        warp::reject::custom(DbError {
+            details: e.to_string(),
+        })
+    };
+    let data = tokio::fs::read(&staging_path).await.map_err(|e| {
+        warp::reject::custom(FileError {
+            details: e.to_string(),
+        })
+    })?;
+    let prepared = match prepare_mod_file(data, &config).await {
+        Ok(prepared) => prepared,
+        Err(details) => {
+            with_conn(&db, {
+                let id = id.clone();
+                move |conn| clear_partial_upload(conn, &id).map_err(db_error)
+            })
+            .await?;
+            discard_upload_file(&staging_path).await;
+            return Err(warp::reject::custom(UploadError { details }));
+        }
+    };
+
+    let (mut mod_metadata, readme, thumbnail_sizes) = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            conn.query_row(
+                &format!("SELECT {}, readme, thumbnail_original_bytes, thumbnail_bytes FROM mods WHERE id = ?1", EXPORT_COLUMNS),
+                params![id],
+                |row| {
+                    Ok((
+                        row_to_metadata(row)?,
+                        row.get::<_, String>("readme")?,
+                        (
+                            row.get::<_, Option<i64>>("thumbnail_original_bytes")?,
+                            row.get::<_, Option<i64>>("thumbnail_bytes")?,
+                        ),
+                    ))
+                },
+            )
+            .map_err(db_error)
+        }
+    })
+    .await?;
+    let previous_path = mod_metadata.file_path.clone();
+    mod_metadata.compression = prepared.compression.to_string();
+
+    let mut staged = None;
+    let duplicate = if config.quarantine_uploads {
+        None
+    } else {
+        let checksum = prepared.sha256.clone();
+        with_conn(&db, move |conn| {
+            find_stored_duplicate(conn, &checksum).map_err(db_error)
+        })
+        .await?
+    };
+    match duplicate {
+        Some(existing) => mod_metadata.file_path = existing,
+        None => {
+            let file_path = upload_file_path(&config, &mod_metadata)
+                .map_err(|details| warp::reject::custom(UploadError { details }))?;
+            let file_path = if config.quarantine_uploads {
+                held_file_path(&config, &id, &mod_metadata.compression)
+            } else {
+                let (id, version) = (id.clone(), mod_metadata.version.clone());
+                let replaces_mod_row = !config.keep_versions;
+                with_conn(&db, move |conn| {
+                    unshared_file_path(conn, file_path, &id, &version, replaces_mod_row)
+                        .map_err(db_error)
+                })
+                .await?
+            };
+            let staged_path = format!("{}.upload", file_path);
+            let dir = Path::new(&file_path).parent().unwrap_or(&config.mods_dir);
+            let stored = match fs::create_dir_all(dir) {
+                Ok(()) => {
+                    config
+                        .storage_retry
+                        .run("Storing upload", || {
+                            tokio::fs::write(&staged_path, &prepared.data)
+                        })
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = stored {
+                discard_upload_file(&staged_path).await;
+                return Err(warp::reject::custom(FileError {
+                    details: e.to_string(),
+                }));
+            }
+            staged = Some(staged_path);
+            mod_metadata.file_path = file_path;
+        }
+    }
+
+    let written = with_conn(&db, {
+        let (id, identity, config) = (id.clone(), identity.clone(), config.clone());
+        let (mod_metadata, sha256) = (mod_metadata.clone(), prepared.sha256.clone());
+        let (size_bytes, server_gzipped) = (prepared.data.len() as i64, prepared.server_gzipped);
+        move |conn| {
+            conn.transaction()
+                .and_then(|tx| {
+                    let columns = UploadColumns {
+                        readme: &readme,
+                        sha256: Some(&sha256),
+                        size_bytes: Some(size_bytes),
+                        server_gzipped,
+                        thumbnail_sizes,
+                    };
+                    let replaced = if config.quarantine_uploads {
+                        hold_upload(&tx, &identity, &mod_metadata, &columns)?.1
+                    } else {
+                        upsert_mod_row(&tx, &config, &identity, &mod_metadata, &columns)?;
+                        None
+                    };
+                    clear_partial_upload(&tx, &id)?;
+                    tx.commit()?;
+                    Ok(replaced)
+                })
+                .map_err(db_error)
+        }
+    })
+    .await;
+    let replaced = match written {
+        Ok(replaced) => replaced,
+        Err(rejection) => {
+            if let Some(staged_path) = &staged {
+                discard_upload_file(staged_path).await;
+            }
+            return Err(rejection);
+        }
+    };
+    if let Some(staged_path) = &staged {
+        let moved = config
+            .storage_retry
+            .run("Storing upload", || {
+                tokio::fs::rename(staged_path, &mod_metadata.file_path)
+            })
+            .await;
+        if let Err(e) = moved {
+            discard_upload_file(staged_path).await;
+            return Err(warp::reject::custom(FileError {
+                details: e.to_string(),
+            }));
+        }
+    }
+    discard_upload_file(&staging_path).await;
+    if let Some(replaced) = &replaced {
+        discard_upload_file(replaced).await;
+    }
+    // The listed file keeps serving while its update is held.
+    if !config.quarantine_uploads && previous_path != mod_metadata.file_path {
+        let recorded = with_conn(&db, {
+            let previous_path = previous_path.clone();
+            move |conn| Ok(file_is_recorded(conn, &previous_path))
+        })
+        .await?;
+        if !recorded && let Err(e) = fs::remove_file(&previous_path) {
+            log_line!("Failed to remove replaced file {}: {}", previous_path, e);
+        }
+    }
+
+    cache.invalidate();
+    if !config.quarantine_uploads {
+        events.publish(CatalogEvent::Upserted { id });
+    }
+
+    let reply = warp::reply::json(&json!({
+        "received": received,
+        "total": total,
+        "complete": true,
+    }));
+    Ok(warp::reply::with_status(reply, StatusCode::OK))
+}
+
+/// Forgets the ranges received for `id`'s content-range upload.
+fn clear_partial_upload(conn: &Connection, id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM partial_upload_ranges WHERE mod_id = ?1",
+        params![id],
+    )?;
+    conn.execute("DELETE FROM partial_uploads WHERE mod_id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Parses `bytes <start>-<end>/<total>` with an inclusive end inside the total.
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, total) = range.split_once('/')?;
+    let (start, end) = span.split_once('-')?;
+    let (start, end, total) = (
+        start.trim().parse::<u64>().ok()?,
+        end.trim().parse::<u64>().ok()?,
+        total.trim().parse::<u64>().ok()?,
+    );
+    (start <= end && end < total).then_some((start, end, total))
+}
+
+async fn write_chunk(path: &str, offset: u64, total: u64, data: &[u8]) -> io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .await?;
+    file.set_len(total).await?;
+    file.seek(io::SeekFrom::Start(offset)).await?;
+    file.write_all(data).await?;
+    file.flush().await
+}
+
+/// Counts the distinct bytes received so far, merging overlapping chunks.
+fn received_bytes(conn: &Connection, id: &str) -> Result<u64, rusqlite::Error> {
+    let ranges = conn
+        .prepare("SELECT start, end FROM partial_upload_ranges WHERE mod_id = ?1 ORDER BY start")?
+        .query_map(params![id], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut received = 0;
+    let mut covered_until: Option<u64> = None;
+    for (start, end) in ranges {
+        let start = match covered_until {
+            Some(covered) if covered >= end => continue,
+            Some(covered) => start.max(covered + 1),
+            None => start,
+        };
+        received += end - start + 1;
+        covered_until = Some(end);
+    }
+    Ok(received)
+}
+
+async fn read_part_to_string(part: Part) -> Result<String, warp::Error> {
+    let bytes = read_part_to_bytes(part).await?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+fn is_zstd(data: &[u8]) -> bool {
+    data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd])
+}
+
+fn gzip_bytes(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    io::Write::write_all(&mut encoder, data)?;
+    encoder.finish()
+}
+
+/// The compression of a stored file as recorded in its mod row.
+fn compression_of(data: &[u8]) -> &'static str {
+    if is_gzip(data) {
+        "gzip"
+    } else if is_zstd(data) {
+        "zstd"
+    } else {
+        "none"
+    }
+}
+
+/// The archive entry holding an export's metadata.
+const CATALOG_ENTRY: &str = "catalog.json";
+
+/// Streams a tar archive of the whole catalog: `catalog.json` with every
+/// mod's metadata and thumbnail, then each mod's file under `files/` and its
+/// README under `readmes/`. Kept versions other than the latest are left out.
+async fn handle_export_catalog(
+    admin_key: Option<String>,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<Response, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    let (mods, readmes) = with_conn(&db, move |conn| {
+        load_all_metadata(conn, EXPORT_COLUMNS)
+            .and_then(|mods| {
+                let readmes = conn
+                    .prepare("SELECT id, readme FROM mods WHERE readme != ''")?
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<(String, String)>, _>>()?;
+                Ok((mods, readmes))
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+    })
+    .await?;
+
+    let archive = tokio::task::spawn_blocking(move || write_catalog_archive(&mods, &readmes))
+        .await
+        .map_err(io::Error::other)
+        .and_then(|archive| archive)
+        .map_err(|e| {
+            warp::reject::custom(FileError {
+                details: e.to_string(),
+            })
+        })?;
+
+    let body = ReaderStream::new(tokio::fs::File::from_std(archive));
+    let mut response = Response::new(Body::wrap_stream(body));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-tar"));
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"mods-export.tar\""),
+    );
+    Ok(response)
+}
+
+/// Writes the export archive to an anonymous temporary file, rewound so it can
+/// be streamed from the start.
+fn write_catalog_archive(
+    mods: &[ModMetadata],
+    readmes: &[(String, String)],
+) -> io::Result<fs::File> {
+    let mut builder = tar::Builder::new(tempfile::tempfile()?);
+    let mut append = |name: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(unix_now() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data)
+    };
+    append(CATALOG_ENTRY, &serde_json::to_vec(mods)?)?;
+    for (id, readme) in readmes {
+        append(&format!("readmes/{}.md", id), readme.as_bytes())?;
+    }
+    for mod_metadata in mods.iter().filter(|m| !m.file_path.is_empty()) {
+        builder
+            .append_path_with_name(
+                &mod_metadata.file_path,
+                catalog_file_entry(&mod_metadata.id, &mod_metadata.compression),
+            )
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to add the file of `{}`: {}", mod_metadata.id, e),
+                )
+            })?;
+    }
+
+    let mut archive = builder.into_inner()?;
+    io::Seek::rewind(&mut archive)?;
+    Ok(archive)
+}
+
+fn catalog_file_entry(id: &str, compression: &str) -> String {
+    format!("files/{}.{}", id, file_extension(compression))
+}
+
+/// Restores an archive written by `/admin/export`. Ids that already exist
+/// are skipped unless `overwrite` is set.
+async fn handle_import_catalog(
+    query: ImportQuery,
+    identity: String,
+    body: impl futures::Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    let archive = spool_body(body, &config.mods_dir, config.max_import_bytes).await?;
+    let imported = with_conn(&db, move |conn| {
+        import_catalog(conn, &config, &identity, archive, query.overwrite)
+    })
+    .await?;
+
+    if !imported.imported.is_empty() {
+        cache.invalidate();
+    }
+    for id in &imported.imported {
+        events.publish(CatalogEvent::Upserted { id: id.clone() });
+    }
+    Ok(warp::reply::json(&json!({
+        "imported": imported.imported,
+        "skipped": imported.skipped,
+    })))
+}
+
+/// Writes a request body of at most `max_bytes` to an anonymous temporary
+/// file in `dir`, rewound so it can be read from the start.
+async fn spool_body(
+    mut body: impl futures::Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    dir: &Path,
+    max_bytes: u64,
+) -> Result<fs::File, Rejection> {
+    let file_error = |e: io::Error| {
+        warp::reject::custom(FileError {
+            details: e.to_string(),
+        })
+    };
+    let mut file = tokio::fs::File::from_std(tempfile::tempfile_in(dir).map_err(file_error)?);
+    let mut written = 0u64;
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| {
+            warp::reject::custom(UploadError {
+                details: e.to_string(),
+            })
+        })?;
+        written += chunk.remaining() as u64;
+        if written > max_bytes {
+            return Err(warp::reject::custom(PayloadTooLargeError {
+                details: format!("body is larger than {} bytes", max_bytes),
+            }));
+        }
+        file.write_all_buf(&mut chunk).await.map_err(file_error)?;
+    }
+    file.rewind().await.map_err(file_error)?;
+    Ok(file.into_std().await)
+}
+
+struct ImportedCatalog {
+    imported: Vec<String>,
+    skipped: Vec<String>,
+}
+
+/// Unpacks an export archive and writes every mod in one transaction. Files
+/// are unpacked into a staging directory beside the mods and moved to names
+/// no row uses once every row is written, so a bad archive changes nothing
+/// and replaced files stay intact until the rows stop pointing at them.
+fn import_catalog(
+    conn: &mut Connection,
+    config: &Config,
+    identity: &str,
+    archive: fs::File,
+    overwrite: bool,
+) -> Result<ImportedCatalog, Rejection> {
+    let upload_error = |details: String| warp::reject::custom(UploadError { details });
+    let file_error = |e: io::Error| {
+        warp::reject::custom(FileError {
+            details: e.to_string(),
+        })
+    };
+    let db_error = |e: rusqlite::Error| {
+        warp::reject::custom(DbError {
+            details: e.to_string(),
+        })
+    };
+    let unreadable =
+        |e: io::Error| upload_error(format!("archive is not a readable export: {}", e));
+
+    let staging = tempfile::tempdir_in(&config.mods_dir).map_err(file_error)?;
+    let mut catalog: Option<Vec<ModMetadata>> = None;
+    let mut readmes = HashMap::new();
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries().map_err(unreadable)? {
+        let mut entry = entry.map_err(unreadable)?;
+        let path = entry.path().map_err(unreadable)?.into_owned();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if path == Path::new(CATALOG_ENTRY) {
+            catalog = Some(
+                serde_json::from_reader(&mut entry)
+                    .map_err(|e| upload_error(format!("invalid `{}`: {}", CATALOG_ENTRY, e)))?,
+            );
+        } else if path.parent() == Some(Path::new("files")) && is_safe_path_segment(name) {
+            if entry.size() > config.max_file_bytes {
+                return Err(warp::reject::custom(PayloadTooLargeError {
+                    details: format!(
+                        "`{}` is larger than {} bytes",
+                        path.display(),
+                        config.max_file_bytes
+                    ),
+                }));
+            }
+            entry
+                .unpack(staging.path().join(name))
+                .map_err(unreadable)?;
+        } else if let Some(id) = name.strip_suffix(".md")
+            && path.parent() == Some(Path::new("readmes"))
+        {
+            let mut readme = String::new();
+            entry.read_to_string(&mut readme).map_err(unreadable)?;
+            readmes.insert(id.to_string(), readme);
+        }
+    }
+    let catalog =
+        catalog.ok_or_else(|| upload_error(format!("archive has no `{}`", CATALOG_ENTRY)))?;
+
+    let tx = conn.transaction().map_err(db_error)?;
+    let mut imported = Vec::new();
+    let mut skipped = Vec::new();
+    let mut moves = Vec::new();
+    let mut superseded = Vec::new();
+    for mut mod_metadata in catalog {
+        validate_mod_id(&mod_metadata.id).map_err(warp::reject::custom)?;
+        if imported.contains(&mod_metadata.id) || skipped.contains(&mod_metadata.id) {
+            continue;
+        }
+        let current_path: Option<String> = tx
+            .query_row(
+                "SELECT file_path FROM mods WHERE id = ?1",
+                params![mod_metadata.id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+        if current_path.is_some() && !overwrite {
+            skipped.push(mod_metadata.id);
+            continue;
+        }
+        enforce_field_lengths(config, &mut mod_metadata).map_err(upload_error)?;
+
+        let mut sha256 = None;
+        let mut size_bytes = None;
+        if !mod_metadata.file_path.is_empty() {
+            let entry = catalog_file_entry(&mod_metadata.id, &mod_metadata.compression);
+            let staged = staging
+                .path()
+                .join(Path::new(&entry).file_name().unwrap_or_default());
+            let mut head = Vec::with_capacity(4);
+            fs::File::open(&staged)
+                .and_then(|file| file.take(4).read_to_end(&mut head))
+                .map_err(|_| upload_error(format!("archive has no `{}`", entry)))?;
+            if !is_gzip(&head) && !is_zstd(&head) {
+                return Err(upload_error(format!(
+                    "`{}` is not gzip or zstd compressed",
+                    entry
+                )));
+            }
+            let checksum = sha256_file(&staged.to_string_lossy()).map_err(file_error)?;
+            let size = fs::metadata(&staged).map_err(file_error)?.len();
+            if mod_metadata
+                .sha256
+                .as_ref()
+                .is_some_and(|sha| *sha != checksum)
+            {
+                return Err(upload_error(format!(
+                    "`{}` does not match its recorded checksum",
+                    entry
+                )));
+            }
+            mod_metadata.compression = compression_of(&head).to_string();
+            mod_metadata.file_path =
+                match find_stored_duplicate(&tx, &checksum).map_err(db_error)? {
+                    Some(duplicate) => duplicate,
+                    None => {
+                        let path = upload_file_path(config, &mod_metadata).map_err(upload_error)?;
+                        let path = fresh_file_path(&tx, path);
+                        moves.push((staged, path.clone()));
+                        path
+                    }
+                };
+            sha256 = Some(checksum);
+            size_bytes = Some(size as i64);
+        }
+
+        let readme = readmes.remove(&mod_metadata.id).unwrap_or_default();
+        let (_, is_latest) = upsert_mod_row(
+            &tx,
+            config,
+            identity,
+            &mod_metadata,
+            &UploadColumns {
+                readme: &readme,
+                sha256: sha256.as_deref(),
+                size_bytes,
+                server_gzipped: false,
+                thumbnail_sizes: (None, None),
+            },
+        )
+        .map_err(db_error)?;
+        // The restored row keeps the history it was exported with.
+        if is_latest {
+            tx.execute(
+                "UPDATE mods SET created_at = COALESCE(?2, created_at),
+                    updated_at = COALESCE(?3, updated_at), downloads = ?4
+                WHERE id = ?1",
+                params![
+                    mod_metadata.id,
+                    mod_metadata.created_at,
+                    mod_metadata.updated_at,
+                    mod_metadata.downloads
+                ],
+            )
+            .map_err(db_error)?;
+        }
+        superseded.extend(current_path.filter(|path| *path != mod_metadata.file_path));
+        imported.push(mod_metadata.id);
+    }
+
+    // Every target is a name nothing else uses, so undoing a failed import
+    // only ever removes files it moved there itself.
+    let discard_moved = |moved: &[(PathBuf, String)]| {
+        for (_, target) in moved {
+            let _ = fs::remove_file(target);
+        }
+    };
+    for (index, (staged, target)) in moves.iter().enumerate() {
+        let moved = Path::new(target)
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::rename(staged, target));
+        if let Err(e) = moved {
+            discard_moved(&moves[..index]);
+            return Err(file_error(e));
+        }
+    }
+    if let Err(e) = tx.commit() {
+        discard_moved(&moves);
+        return Err(db_error(e));
+    }
+
+    for path in superseded {
+        if !file_is_recorded(conn, &path)
+            && let Err(e) = fs::remove_file(&path)
+        {
+            log_line!("Failed to remove replaced file {}: {}", path, e);
+        }
+    }
+    Ok(ImportedCatalog { imported, skipped })
+}
+
+/// The file extension a stored file gets for its compression. Files the
+/// server couldn't identify keep the historical `.gz`.
+fn file_extension(compression: &str) -> &'static str {
+    match compression {
+        "zstd" => "zst",
+        _ => "gz",
+    }
+}
+
+/// Puts an upload into `MOD_DB_STORAGE_FORMAT`, returning the bytes to store
+/// and whether the server compressed a raw file. Gzip uploads are recompressed
+/// for zstd storage; files already in the storage format are kept as sent,
+/// and raw files are only compressed with `MOD_DB_AUTO_GZIP`.
+fn encode_for_storage(
+    data: Vec<u8>,
+    format: StorageFormat,
+    compress_raw: bool,
+) -> io::Result<(Vec<u8>, bool)> {
+    match format {
+        StorageFormat::Gzip if !is_gzip(&data) && !is_zstd(&data) && compress_raw => {
+            Ok((gzip_bytes(&data)?, true))
+        }
+        StorageFormat::Zstd if is_gzip(&data) => {
+            let mut encoded = Vec::new();
+            zstd::stream::copy_encode(GzDecoder::new(data.as_slice()), &mut encoded, 0)?;
+            Ok((encoded, false))
+        }
+        StorageFormat::Zstd if !is_zstd(&data) && compress_raw => {
+            Ok((zstd::encode_all(data.as_slice(), 0)?, true))
+        }
+        _ => Ok((data, false)),
+    }
+}
+
+/// A mod file ready to be stored.
+struct PreparedFile {
+    data: Vec<u8>,
+    manifest: Option<ModManifest>,
+    sha256: String,
+    server_gzipped: bool,
+    compression: &'static str,
+}
+
+/// Compresses the file for storage, enforces the configured required archive
+/// entry, reads the manifest, and hashes the bytes as stored. Files must be
+/// gzip or zstd unless `MOD_DB_AUTO_GZIP` compresses them, so a zip or other
+/// raw upload is refused here rather than failing to decompress on download.
+async fn prepare_mod_file(data: Vec<u8>, config: &Config) -> Result<PreparedFile, String> {
+    if !config.auto_gzip && !is_gzip(&data) && !is_zstd(&data) {
+        return Err("file is not gzip or zstd compressed".to_string());
+    }
+    let required = config.required_archive_entry.clone();
+    let format = config.storage_format;
+    let auto_gzip = config.auto_gzip;
+    let (data, server_gzipped, inspected, sha256) = tokio::task::spawn_blocking(move || {
+        let (data, server_gzipped) = encode_for_storage(data, format, auto_gzip)?;
+        let inspected = inspect_archive(&data, required.as_deref());
+        let checksum = to_hex(&Sha256::digest(&data));
+        Ok::<_, io::Error>((data, server_gzipped, inspected, checksum))
+    })
+    .await
+    .map_err(io::Error::other)
+    .and_then(|prepared| prepared)
+    .map_err(|e| e.to_string())?;
+
+    let manifest = match (inspected, &config.required_archive_entry) {
+        (Ok(contents), Some(entry)) if !contents.has_required_entry => {
+            return Err(format!("archive is missing the required entry `{}`", entry));
+        }
+        (Ok(contents), _) => contents.manifest,
+        (Err(e), Some(_)) => {
+            return Err(format!("file is not a readable compressed tarball: {}", e));
+        }
+        // Without a required entry, files that are not tarballs are fine.
+        (Err(_), None) => None,
+    };
+    let compression = compression_of(&data);
+    Ok(PreparedFile {
+        data,
+        manifest,
+        sha256,
+        server_gzipped,
+        compression,
+    })
+}
+
+/// Scans a gzip or zstd tarball for the configured required entry and the top-level
+/// `mod.json`, stopping as soon as both are found. Entry paths are compared
+/// without a leading `./`.
+fn inspect_archive(data: &[u8], required: Option<&str>) -> io::Result<ArchiveContents> {
+    let mut contents = ArchiveContents {
+        has_required_entry: false,
+        manifest: None,
+    };
+    let mut manifest_seen = false;
+    let decoder: Box<dyn Read + '_> = if is_zstd(data) {
+        Box::new(zstd::stream::Decoder::new(data)?)
+    } else {
+        Box::new(GzDecoder::new(data))
+    };
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let entry_path = entry_path.strip_prefix("./").unwrap_or(&entry_path);
+
+        if required.is_some_and(|required| entry_path == Path::new(required)) {
+            contents.has_required_entry = true;
+        }
+        if !manifest_seen && entry_path == Path::new(MANIFEST_ENTRY) {
+            manifest_seen = true;
+            let mut raw = Vec::new();
+            entry.take(MAX_MANIFEST_BYTES).read_to_end(&mut raw)?;
+            match serde_json::from_slice(&raw) {
+                Ok(manifest) => contents.manifest = Some(manifest),
+                Err(e) => log_line!("Ignoring unreadable {}: {}", MANIFEST_ENTRY, e),
+            }
+        }
+
+        if manifest_seen && (required.is_none() || contents.has_required_entry) {
+            break;
+        }
+    }
+    Ok(contents)
+}
+
+async fn read_part_to_bytes(mut part: Part) -> Result<Vec<u8>, warp::Error> {
+    let mut bytes = Vec::new();
+    while let Some(data) = part.data().await {
+        let data = data?;
+        bytes.extend_from_slice(data.chunk());
+    }
+    Ok(bytes)
+}
+
+/// Serves a stored file. Every file lives in `MOD_DB_MODS_DIR`, so there is no
+/// backend to coalesce concurrent downloads for: each streams from its own
+/// file handle, and the source is only contacted by a refetch, which
+/// `SingleFlight` coalesces.
+async fn handle_download(
+    id: String,
+    query: DownloadQuery,
+    headers: DownloadHeaders,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+) -> Result<Response, Rejection> {
+    let verify = query.verify.unwrap_or(false);
+    let (file_path, checksum, is_latest) = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            let (latest_version, latest_file_path, latest_sha256): (
+                String,
+                String,
+                Option<String>,
+            ) = conn
+                .query_row(
+                    "SELECT version, file_path, sha256 FROM mods WHERE id = ?1",
+                    params![id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?
+                .ok_or_else(|| mod_not_found(conn, &id))?;
+            // Versions kept before their checksums were recorded fall back to a
+            // weak ETag.
+            Ok(match query.version.filter(|version| !version.is_empty()) {
+                Some(version) if version != latest_version => {
+                    let (file_path, checksum) = conn
+                        .query_row(
+                            "SELECT file_path, sha256 FROM mod_versions
+                            WHERE id = ?1 AND version = ?2",
+                            params![id, version],
+                            |row| Ok((row.get(0)?, row.get(1)?)),
+                        )
+                        .optional()
+                        .map_err(|e| {
+                            warp::reject::custom(DbError {
+                                details: e.to_string(),
+                            })
+                        })?
+                        .ok_or_else(|| {
+                            warp::reject::custom(NotFoundError {
+                                details: format!("mod `{}` has no version `{}`", id, version),
+                            })
+                        })?;
+                    (file_path, checksum, false)
+                }
+                _ => (latest_file_path, latest_sha256, true),
+            })
+        }
+    })
+    .await?;
+
+    if let Some(base) = &config.download_redirect_base {
+        let location = download_redirect_url(&config, base, &file_path)
+            .map_err(|details| warp::reject::custom(FileError { details }))?;
+        record_download(&db, &cache, &id).await;
+        return Ok(warp::redirect::found(location).into_response());
+    }
+
+    if verify {
+        let expected = match &checksum {
+            Some(checksum) => checksum.clone(),
+            None if is_latest => compute_checksum(&db, &id, file_path.clone()).await?,
+            None => {
+                return Err(warp::reject::custom(QueryError {
+                    details: "this version was stored before checksums were recorded".to_string(),
+                }));
+            }
+        };
+        let hashed_path = file_path.clone();
+        let actual = tokio::task::spawn_blocking(move || sha256_file(&hashed_path))
+            .await
+            .map_err(io::Error::other)
+            .and_then(|hashed| hashed)
+            .map_err(|e| {
+                warp::reject::custom(FileError {
+                    details: e.to_string(),
+                })
+            })?;
+        if !actual.eq_ignore_ascii_case(&expected) {
+            log_line!(
+                "Checksum mismatch for {}: stored {}, file has {}",
+                id,
+                expected,
+                actual
+            );
+            return Err(warp::reject::custom(FileError {
+                details: format!("stored file of `{}` does not match its checksum", id),
+            }));
+        }
+    }
+
+    let file_metadata = tokio::fs::metadata(&file_path).await.map_err(|e| {
+        warp::reject::custom(FileError {
+            details: e.to_string(),
+        })
+    })?;
+    let etag = match &checksum {
+        Some(checksum) => strong_etag(checksum),
+        None => weak_etag(&file_metadata),
+    };
+    let etag_header = HeaderValue::from_str(&etag).map_err(|e| {
+        warp::reject::custom(FileError {
+            details: e.to_string(),
+        })
+    })?;
+    if headers
+        .if_none_match
+        .is_some_and(|header| etag_matches(&header, &etag))
+    {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        response.headers_mut().insert(ETAG, etag_header);
+        return Ok(response);
+    }
+
+    let len = file_metadata.len();
+    // A resuming client sends the ETag it started with in `If-Range`; if the
+    // file has changed since, it gets the whole new file instead of a slice.
+    // Weak tags and dates never match.
+    let range = headers.range.filter(|_| {
+        headers
+            .if_range
+            .as_deref()
+            .is_none_or(|tag| !etag.starts_with("W/") && tag.trim() == etag)
+    });
+    let ranges = match range
+        .as_deref()
+        .map(|header| parse_range_header(header, len))
+    {
+        None | Some(RangeRequest::Full) => Vec::new(),
+        Some(RangeRequest::Ranges(ranges)) => ranges,
+        Some(RangeRequest::Unsatisfiable) => {
+            let mut response = warp::reply::json(&json!({
+                "code": StatusCode::RANGE_NOT_SATISFIABLE.as_u16(),
+                "message": format!("Range Not Satisfiable: the file is {} bytes", len),
+            }))
+            .into_response();
+            *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            let headers = response.headers_mut();
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len)).expect("content range is ASCII"),
+            );
+            headers.insert(ETAG, etag_header);
+            return Ok(response);
+        }
+    };
+
+    // The file is streamed from disk rather than read into memory, so
+    // concurrent downloads of large mods don't each hold a full copy.
+    let mut file = config
+        .storage_retry
+        .run("Opening download", || tokio::fs::File::open(&file_path))
+        .await
+        .map_err(|e| {
+            warp::reject::custom(FileError {
+                details: e.to_string(),
+            })
+        })?;
+    // Download managers fetch a file in many range requests, so only the one
+    // that starts at the beginning counts as a download.
+    if ranges.first().is_none_or(|range| range.start == 0) {
+        record_download(&db, &cache, &id).await;
+    }
+    let chunk_size = match config.download_bytes_per_sec {
+        Some(rate) => DOWNLOAD_CHUNK_BYTES.min(rate as usize),
+        None => DOWNLOAD_CHUNK_BYTES,
+    };
+
+    let boundary = to_hex(&rand::random::<[u8; 16]>());
+    let (status, content_length, body) = match ranges.as_slice() {
+        [] => (
+            StatusCode::OK,
+            len,
+            ReaderStream::with_capacity(file, chunk_size).boxed(),
+        ),
+        [range] => {
+            file.seek(io::SeekFrom::Start(range.start))
+                .await
+                .map_err(|e| {
+                    warp::reject::custom(FileError {
+                        details: e.to_string(),
+                    })
+                })?;
+            (
+                StatusCode::PARTIAL_CONTENT,
+                range.len(),
+                ReaderStream::with_capacity(file.take(range.len()), chunk_size).boxed(),
+            )
+        }
+        ranges => {
+            drop(file);
+            let (content_length, body) =
+                multipart_byteranges(file_path, ranges.to_vec(), len, &boundary, chunk_size);
+            (StatusCode::PARTIAL_CONTENT, content_length, body)
+        }
+    };
+    let body = match config.download_bytes_per_sec {
+        Some(rate) => Body::wrap_stream(throttle(body, rate)),
+        None => Body::wrap_stream(body),
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(content_length));
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(ETAG, etag_header);
+    match ranges.as_slice() {
+        [] => {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+        }
+        [range] => {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&range.content_range(len)).expect("content range is ASCII"),
+            );
+        }
+        _ => {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))
+                    .expect("boundary is ASCII"),
+            );
+        }
+    }
+    Ok(response)
+}
+
+/// One satisfiable range of a download, with an inclusive `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+enum RangeRequest {
+    /// The header uses another unit or asks for too many ranges, and is ignored.
+    Full,
+    Ranges(Vec<ByteRange>),
+    /// The header is malformed or no range lies within the file.
+    Unsatisfiable,
+}
+
+/// Parses a `Range: bytes=...` header against a file of `len` bytes. The
+/// ranges come back sorted with overlapping and adjacent ones merged, so each
+/// byte is sent once. A header in another unit, or asking for more than
+/// `MAX_BYTE_RANGES` ranges, is ignored and the whole file is sent.
+fn parse_range_header(header: &str, len: u64) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let specs = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect::<Vec<_>>();
+    if specs.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    if specs.len() > MAX_BYTE_RANGES {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeRequest::Unsatisfiable;
+        };
+        let range = match (start.trim(), end.trim()) {
+            ("", suffix) => {
+                let Ok(suffix) = suffix.parse::<u64>() else {
+                    return RangeRequest::Unsatisfiable;
+                };
+                (suffix > 0 && len > 0).then(|| ByteRange {
+                    start: len.saturating_sub(suffix),
+                    end: len - 1,
+                })
+            }
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeRequest::Unsatisfiable;
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Unsatisfiable,
+                    },
+                };
+                (start < len).then(|| ByteRange {
+                    start,
+                    end: end.min(len - 1),
+                })
+            }
+        };
+        ranges.extend(range);
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    RangeRequest::Ranges(merged)
+}
+
+/// Builds a `multipart/byteranges` body for `ranges` of the file at `path`,
+/// returning its exact length alongside the stream. Each part reopens the file
+/// when it is reached, so only one handle is open at a time.
+fn multipart_byteranges(
+    path: String,
+    ranges: Vec<ByteRange>,
+    total: u64,
+    boundary: &str,
+    chunk_size: usize,
+) -> (
+    u64,
+    futures::stream::BoxStream<'static, Result<Bytes, io::Error>>,
+) {
+    let part_headers = ranges
+        .iter()
+        .map(|range| {
+            Bytes::from(format!(
+                "\r\n--{}\r\nContent-Type: application/octet-stream\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                range.content_range(total)
+            ))
+        })
+        .collect::<Vec<_>>();
+    let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+    let content_length = part_headers
+        .iter()
+        .map(|header| header.len() as u64)
+        .chain(ranges.iter().map(ByteRange::len))
+        .sum::<u64>()
+        + closing.len() as u64;
+
+    let parts =
+        futures::stream::iter(ranges.into_iter().zip(part_headers)).then(move |(range, header)| {
+            let path = path.clone();
+            async move {
+                let mut file = tokio::fs::File::open(&path).await?;
+                file.seek(io::SeekFrom::Start(range.start)).await?;
+                let data = ReaderStream::with_capacity(file.take(range.len()), chunk_size);
+                Ok::<_, io::Error>(futures::stream::once(async move { Ok(header) }).chain(data))
+            }
+        });
+    let body = parts
+        .try_flatten()
+        .chain(futures::stream::once(async move { Ok(closing) }))
+        .boxed();
+    (content_length, body)
+}
+
+/// Paces a byte stream to `bytes_per_sec`: each chunk is held back until the
+/// bytes sent so far, itself included, fit the rate since the first chunk.
+fn throttle<S>(
+    chunks: S,
+    bytes_per_sec: u64,
+) -> impl futures::Stream<Item = Result<Bytes, io::Error>> + Send
+where
+    S: futures::Stream<Item = Result<Bytes, io::Error>> + Send + Unpin,
+{
+    let start = tokio::time::Instant::now();
+    futures::stream::unfold((chunks, 0u64), move |(mut chunks, sent)| async move {
+        let chunk = chunks.next().await?;
+        let sent = sent + chunk.as_ref().map_or(0, |chunk| chunk.len() as u64);
+        let due = Duration::from_secs_f64(sent as f64 / bytes_per_sec as f64);
+        tokio::time::sleep_until(start + due).await;
+        Some((chunk, (chunks, sent)))
+    })
+}
+
+/// Derives a weak ETag from a file's size and modification time, which is cheap
+/// to compute for large files that would be costly to hash on every request.
+fn weak_etag(metadata: &std::fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_nanos())
+        .unwrap_or_default();
+    format!("W/\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+/// The strong ETag of a stored file, from its SHA-256.
+fn strong_etag(checksum: &str) -> String {
+    format!("\"{}\"", checksum)
+}
+
+/// Strong comparison against an `If-Match` list, as used for writes: weak tags
+/// never match, and `*` matches any stored file.
+fn if_match_allows(if_match: &str, etag: Option<&str>) -> bool {
+    let Some(etag) = etag else {
+        return false;
+    };
+    if_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || (!candidate.starts_with("W/") && candidate == etag))
+}
+
+/// Weak comparison against an `If-None-Match` list, as used for GET requests.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+}
+
+/// Logs a served download for the stats endpoints. A failure here is logged
+/// rather than failing a download that already succeeded.
+async fn record_download(db: &DbConnection, cache: &MetadataCache, id: &str) {
+    let (db, mod_id) = (db.clone(), id.to_string());
+    let recorded = tokio::task::spawn_blocking(move || {
+        db.get().map_err(|e| e.to_string()).and_then(|mut conn| {
+            conn.transaction()
+                .and_then(|tx| {
+                    tx.execute(
+                        "INSERT INTO download_log (mod_id, downloaded_at) VALUES (?1, ?2)",
+                        params![mod_id, unix_now()],
+                    )?;
+                    tx.execute(
+                        "UPDATE mods SET downloads = downloads + 1 WHERE id = ?1",
+                        params![mod_id],
+                    )?;
+                    tx.commit()
+                })
+                .map_err(|e| e.to_string())
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|recorded| recorded);
+    match recorded {
+        // The cached listing carries the count, so it is rebuilt.
+        Ok(()) => cache.invalidate(),
+        Err(e) => log_line!("Failed to record download of {}: {}", id, e),
+    }
+}
+
+/// Characters a URL path segment can carry as they are.
+const PATH_SEGMENT: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Builds the CDN URL for a stored file from its path under `MOD_DB_MODS_DIR`,
+/// percent-encoded segment by segment. With a secret configured, the URL
+/// carries an expiry and an HMAC-SHA256 signature of `<path>:<expires>` that the
+/// CDN can verify before serving.
+fn download_redirect_url(config: &Config, base: &str, file_path: &str) -> Result<Uri, String> {
+    let invalid = || format!("invalid stored file path `{}`", file_path);
+    let relative = Path::new(file_path)
+        .strip_prefix(&config.mods_dir)
+        .map_err(|_| invalid())?;
+    let mut path = String::new();
+    for component in relative.components() {
+        let segment = match component {
+            Component::Normal(segment) => segment.to_str().ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        path.push('/');
+        path.extend(percent_encoding::utf8_percent_encode(segment, PATH_SEGMENT));
+    }
+    if path.is_empty() {
+        return Err(invalid());
+    }
+
+    let url = match &config.download_redirect_secret {
+        Some(secret) => {
+            let expires = (SystemTime::now() + config.download_redirect_ttl)
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?
+                .as_secs();
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| e.to_string())?;
+            mac.update(format!("{}:{}", path, expires).as_bytes());
+            let signature = to_hex(&mac.finalize().into_bytes());
+            format!(
+                "{}{}?expires={}&signature={}",
+                base, path, expires, signature
+            )
+        }
+        None => format!("{}{}", base, path),
+    };
+
+    Uri::try_from(url).map_err(|e| e.to_string())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+async fn handle_readme(
+    id: String,
+    query: ReadmeQuery,
+    db: DbConnection,
+) -> Result<impl Reply, Rejection> {
+    let version = query.version.filter(|version| !version.is_empty());
+    let readme = with_conn(&db, {
+        let (id, version) = (id.clone(), version.clone());
+        move |conn| {
+            let readme: Option<String> = conn
+                .query_row(
+                    "SELECT CASE WHEN ?2 IS NULL OR version = ?2 THEN readme
+                        ELSE (SELECT readme FROM mod_versions WHERE mod_versions.id = mods.id AND mod_versions.version = ?2)
+                    END FROM mods WHERE id = ?1",
+                    params![id, version],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?
+                .ok_or_else(|| mod_not_found(conn, &id))?;
+            Ok(readme)
+        }
+    })
+    .await?;
+    // Versions kept before their metadata was recorded have no readme of
+    // their own.
+    let readme = readme.ok_or_else(|| {
+        warp::reject::custom(NotFoundError {
+            details: format!(
+                "mod `{}` has no readme stored for version `{}`",
+                id,
+                version.unwrap_or_default()
+            ),
+        })
+    })?;
+
+    match query.format.as_deref() {
+        None | Some("markdown") => Ok(warp::reply::with_header(
+            readme,
+            "Content-Type",
+            "text/markdown; charset=utf-8",
+        )),
+        Some("html") => Ok(warp::reply::with_header(
+            render_readme_html(&readme),
+            "Content-Type",
+            "text/html; charset=utf-8",
+        )),
+        Some(other) => Err(warp::reject::custom(QueryError {
+            details: format!("unsupported readme format `{}`", other),
+        })),
+    }
+}
+
+/// Serves a mod's thumbnail image. Thumbnails are stored without a MIME type,
+/// so it is sniffed from the image's first bytes once and kept.
+async fn handle_thumbnail(id: String, db: DbConnection) -> Result<impl Reply, Rejection> {
+    let (thumbnail, mime): (String, Option<String>) = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            conn.query_row(
+                "SELECT thumbnail, thumbnail_mime FROM mods WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?
+            .ok_or_else(|| mod_not_found(conn, &id))
+        }
+    })
+    .await?;
+    if thumbnail.is_empty() {
+        return Err(warp::reject::custom(NotFoundError {
+            details: format!("mod `{}` has no thumbnail", id),
+        }));
+    }
+    let image = BASE64_STANDARD.decode(&thumbnail).map_err(|e| {
+        warp::reject::custom(FileError {
+            details: format!("stored thumbnail is not valid base64: {}", e),
+        })
+    })?;
+
+    let mime = match mime {
+        Some(mime) => mime,
+        None => {
+            let mime = sniff_image_mime(&image).to_string();
+            with_conn(&db, {
+                let (id, mime) = (id.clone(), mime.clone());
+                move |conn| {
+                    if let Err(e) = conn.execute(
+                        "UPDATE mods SET thumbnail_mime = ?1 WHERE id = ?2",
+                        params![mime, id],
+                    ) {
+                        log_line!("Failed to store thumbnail type of {}: {}", id, e);
+                    }
+                    Ok(())
+                }
+            })
+            .await?;
+            mime
+        }
+    };
+
+    let mut response = Response::new(Body::from(image));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&mime)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    Ok(response)
+}
+
+/// Where an upload's file is stored: one file per id, or one per version when
+/// versions are kept.
+fn upload_file_path(config: &Config, metadata: &ModMetadata) -> Result<String, String> {
+    let extension = file_extension(&metadata.compression);
+    if !config.keep_versions {
+        return Ok(config.mod_path(format!("{}.{}", metadata.id, extension)));
+    }
+    if !is_safe_path_segment(&metadata.version) {
+        return Err("version may only contain letters, digits, `.`, `-`, `_`, and `+` when versions are kept".to_string());
+    }
+    Ok(
+        config
+            .mod_path(Path::new(&metadata.id).join(format!("{}.{}", metadata.version, extension))),
+    )
+}
+
+/// Imports every bundle in `MOD_DB_SEED_DIR` whose id isn't stored yet. A
+/// bundle is an `<id>.tar.gz` (or `.tgz`) whose `mod.json` gives at least the
+/// title and version, optionally next to an `<id>.png`, `.jpg`, `.webp`, or
+/// `.gif` thumbnail. Ids that were deleted are not brought back. `run` calls
+/// this before serving requests.
+pub async fn seed_from_dir(db: &DbConnection, config: &Config, dir: &Path) -> io::Result<()> {
+    let mut bundles = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| seed_bundle_id(&path).map(|id| (id, path)))
+        .collect::<Vec<_>>();
+    bundles.sort();
+
+    let mut imported = 0;
+    for (id, path) in bundles {
+        match import_seed_bundle(db, config, &id, &path).await {
+            Ok(true) => {
+                log_line!("Seeded mod {} from {}", id, path.display());
+                imported += 1;
+            }
+            Ok(false) => {}
+            Err(e) => log_line!("Skipping seed bundle {}: {}", path.display(), e),
+        }
+    }
+    log_line!("Seeded {} mod(s) from {}", imported, dir.display());
+    Ok(())
+}
+
+fn seed_bundle_id(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let id = name
+        .strip_suffix(".tar.gz")
+        .or_else(|| name.strip_suffix(".tgz"))?;
+    validate_mod_id(id).ok()?;
+    Some(id.to_string())
+}
+
+/// Imports one seed bundle, returning `false` when the id is already known.
+async fn import_seed_bundle(
+    db: &DbConnection,
+    config: &Config,
+    id: &str,
+    path: &Path,
+) -> Result<bool, String> {
+    let known: bool = db
+        .get()
+        .map_err(|e| e.to_string())?
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)
+                OR EXISTS(SELECT 1 FROM deleted_mods WHERE id = ?1)",
+            params![id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if known {
+        return Ok(false);
+    }
+
+    let data = tokio::fs::read(path).await.map_err(|e| e.to_string())?;
+    let prepared = prepare_mod_file(data, config).await?;
+    let manifest = prepared
+        .manifest
+        .ok_or_else(|| format!("bundle has no `{}`", MANIFEST_ENTRY))?;
+    let mut thumbnail = String::new();
+    let mut thumbnail_bytes = None;
+    for extension in ["png", "jpg", "jpeg", "webp", "gif"] {
+        if let Ok(image) =
+            tokio::fs::read(path.with_file_name(format!("{}.{}", id, extension))).await
+        {
+            thumbnail_bytes = Some(image.len() as i64);
+            thumbnail = BASE64_STANDARD.encode(image);
+            break;
+        }
+    }
+    let mut metadata = ModMetadata {
+        id: id.to_string(),
+        title: manifest.title.unwrap_or_default(),
+        version: manifest.version.unwrap_or_default(),
+        thumbnail,
+        file_path: String::new(),
+        description: manifest.description.unwrap_or_default(),
+        author: manifest.author.unwrap_or_default(),
+        tags: Vec::new(),
+        min_game_version: None,
+        source_url: None,
+        size_bytes: None,
+        created_at: None,
+        updated_at: None,
+        sha256: None,
+        compression: prepared.compression.to_string(),
+        downloads: 0,
+    };
+    if metadata.title.trim().is_empty() || metadata.version.trim().is_empty() {
+        return Err(format!(
+            "`{}` must give a title and version",
+            MANIFEST_ENTRY
+        ));
+    }
+    enforce_field_lengths(config, &mut metadata)?;
+    metadata.file_path = upload_file_path(config, &metadata)?;
+
+    if let Some(dir) = Path::new(&metadata.file_path).parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    tokio::fs::write(&metadata.file_path, &prepared.data)
+        .await
+        .map_err(|e| e.to_string())?;
+    let written = db.get().map_err(|e| e.to_string()).and_then(|mut conn| {
+        write_upload_metadata(
+            &mut conn,
+            config,
+            "seed",
+            &metadata,
+            &UploadColumns {
+                readme: "",
+                sha256: Some(&prepared.sha256),
+                size_bytes: Some(prepared.data.len() as i64),
+                server_gzipped: prepared.server_gzipped,
+                thumbnail_sizes: (thumbnail_bytes, thumbnail_bytes),
+            },
+        )
+        .map_err(|e| e.to_string())
+    });
+    if let Err(e) = written {
+        discard_upload_file(&metadata.file_path).await;
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// The columns of an upload's mod row that aren't part of `ModMetadata`.
+struct UploadColumns<'a> {
+    readme: &'a str,
+    sha256: Option<&'a str>,
+    size_bytes: Option<i64>,
+    server_gzipped: bool,
+    thumbnail_sizes: (Option<i64>, Option<i64>),
+}
+
+/// Writes an upload's metadata in one transaction and returns whether the mod
+/// already existed and whether this upload is now its latest version.
+fn write_upload_metadata(
+    conn: &mut Connection,
+    config: &Config,
+    identity: &str,
+    mod_metadata: &ModMetadata,
+    columns: &UploadColumns,
+) -> Result<(bool, bool), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let written = upsert_mod_row(&tx, config, identity, mod_metadata, columns)?;
+    tx.commit()?;
+    Ok(written)
+}
+
+/// Writes the rows for one upload, returning whether the mod already existed
+/// and whether this is its latest version. Callers own the transaction.
+fn upsert_mod_row(
+    tx: &Connection,
+    config: &Config,
+    identity: &str,
+    mod_metadata: &ModMetadata,
+    columns: &UploadColumns,
+) -> Result<(bool, bool), rusqlite::Error> {
+    let current_version: Option<String> = tx
+        .query_row(
+            "SELECT version FROM mods WHERE id = ?1",
+            params![mod_metadata.id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let exists = current_version.is_some();
+    // With versions kept, uploading an older version only stores it; the mod
+    // row keeps describing the latest one.
+    let is_latest = !config.keep_versions
+        || current_version
+            .as_deref()
+            .is_none_or(|current| !is_older_version(&mod_metadata.version, current));
+
+    if config.keep_versions {
+        let tags = (!mod_metadata.tags.is_empty()).then(|| mod_metadata.tags.join(","));
+        tx.execute(
+            "INSERT OR REPLACE INTO mod_versions (id, version, file_path, uploaded_at, sha256, title, description, readme, author, tags, min_game_version, source_url, thumbnail, size_bytes, compression)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                mod_metadata.id,
+                mod_metadata.version,
+                mod_metadata.file_path,
+                unix_now(),
+                columns.sha256,
+                mod_metadata.title,
+                mod_metadata.description,
+                columns.readme,
+                mod_metadata.author,
+                tags,
+                mod_metadata.min_game_version,
+                mod_metadata.source_url,
+                mod_metadata.thumbnail,
+                columns.size_bytes,
+                mod_metadata.compression
+            ],
+        )?;
+    }
+
+    if exists && is_latest {
+        tx.execute(
+            "UPDATE mods SET title = ?1, version = ?2, thumbnail = ?3, file_path = ?4, readme = ?5, title_normalized = ?6, description = ?7, author = ?8, min_game_version = ?9, sha256 = ?10, size_bytes = ?11, thumbnail_mime = NULL, server_gzipped = ?12, source_url = ?13, updated_at = ?14, thumbnail_original_bytes = ?15, thumbnail_bytes = ?16, compression = ?17 WHERE id = ?18",
+            params![
+                mod_metadata.title,
+                mod_metadata.version,
+                mod_metadata.thumbnail,
+                mod_metadata.file_path,
+                columns.readme,
+                fold_for_search(&mod_metadata.title),
+                mod_metadata.description,
+                mod_metadata.author,
+                mod_metadata.min_game_version,
+                columns.sha256,
+                columns.size_bytes,
+                columns.server_gzipped,
+                mod_metadata.source_url,
+                unix_now(),
+                columns.thumbnail_sizes.0,
+                columns.thumbnail_sizes.1,
+                mod_metadata.compression,
+                mod_metadata.id
+            ],
+        )?;
+    } else if !exists {
+        tx.execute(
+            "INSERT INTO mods (id, title, version, thumbnail, file_path, readme, title_normalized, description, author, min_game_version, sha256, size_bytes, server_gzipped, source_url, created_at, updated_at, thumbnail_original_bytes, thumbnail_bytes, compression) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?15, ?16, ?17, ?18)",
+            params![
+                mod_metadata.id,
+                mod_metadata.title,
+                mod_metadata.version,
+                mod_metadata.thumbnail,
+                mod_metadata.file_path,
+                columns.readme,
+                fold_for_search(&mod_metadata.title),
+                mod_metadata.description,
+                mod_metadata.author,
+                mod_metadata.min_game_version,
+                columns.sha256,
+                columns.size_bytes,
+                columns.server_gzipped,
+                mod_metadata.source_url,
+                unix_now(),
+                columns.thumbnail_sizes.0,
+                columns.thumbnail_sizes.1,
+                mod_metadata.compression
+            ],
+        )?;
+    }
+    if is_latest {
+        replace_tags(tx, &mod_metadata.id, &mod_metadata.tags)?;
+        sync_search_index(tx, &mod_metadata.id)?;
+    }
+    tx.execute(
+        "DELETE FROM deleted_mods WHERE id = ?1",
+        params![mod_metadata.id],
+    )?;
+    let action = if exists { "update" } else { "create" };
+    record_change(tx, &mod_metadata.id, action)?;
+    record_audit(
+        tx,
+        config,
+        identity,
+        action,
+        &mod_metadata.id,
+        Some(&mod_metadata.version),
+    )?;
+    Ok((exists, is_latest))
+}
+
+/// Where a held upload's file waits for review, apart from the mod's listed
+/// file. Ids have no `.`, so this can't be another mod's file.
+fn held_file_path(config: &Config, id: &str, compression: &str) -> String {
+    config.mod_path(format!("{}.pending.{}", id, file_extension(compression)))
+}
+
+/// Queues an upload for review in place of any revision already held for
+/// the mod, leaving its listed row alone. Returns whether the mod is listed
+/// and the file of the revision this one replaces.
+fn hold_upload(
+    tx: &Connection,
+    identity: &str,
+    mod_metadata: &ModMetadata,
+    columns: &UploadColumns,
+) -> Result<(bool, Option<String>), rusqlite::Error> {
+    let listed = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM mods WHERE id = ?1)",
+        params![mod_metadata.id],
+        |row| row.get(0),
+    )?;
+    let replaced = tx
+        .query_row(
+            "SELECT file_path FROM pending_uploads WHERE id = ?1",
+            params![mod_metadata.id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let tags = (!mod_metadata.tags.is_empty()).then(|| mod_metadata.tags.join(","));
+    tx.execute(
+        "INSERT OR REPLACE INTO pending_uploads (id, title, version, thumbnail, file_path, readme, description, author, tags, min_game_version, source_url, sha256, size_bytes, server_gzipped, compression, thumbnail_original_bytes, thumbnail_bytes, identity, uploaded_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+        params![
+            mod_metadata.id,
+            mod_metadata.title,
+            mod_metadata.version,
+            mod_metadata.thumbnail,
+            mod_metadata.file_path,
+            columns.readme,
+            mod_metadata.description,
+            mod_metadata.author,
+            tags,
+            mod_metadata.min_game_version,
+            mod_metadata.source_url,
+            columns.sha256,
+            columns.size_bytes,
+            columns.server_gzipped,
+            mod_metadata.compression,
+            columns.thumbnail_sizes.0,
+            columns.thumbnail_sizes.1,
+            identity,
+            unix_now()
+        ],
+    )?;
+    Ok((
+        listed,
+        replaced.filter(|path| *path != mod_metadata.file_path),
+    ))
+}
+
+/// Removes a file written for an upload that then failed.
+async fn discard_upload_file(path: &str) {
+    if let Err(e) = tokio::fs::remove_file(path).await
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        log_line!("Failed to remove failed upload {}: {}", path, e);
+    }
+}
+
+/// Recompresses a PNG thumbnail losslessly for `MOD_DB_OPTIMIZE_THUMBNAILS`.
+/// Anything that isn't a PNG, fails to optimize, or doesn't get smaller is
+/// kept as uploaded.
+fn optimize_thumbnail(data: Vec<u8>) -> Vec<u8> {
+    if sniff_image_mime(&data) != "image/png" {
+        return data;
+    }
+    let mut options = oxipng::Options::from_preset(2);
+    options.timeout = Some(THUMBNAIL_OPTIMIZE_TIMEOUT);
+    match oxipng::optimize_from_memory(&data, &options) {
+        Ok(optimized) if optimized.len() < data.len() => {
+            log_line!(
+                "Optimized thumbnail from {} to {} bytes",
+                data.len(),
+                optimized.len()
+            );
+            optimized
+        }
+        Ok(_) => data,
+        Err(e) => {
+            log_line!("Failed to optimize thumbnail: {}", e);
+            data
+        }
+    }
+}
+
+/// Picks an image MIME type from the file's magic number.
+fn sniff_image_mime(image: &[u8]) -> &'static str {
+    match image {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => "image/png",
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => "image/webp",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+fn render_readme_html(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::all());
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    ammonia::clean(&html)
+}
+
+/// Liveness probe: answers `ok` when a pooled connection runs `SELECT 1`.
+async fn handle_health(db: DbConnection) -> Result<impl Reply, Rejection> {
+    let healthy = tokio::task::spawn_blocking(move || {
+        db.get_timeout(HEALTH_CHECK_TIMEOUT)
+            .map_err(|e| e.to_string())
+            .and_then(|conn| {
+                conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+                    .map_err(|e| e.to_string())
+            })
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|checked| checked);
+    Ok(match healthy {
+        Ok(_) => warp::reply::with_status(
+            warp::reply::json(&json!({ "status": "ok" })),
+            StatusCode::OK,
+        ),
+        Err(e) => {
+            log_line!("Health check failed: {}", e);
+            warp::reply::with_status(
+                warp::reply::json(&json!({ "status": "degraded" })),
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+        }
+    })
+}
+
+async fn handle_capabilities(config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&capabilities(&config)))
+}
+
+/// Describes which optional features and limits this server instance has, so
+/// clients can adapt without probing individual routes.
+fn capabilities(config: &Config) -> serde_json::Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "routes": {
+            "metadata": config.enable_metadata,
+            "upload": config.enable_upload,
+            "download": config.enable_download,
+            "readme": config.enable_readme,
+            "setup": config.enable_setup,
+        },
+        "features": {
+            "admin": config.admin_key.is_some(),
+            "post_upload_command": config.post_upload_command.is_some(),
+            "readme_html": true,
+            "download_redirect": config.download_redirect_base.is_some(),
+            "audit_log": config.audit_log,
+            "json_case": match config.json_case {
+                JsonCase::Snake => "snake",
+                JsonCase::Camel => "camel",
+            },
+        },
+        "limits": {
+            "max_upload_bytes": config.max_file_bytes,
+            "max_thumbnail_bytes": config.max_thumbnail_bytes,
+            "max_readme_bytes": MAX_README_BYTES,
+            "max_title_length": config.max_title_length,
+            "max_description_length": config.max_description_length,
+            "max_multipart_parts": config.max_multipart_parts,
+            "overlong_fields": match config.overlong_fields {
+                OverlongFieldMode::Reject => "reject",
+                OverlongFieldMode::Truncate => "truncate",
+            },
+        },
+    })
+}
+
+/// Rewrites the field names of a JSON response to the configured case. Bodies
+/// are left untouched for the default snake_case, so only camelCase responses
+/// are buffered.
+async fn apply_json_case(response: Response, case: JsonCase) -> Response {
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if case == JsonCase::Snake || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log_line!("Failed to read response body: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    camel_case_keys(&mut value);
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(value.to_string()))
+}
+
+fn camel_case_keys(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    camel_case_keys(&mut value);
+                    (snake_to_camel(&key), value)
+                })
+                .collect();
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(camel_case_keys),
+        _ => {}
+    }
+}
+
+fn snake_to_camel(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' && !camel.is_empty() {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+async fn handle_export_metadata(
+    query: ExportQuery,
+    admin_key: Option<String>,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<Response, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    with_conn(&db, move |conn| match query.format.as_deref() {
+        None | Some("json") => {
+            let mods = load_all_metadata(conn, EXPORT_COLUMNS).map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+            Ok(warp::reply::json(&mods).into_response())
+        }
+        Some("sqlite") => {
+            let data = export_metadata_sqlite(conn).map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+            let reply = warp::reply::with_header(data, "Content-Type", "application/vnd.sqlite3");
+            let reply = warp::reply::with_header(
+                reply,
+                "Content-Disposition",
+                "attachment; filename=\"mods-metadata.sqlite\"",
+            );
+            Ok(reply.into_response())
+        }
+        Some(other) => Err(warp::reject::custom(QueryError {
+            details: format!("unsupported export format `{}`", other),
+        })),
+    })
+    .await
+}
+
+/// Lists mods whose thumbnail is empty or can't be decoded into an image, for
+/// maintainers filling gaps in the catalog.
+async fn handle_missing_thumbnails(
+    admin_key: Option<String>,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    let mods = with_conn(&db, move |conn| {
+        let mods = conn
+            .prepare("SELECT id, title, version, thumbnail FROM mods ORDER BY id")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+        Ok(mods)
+    })
+    .await?;
+
+    let missing = mods
+        .into_iter()
+        .filter_map(|(id, title, version, thumbnail)| {
+            let reason = if thumbnail.is_empty() {
+                "empty"
+            } else if BASE64_STANDARD.decode(&thumbnail).is_err() {
+                "unreadable"
+            } else {
+                return None;
+            };
+            Some(json!({
+                "id": id,
+                "title": title,
+                "version": version,
+                "reason": reason,
+            }))
+        })
+        .collect::<Vec<_>>();
+    Ok(warp::reply::json(&missing))
+}
+
+/// Stats every mod's file and stores its size where the recorded one is
+/// missing or wrong. Mods whose file is gone are reported and left alone.
+async fn handle_recompute_sizes(
+    admin_key: Option<String>,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    let recorded = with_conn(&db, move |conn| {
+        conn.prepare("SELECT id, file_path, size_bytes FROM mods ORDER BY id")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+    })
+    .await?;
+    let checked = recorded.len();
+
+    let (changed, missing) = tokio::task::spawn_blocking(move || {
+        let mut changed = Vec::new();
+        let mut missing = Vec::new();
+        for (id, file_path, size_bytes) in recorded {
+            match fs::metadata(&file_path) {
+                Ok(metadata) if size_bytes != Some(metadata.len() as i64) => {
+                    changed.push((id, metadata.len() as i64));
+                }
+                Ok(_) => {}
+                Err(_) => missing.push(id),
+            }
+        }
+        (changed, missing)
+    })
+    .await
+    .map_err(|e| {
+        warp::reject::custom(FileError {
+            details: e.to_string(),
+        })
+    })?;
+
+    with_conn(&db, {
+        let changed = changed.clone();
+        move |conn| {
+            for (id, size) in &changed {
+                conn.execute(
+                    "UPDATE mods SET size_bytes = ?1 WHERE id = ?2",
+                    params![size, id],
+                )
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            }
+            let details = format!("fixed {}", changed.len());
+            record_audit(
+                conn,
+                &config,
+                &identity,
+                "recompute-sizes",
+                "*",
+                Some(&details),
+            )
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+            Ok(())
+        }
+    })
+    .await?;
+
+    if !changed.is_empty() {
+        cache.invalidate();
+    }
+    for (id, _) in &changed {
+        events.publish(CatalogEvent::Upserted { id: id.clone() });
+    }
+
+    Ok(warp::reply::json(&json!({
+        "checked": checked,
+        "fixed": changed.len(),
+        "missing": missing,
+    })))
+}
+
+/// Migrates every mod's latest file to another compression. Each file is
+/// decoded and re-encoded beside the original, renamed into place, and only
+/// then recorded; the old file is removed last. A mod changed while its file
+/// was being recompressed keeps the new upload and is reported as skipped.
+async fn handle_recompress(
+    query: RecompressQuery,
+    admin_key: Option<String>,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    let to = query.to.as_deref().unwrap_or_default();
+    let to = StorageFormat::parse(to).ok_or_else(|| {
+        warp::reject::custom(QueryError {
+            details: format!("invalid format `{}`, expected `gzip` or `zstd`", to),
+        })
+    })?;
+    let recorded = with_conn(&db, move |conn| {
+        conn.prepare(
+            "SELECT id, file_path, compression FROM mods WHERE file_path != '' ORDER BY id",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })
+    })
+    .await?;
+    let checked = recorded.len();
+    let pending = recorded
+        .into_iter()
+        .filter(|(_, _, compression)| compression != to.name())
+        .collect::<Vec<_>>();
+
+    let mut recompressed = 0;
+    let mut skipped = Vec::new();
+    let mut failed = Vec::new();
+    for (index, (id, file_path, compression)) in pending.iter().enumerate() {
+        if StorageFormat::parse(compression).is_none() {
+            skipped.push(id.clone());
+            continue;
+        }
+        let target = Path::new(file_path)
+            .with_extension(file_extension(to.name()))
+            .to_string_lossy()
+            .into_owned();
+        // No row points at the target yet, so it can't be a file another mod
+        // shares.
+        let target = with_conn(&db, {
+            let id = id.clone();
+            move |conn| {
+                unshared_file_path(conn, target, &id, "", false).map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })
+            }
+        })
+        .await?;
+        let staging_path = format!("{}.recompress", target);
+        let (source, staged) = (file_path.clone(), staging_path.clone());
+        let written = tokio::task::spawn_blocking(move || {
+            recompress_file(&source, &staged, to)?;
+            let size = fs::metadata(&staged)?.len() as i64;
+            Ok::<_, io::Error>((sha256_file(&staged)?, size))
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|written| written)
+        .and_then(|written| fs::rename(&staging_path, &target).map(|_| written));
+        let (sha256, size_bytes) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&staging_path);
+                log_line!("Failed to recompress {}: {}", id, e);
+                failed.push(json!({ "id": id, "error": e.to_string() }));
+                continue;
+            }
+        };
+
+        let (updated, target_recorded, source_recorded) = with_conn(&db, {
+            let (config, identity) = (config.clone(), identity.clone());
+            let (id, file_path) = (id.clone(), file_path.clone());
+            let (compression, target) = (compression.clone(), target.clone());
+            move |conn| {
+                let updated = conn
+                    .transaction()
+                    .and_then(|tx| {
+                        let updated = tx.execute(
+                            "UPDATE mods SET file_path = ?1, sha256 = ?2, size_bytes = ?3, compression = ?4, updated_at = ?5
+                            WHERE id = ?6 AND file_path = ?7 AND compression = ?8",
+                            params![
+                                target,
+                                sha256,
+                                size_bytes,
+                                to.name(),
+                                unix_now(),
+                                id,
+                                file_path,
+                                compression
+                            ],
+                        )?;
+                        if updated > 0 {
+                            tx.execute(
+                                "UPDATE mod_versions SET file_path = ?1, sha256 = ?2, size_bytes = ?3, compression = ?4
+                                WHERE id = ?5 AND file_path = ?6",
+                                params![target, sha256, size_bytes, to.name(), id, file_path],
+                            )?;
+                            record_change(&tx, &id, "update")?;
+                            record_audit(
+                                &tx,
+                                &config,
+                                &identity,
+                                "recompress",
+                                &id,
+                                Some(to.name()),
+                            )?;
+                        }
+                        tx.commit()?;
+                        Ok(updated > 0)
+                    })
+                    .map_err(|e| {
+                        warp::reject::custom(DbError {
+                            details: e.to_string(),
+                        })
+                    })?;
+                Ok((
+                    updated,
+                    file_is_recorded(conn, &target),
+                    file_is_recorded(conn, &file_path),
+                ))
+            }
+        })
+        .await?;
+        if !updated {
+            // Re-uploaded meanwhile; the upload may have written the target
+            // path itself, so only a file nobody records is removed.
+            if target != *file_path && !target_recorded {
+                let _ = fs::remove_file(&target);
+            }
+            skipped.push(id.clone());
+            continue;
+        }
+        if target != *file_path && !source_recorded {
+            let _ = fs::remove_file(file_path);
+        }
+        events.publish(CatalogEvent::Upserted { id: id.clone() });
+        recompressed += 1;
+        log_line!(
+            "Recompressed {} to {} ({}/{})",
+            id,
+            to.name(),
+            index + 1,
+            pending.len()
+        );
+    }
+    if recompressed > 0 {
+        cache.invalidate();
+    }
+
+    Ok(warp::reply::json(&json!({
+        "to": to.name(),
+        "checked": checked,
+        "recompressed": recompressed,
+        "skipped": skipped,
+        "failed": failed,
+    })))
+}
+
+/// A stored file whose bytes hash to `sha256`, for an upload to point at
+/// instead of writing another copy.
+fn find_stored_duplicate(
+    conn: &Connection,
+    sha256: &str,
+) -> Result<Option<String>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT file_path FROM mods WHERE sha256 = ?1
+        UNION SELECT file_path FROM mod_versions WHERE sha256 = ?1",
+    )?;
+    let paths = stmt
+        .query_map(params![sha256], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(paths.into_iter().find(|path| Path::new(path).is_file()))
+}
+
+/// Picks where a new file for `version` of `id` goes when `path` is its usual
+/// place. A file other mods or versions were deduplicated onto must keep its
+/// bytes, so the new one then gets a numbered name beside it. The mod row of
+/// `id` counts as replaced when `replaces_mod_row` is set or it describes
+/// `version`.
+fn unshared_file_path(
+    conn: &Connection,
+    path: String,
+    id: &str,
+    version: &str,
+    replaces_mod_row: bool,
+) -> Result<String, rusqlite::Error> {
+    let shared = |candidate: &str| {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM mods WHERE file_path = ?1
+                AND NOT (id = ?2 AND (?4 OR version = ?3)))
+            OR EXISTS(SELECT 1 FROM mod_versions WHERE file_path = ?1
+                AND NOT (id = ?2 AND version = ?3))",
+            params![candidate, id, version, replaces_mod_row],
+            |row| row.get::<_, bool>(0),
+        )
+    };
+    if !shared(&path)? {
+        return Ok(path);
+    }
+    let usual = Path::new(&path);
+    let stem = usual.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = usual.extension().unwrap_or_default().to_string_lossy();
+    let mut n = 1;
+    loop {
+        let candidate = usual
+            .with_file_name(format!("{}-{}.{}", stem, n, extension))
+            .to_string_lossy()
+            .into_owned();
+        if !shared(&candidate)? {
+            return Ok(candidate);
+        }
+        n += 1;
+    }
+}
+
+/// `path`, or the first numbered name beside it, that no row records and no
+/// file occupies, so writing there can't touch a file anything relies on.
+fn fresh_file_path(conn: &Connection, path: String) -> String {
+    let taken =
+        |candidate: &str| file_is_recorded(conn, candidate) || Path::new(candidate).exists();
+    if !taken(&path) {
+        return path;
+    }
+    let usual = Path::new(&path);
+    let stem = usual.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = usual.extension().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| {
+            usual
+                .with_file_name(format!("{}-{}.{}", stem, n, extension))
+                .to_string_lossy()
+                .into_owned()
+        })
+        .find(|candidate| !taken(candidate))
+        .unwrap_or(path)
+}
+
+/// Whether a mod other than `id`, or one of its versions, points at `path`.
+fn file_is_shared(conn: &Connection, path: &str, id: &str) -> bool {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM mods WHERE file_path = ?1 AND id != ?2)
+            OR EXISTS(SELECT 1 FROM mod_versions WHERE file_path = ?1 AND id != ?2)",
+        params![path, id],
+        |row| row.get(0),
+    )
+    .unwrap_or(true)
+}
+
+/// Whether any mod row or kept version still points at `path`.
+fn file_is_recorded(conn: &Connection, path: &str) -> bool {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM mods WHERE file_path = ?1)
+            OR EXISTS(SELECT 1 FROM mod_versions WHERE file_path = ?1)",
+        params![path],
+        |row| row.get(0),
+    )
+    .unwrap_or(true)
+}
+
+/// Decodes the gzip or zstd file at `source` and writes it to `target`
+/// encoded as `to`, streaming so the decoded tarball is never held in memory.
+fn recompress_file(source: &str, target: &str, to: StorageFormat) -> io::Result<()> {
+    let mut input = fs::File::open(source)?;
+    let mut head = Vec::with_capacity(4);
+    (&mut input).take(4).read_to_end(&mut head)?;
+    io::Seek::seek(&mut input, io::SeekFrom::Start(0))?;
+    let mut decoded: Box<dyn Read> = match compression_of(&head) {
+        "gzip" => Box::new(GzDecoder::new(input)),
+        "zstd" => Box::new(zstd::stream::Decoder::new(input)?),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file is neither gzip nor zstd",
+            ));
+        }
+    };
+    let output = fs::File::create(target)?;
+    match to {
+        StorageFormat::Gzip => {
+            let mut encoder = GzEncoder::new(output, Compression::default());
+            io::copy(&mut decoded, &mut encoder)?;
+            encoder.finish()?.sync_all()
+        }
+        StorageFormat::Zstd => {
+            let mut encoder = zstd::stream::Encoder::new(output, 0)?;
+            io::copy(&mut decoded, &mut encoder)?;
+            encoder.finish()?.sync_all()
+        }
+    }
+}
+
+fn load_all_metadata(
+    conn: &Connection,
+    columns: &str,
+) -> Result<Vec<ModMetadata>, rusqlite::Error> {
+    conn.prepare(&format!("SELECT {} FROM mods", columns))?
+        .query_map([], row_to_metadata)?
+        .collect()
+}
+
+/// Copies the metadata columns into a standalone SQLite file and returns its
+/// bytes. Mod files themselves are not included.
+fn export_metadata_sqlite(conn: &Connection) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let export = tempfile::NamedTempFile::new()?;
+    let export_path = export.path().to_string_lossy().into_owned();
+
+    conn.execute("ATTACH DATABASE ?1 AS export", params![export_path])?;
+    let copied = conn.execute(
+        &format!(
+            "CREATE TABLE export.mods AS SELECT {} FROM main.mods",
+            EXPORT_COLUMNS
+        ),
+        [],
+    );
+    conn.execute("DETACH DATABASE export", [])?;
+    copied?;
+
+    Ok(fs::read(export.path())?)
+}
+
+async fn handle_setup(
+    query: SetupQuery,
+    admin_key: Option<String>,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    if query.reset {
+        authorize_admin(&config, admin_key.as_deref())?;
+        if !query.confirm {
+            return Err(warp::reject::custom(QueryError {
+                details: "destructive setup requires confirm=true".to_string(),
+            }));
+        }
+    }
+
+    let removed = with_conn(&db, move |conn| {
+        let mut removed = Vec::new();
+        if query.reset {
+            refuse_if_protected(conn, None)?;
+            removed = conn
+                .prepare("SELECT id FROM mods")
+                .and_then(|mut stmt| {
+                    stmt.query_map([], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .and_then(|ids| reset_db(conn).map(|_| ids))
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+        }
+        setup_db(conn).map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })?;
+        if query.reset {
+            record_audit(conn, &config, &identity, "reset", "*", None).map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+        }
+        Ok(removed)
+    })
+    .await?;
+
+    if query.reset {
+        cache.invalidate();
+        for id in removed {
+            events.publish(CatalogEvent::Deleted { id });
+        }
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Sets or clears a mod's `protected` flag. Protected mods can't be removed
+/// until they are unprotected again.
+async fn handle_set_protected(
+    id: String,
+    admin_key: Option<String>,
+    request: ProtectedRequest,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+
+    with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            let updated = conn
+                .execute(
+                    "UPDATE mods SET protected = ?1 WHERE id = ?2",
+                    params![request.protected, id],
+                )
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?;
+            if updated == 0 {
+                return Err(mod_not_found(conn, &id));
+            }
+            let action = if request.protected {
+                "protect"
+            } else {
+                "unprotect"
+            };
+            record_audit(conn, &config, &identity, action, &id, None).map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+            Ok(())
+        }
+    })
+    .await?;
+
+    Ok(warp::reply::json(&json!({
+        "id": id,
+        "protected": request.protected,
+    })))
+}
+
+/// An upload held for review, with its post-upload hook's status as `scan`.
+#[derive(Serialize)]
+struct PendingMod {
+    #[serde(flatten)]
+    metadata: ModMetadata,
+    scan: ProcessingStatus,
+}
+
+/// Lists the uploads `MOD_DB_QUARANTINE_UPLOADS` is holding, oldest first,
+/// with their thumbnails inline since there is no listed one to link to.
+/// Uploads whose hook isn't tracked have finished processing.
+async fn handle_pending(
+    admin_key: Option<String>,
+    db: DbConnection,
+    config: Arc<Config>,
+    processing: ProcessingTracker,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+
+    let mods = with_conn(&db, move |conn| {
+        let mods = conn
+            .prepare(&format!(
+                "SELECT {} FROM pending_uploads ORDER BY uploaded_at, id",
+                PENDING_COLUMNS
+            ))
+            .and_then(|mut stmt| {
+                stmt.query_map([], row_to_metadata)?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })?;
+        Ok(mods)
+    })
+    .await?;
+
+    let pending = mods
+        .into_iter()
+        .map(|metadata| PendingMod {
+            scan: processing
+                .get(&metadata.id)
+                .unwrap_or(ProcessingStatus::Complete),
+            metadata,
+        })
+        .collect::<Vec<_>>();
+    Ok(warp::reply::json(&pending))
+}
+
+fn not_pending(id: &str) -> Rejection {
+    warp::reject::custom(NotFoundError {
+        details: format!("mod `{}` has no upload waiting for review", id),
+    })
+}
+
+/// Swaps a held upload into the catalog in place of the mod's listed
+/// revision, as if it had just been uploaded, and announces it.
+async fn handle_approve(
+    id: String,
+    admin_key: Option<String>,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+    cache: MetadataCache,
+    events: CatalogEvents,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+    let db_error = |e: rusqlite::Error| {
+        warp::reject::custom(DbError {
+            details: e.to_string(),
+        })
+    };
+
+    let (held, staged, target) = with_conn(&db, {
+        let (id, config) = (id.clone(), config.clone());
+        move |conn| {
+            let (mut held, readme, server_gzipped, thumbnail_sizes, uploader) = conn
+                .query_row(
+                    &format!(
+                        "SELECT {}, readme, server_gzipped, thumbnail_original_bytes, thumbnail_bytes, identity FROM pending_uploads WHERE id = ?1",
+                        PENDING_COLUMNS
+                    ),
+                    params![id],
+                    |row| {
+                        Ok((
+                            row_to_metadata(row)?,
+                            row.get::<_, String>("readme")?,
+                            row.get::<_, bool>("server_gzipped")?,
+                            (
+                                row.get::<_, Option<i64>>("thumbnail_original_bytes")?,
+                                row.get::<_, Option<i64>>("thumbnail_bytes")?,
+                            ),
+                            row.get::<_, String>("identity")?,
+                        ))
+                    },
+                )
+                .optional()
+                .map_err(db_error)?
+                .ok_or_else(|| not_pending(&id))?;
+
+            // The held file moves to where an upload of it would have gone, or is
+            // dropped for a stored file with the same bytes.
+            let staged = std::mem::take(&mut held.file_path);
+            let mut target = None;
+            if !staged.is_empty() {
+                let duplicate = match held.sha256.as_deref() {
+                    Some(checksum) => find_stored_duplicate(conn, checksum).map_err(db_error)?,
+                    None => None,
+                };
+                held.file_path = match duplicate {
+                    Some(existing) => existing,
+                    None => {
+                        let file_path = upload_file_path(&config, &held)
+                            .map_err(|details| warp::reject::custom(UploadError { details }))?;
+                        let file_path =
+                            unshared_file_path(conn, file_path, &id, &held.version, !config.keep_versions)
+                                .map_err(db_error)?;
+                        let dir = Path::new(&file_path).parent().unwrap_or(&config.mods_dir);
+                        fs::create_dir_all(dir).map_err(|e| {
+                            warp::reject::custom(FileError {
+                                details: e.to_string(),
+                            })
+                        })?;
+                        target = Some(file_path.clone());
+                        file_path
+                    }
+                };
+            }
+
+            conn.transaction()
+                .and_then(|tx| {
+                    upsert_mod_row(
+                        &tx,
+                        &config,
+                        &uploader,
+                        &held,
+                        &UploadColumns {
+                            readme: &readme,
+                            sha256: held.sha256.as_deref(),
+                            size_bytes: held.size_bytes.map(|size| size as i64),
+                            server_gzipped,
+                            thumbnail_sizes,
+                        },
+                    )?;
+                    tx.execute("DELETE FROM pending_uploads WHERE id = ?1", params![id])?;
+                    record_audit(&tx, &config, &identity, "approve", &id, Some(&held.version))?;
+                    tx.commit()
+                })
+                .map_err(db_error)?;
+            Ok((held, staged, target))
+        }
+    })
+    .await?;
+    match &target {
+        Some(target) => {
+            let moved = config
+                .storage_retry
+                .run("Approving upload", || tokio::fs::rename(&staged, target))
+                .await;
+            if let Err(e) = moved {
+                return Err(warp::reject::custom(FileError {
+                    details: e.to_string(),
+                }));
+            }
+        }
+        None if !staged.is_empty() => discard_upload_file(&staged).await,
+        None => {}
+    }
+    cache.invalidate();
+    events.publish(CatalogEvent::Upserted { id: id.clone() });
+    log_line!("Approved mod {} version {}", id, held.version);
+
+    Ok(warp::reply::json(&json!({
+        "id": id,
+        "moderation": "approved",
+    })))
+}
+
+/// Discards a held upload and its file. The mod's listed revision, if any,
+/// is left as it was.
+async fn handle_reject(
+    id: String,
+    admin_key: Option<String>,
+    identity: String,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+
+    let file_path = with_conn(&db, {
+        let id = id.clone();
+        move |conn| {
+            conn.transaction()
+                .and_then(|tx| {
+                    let file_path: Option<String> = tx
+                        .query_row(
+                            "SELECT file_path FROM pending_uploads WHERE id = ?1",
+                            params![id],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    if file_path.is_some() {
+                        tx.execute("DELETE FROM pending_uploads WHERE id = ?1", params![id])?;
+                        record_audit(&tx, &config, &identity, "reject", &id, None)?;
+                    }
+                    tx.commit()?;
+                    Ok(file_path)
+                })
+                .map_err(|e| {
+                    warp::reject::custom(DbError {
+                        details: e.to_string(),
+                    })
+                })?
+                .ok_or_else(|| not_pending(&id))
+        }
+    })
+    .await?;
+    if !file_path.is_empty() {
+        discard_upload_file(&file_path).await;
+    }
+    log_line!("Rejected the upload held for mod {}", id);
+
+    Ok(StatusCode::OK)
+}
+
+/// Refuses with `423 Locked` to remove a protected mod, or with `id` unset,
+/// to remove every mod while any of them is protected.
+fn refuse_if_protected(conn: &Connection, id: Option<&str>) -> Result<(), Rejection> {
+    let protected: Option<String> = conn
+        .query_row(
+            "SELECT id FROM mods WHERE protected = 1 AND (?1 IS NULL OR id = ?1) LIMIT 1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })?;
+    match protected {
+        Some(protected) => Err(warp::reject::custom(LockedError {
+            details: format!("mod `{}` is protected; unprotect it first", protected),
+        })),
+        None => Ok(()),
+    }
+}
+
+/// Turns maintenance mode on or off. The message and `Retry-After` default to
+/// `MOD_DB_MAINTENANCE_MESSAGE` and `MOD_DB_MAINTENANCE_RETRY_AFTER_SECS`.
+async fn handle_set_maintenance(
+    admin_key: Option<String>,
+    request: MaintenanceRequest,
+    config: Arc<Config>,
+    maintenance: Maintenance,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+
+    let notice = request.enabled.then(|| MaintenanceNotice {
+        message: request
+            .message
+            .filter(|message| !message.trim().is_empty())
+            .unwrap_or_else(|| config.maintenance_message.clone()),
+        retry_after_secs: request
+            .retry_after_secs
+            .unwrap_or(config.maintenance_retry_after.as_secs()),
+    });
+    match &notice {
+        Some(notice) => log_line!("Entering maintenance mode: {}", notice.message),
+        None => log_line!("Leaving maintenance mode"),
+    }
+    maintenance.set(notice.clone());
+
+    Ok(warp::reply::json(&json!({
+        "enabled": notice.is_some(),
+        "notice": notice,
+    })))
+}
+
+/// Streams log lines as server-sent events: the buffered recent lines first,
+/// then new ones as they are written. Lines dropped because the subscriber fell
+/// behind are reported with a `lagged` event.
+async fn handle_log_stream(
+    admin_key: Option<String>,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+
+    let (recent, updates) = LOGS.subscribe();
+    let recent = futures::stream::iter(recent).map(|line| warp::sse::Event::default().data(line));
+    let updates = futures::stream::unfold(updates, |mut updates| async move {
+        let event = match updates.recv().await {
+            Ok(line) => warp::sse::Event::default().data(line),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => warp::sse::Event::default()
+                .event("lagged")
+                .data(skipped.to_string()),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((event, updates))
+    });
+    let events = recent.chain(updates).map(Ok::<_, std::convert::Infallible>);
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+/// Streams catalog changes as they are committed, one `upserted` or `deleted`
+/// event per mod, so clients can stop polling `/metadata`.
+async fn handle_catalog_events(events: CatalogEvents) -> Result<impl Reply, Rejection> {
+    let updates = futures::stream::unfold(events.subscribe(), |mut updates| async move {
+        let event = match updates.recv().await {
+            Ok(change) => warp::sse::Event::default()
+                .event(change.name())
+                .json_data(&change)
+                .unwrap_or_default(),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => warp::sse::Event::default()
+                .event("lagged")
+                .data(skipped.to_string()),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((event, updates))
+    });
+    let events = updates.map(Ok::<_, std::convert::Infallible>);
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+async fn handle_audit(
+    query: AuditQuery,
+    admin_key: Option<String>,
+    db: DbConnection,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    authorize_admin(&config, admin_key.as_deref())?;
+
+    let mut sql =
+        "SELECT id, created_at, identity, action, target_id, details FROM audit_log WHERE 1 = 1"
+            .to_string();
+    let mut values: Vec<rusqlite::types::Value> = Vec::new();
+    for (column, value) in [
+        ("action", &query.action),
+        ("target_id", &query.target_id),
+        ("identity", &query.identity),
+    ] {
+        if let Some(value) = value {
+            values.push(value.clone().into());
+            sql.push_str(&format!(" AND {} = ?{}", column, values.len()));
+        }
+    }
+    if let Some(since) = query.since {
+        values.push(since.into());
+        sql.push_str(&format!(" AND created_at >= ?{}", values.len()));
+    }
+    if let Some(until) = query.until {
+        values.push(until.into());
+        sql.push_str(&format!(" AND created_at < ?{}", values.len()));
+    }
+    values.push(i64::from(query.limit.unwrap_or(100).min(MAX_AUDIT_RESULTS)).into());
+    sql.push_str(&format!(" ORDER BY id DESC LIMIT ?{}", values.len()));
+    values.push(i64::from(query.offset.unwrap_or(0)).into());
+    sql.push_str(&format!(" OFFSET ?{}", values.len()));
+
+    let entries = with_conn(&db, move |conn| {
+        conn.prepare(&sql)
+            .and_then(|mut stmt| {
+                stmt.query_map(rusqlite::params_from_iter(values), |row| {
+                    Ok(AuditEntry {
+                        id: row.get(0)?,
+                        created_at: row.get(1)?,
+                        identity: row.get(2)?,
+                        action: row.get(3)?,
+                        target_id: row.get(4)?,
+                        details: row.get(5)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                warp::reject::custom(DbError {
+                    details: e.to_string(),
+                })
+            })
+    })
+    .await?;
+
+    Ok(warp::reply::json(&entries))
+}
+
+async fn handle_changes(query: ChangesQuery, db: DbConnection) -> Result<impl Reply, Rejection> {
+    let after = query.after.unwrap_or(0);
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_CHANGES_RESULTS);
+
+    // One extra row tells us whether another page exists.
+    let mut changes = with_conn(&db, move |conn| {
+        conn.prepare(
+            "SELECT seq, mod_id, action, changed_at FROM mod_changes
+            WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![after, i64::from(limit) + 1], |row| {
+                Ok(ChangeEntry {
+                    cursor: row.get(0)?,
+                    id: row.get(1)?,
+                    action: row.get(2)?,
+                    changed_at: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| {
+            warp::reject::custom(DbError {
+                details: e.to_string(),
+            })
+        })
+    })
+    .await?;
+    let has_more = changes.len() > limit as usize;
+    changes.truncate(limit as usize);
+    let next_cursor = changes.last().map_or(after, |change| change.cursor);
+
+    Ok(warp::reply::json(&json!({
+        "changes": changes,
+        "next_cursor": next_cursor,
+        "has_more": has_more,
+    })))
+}
+
+/// The routes currently served, as listed in the body of a 404 for an unknown path.
+fn available_routes(config: &Config) -> Vec<&'static str> {
+    let mut routes = vec!["GET /health", "GET /capabilities"];
+    if config.enable_metadata {
+        routes.extend([
+            "GET /metadata",
+            "GET /events",
+            "POST /metadata/batch",
+            "PATCH /metadata/{id}",
+            "GET /search/full",
+            "GET /mods",
+            "GET /changes",
+            "POST /modpack/check",
+            "POST /verify-files",
+            "POST /update-size",
+            "GET /mods/latest",
+            "GET /mods/{id}/versions",
+            "GET /mods/{id}/related",
+            "GET /stats/popular",
+            "GET /authors/{author}/stats",
+            "GET /mods/{id}/analytics.csv",
+            "GET /thumbnail/{id}",
+        ]);
+    }
+    if config.enable_upload {
+        routes.extend([
+            "POST /upload",
+            "POST /mods/{id}/reserve",
+            "POST /mods/{id}/refetch",
+            "DELETE /delete/{id}",
+            "GET /upload/{id}/status",
+            "PUT /download/{id}",
+        ]);
+    }
+    if config.enable_download {
+        routes.extend([
+            "GET /download/{id}",
+            "GET /download/{id}/{version}",
+            "GET /mods/{id}/sources",
+        ]);
+    }
+    if config.enable_readme {
+        routes.push("GET /readme/{id}");
+    }
+    if config.admin_key.is_some() {
+        routes.extend([
+            "GET /admin/export-metadata",
+            "GET /admin/export",
+            "POST /admin/import",
+            "GET /admin/missing-thumbnails",
+            "POST /admin/recompute-sizes",
+            "POST /admin/recompress",
+            "GET /admin/pending",
+            "POST /admin/approve/{id}",
+            "POST /admin/reject/{id}",
+            "PUT /admin/mods/{id}/protected",
+            "GET /admin/audit",
+            "GET /admin/logs/stream",
+            "PUT /admin/maintenance",
+        ]);
+    }
+    if config.enable_setup {
+        routes.push("GET /setup");
+    }
+    routes
+}
+
+tokio::task_local! {
+    /// The language negotiated from the request's `Accept-Language`, so error
+    /// responses can be built in it without threading it through every filter.
+    static LOCALE: Locale;
+}
+
+/// A language error messages are available in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Locale {
+    En,
+    Es,
+    Fr,
+    De,
+}
+
+impl Locale {
+    /// Picks the client's most preferred supported language from an
+    /// `Accept-Language` header, or English when none is supported.
+    fn negotiate(header: Option<&str>) -> Locale {
+        let mut best: Option<(f32, Locale)> = None;
+        for item in header.unwrap_or_default().split(',') {
+            let mut parts = item.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse::<f32>().unwrap_or(0.0),
+                None => 1.0,
+            };
+            let primary = tag.split('-').next().unwrap_or_default();
+            let locale = if primary.eq_ignore_ascii_case("en") {
+                Locale::En
+            } else if primary.eq_ignore_ascii_case("es") {
+                Locale::Es
+            } else if primary.eq_ignore_ascii_case("fr") {
+                Locale::Fr
+            } else if primary.eq_ignore_ascii_case("de") {
+                Locale::De
+            } else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                best = Some((quality, locale));
+            }
+        }
+        best.map_or(Locale::En, |(_, locale)| locale)
+    }
+}
+
+/// Translations of the error headings, in Spanish, French, and German.
+const ERROR_MESSAGES: &[(&str, [&str; 3])] = &[
+    (
+        "Not Found",
+        ["No encontrado", "Introuvable", "Nicht gefunden"],
+    ),
+    ("Gone", ["Eliminado", "Supprimé", "Entfernt"]),
+    (
+        "Too many uploads; retry in {} seconds",
+        [
+            "Demasiadas subidas; reintente en {} segundos",
+            "Trop de téléversements ; réessayez dans {} secondes",
+            "Zu viele Uploads; erneut versuchen in {} Sekunden",
+        ],
+    ),
+    (
+        "Service unavailable",
+        [
+            "Servicio no disponible",
+            "Service indisponible",
+            "Dienst nicht verfügbar",
+        ],
+    ),
+    (
+        "Database error",
+        [
+            "Error de base de datos",
+            "Erreur de base de données",
+            "Datenbankfehler",
+        ],
+    ),
+    (
+        "Upload error",
+        [
+            "Error de subida",
+            "Erreur de téléversement",
+            "Upload-Fehler",
+        ],
+    ),
+    (
+        "File error",
+        ["Error de archivo", "Erreur de fichier", "Dateifehler"],
+    ),
+    (
+        "Validation error",
+        [
+            "Error de validación",
+            "Erreur de validation",
+            "Validierungsfehler",
+        ],
+    ),
+    (
+        "Auth error",
+        [
+            "Error de autenticación",
+            "Erreur d'authentification",
+            "Authentifizierungsfehler",
+        ],
+    ),
+    ("Locked", ["Bloqueado", "Verrouillé", "Gesperrt"]),
+    ("Conflict", ["Conflicto", "Conflit", "Konflikt"]),
+    (
+        "Precondition failed",
+        [
+            "Falló la precondición",
+            "Échec de la précondition",
+            "Vorbedingung fehlgeschlagen",
+        ],
+    ),
+    (
+        "Precondition required",
+        [
+            "Se requiere una precondición",
+            "Précondition requise",
+            "Vorbedingung erforderlich",
+        ],
+    ),
+    (
+        "Payload too large",
+        [
+            "Contenido demasiado grande",
+            "Contenu trop volumineux",
+            "Inhalt zu groß",
+        ],
+    ),
+    (
+        "Upstream error",
+        ["Error del origen", "Erreur en amont", "Fehler der Quelle"],
+    ),
+    (
+        "Post-upload hook error",
+        [
+            "Error del hook posterior a la subida",
+            "Erreur du hook après téléversement",
+            "Fehler im Post-Upload-Hook",
+        ],
+    ),
+    (
+        "Query error",
+        ["Error de consulta", "Erreur de requête", "Abfragefehler"],
+    ),
+    (
+        "Invalid body",
+        ["Cuerpo no válido", "Corps invalide", "Ungültiger Inhalt"],
+    ),
+    (
+        "Internal Server Error",
+        [
+            "Error interno del servidor",
+            "Erreur interne du serveur",
+            "Interner Serverfehler",
+        ],
+    ),
+    (
+        "Request Timeout",
+        [
+            "Tiempo de espera agotado",
+            "Délai d'attente dépassé",
+            "Zeitüberschreitung der Anfrage",
+        ],
+    ),
+];
+
+/// Translates an English error heading into the request's language. The
+/// details that follow a heading come from deep in the handlers and stay in
+/// English.
+fn localize(message: &'static str) -> &'static str {
+    let index = match LOCALE.try_with(|locale| *locale).unwrap_or(Locale::En) {
+        Locale::En => return message,
+        Locale::Es => 0,
+        Locale::Fr => 1,
+        Locale::De => 2,
+    };
+    ERROR_MESSAGES
+        .iter()
+        .find(|(english, _)| *english == message)
+        .map_or(message, |(_, translations)| translations[index])
+}
+
+async fn handle_rejection(
+    err: Rejection,
+    config: Arc<Config>,
+    health: DbHealth,
+) -> Result<Response, std::convert::Infallible> {
+    if err.is_not_found() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::NOT_FOUND.as_u16(),
+            "message": localize("Not Found"),
+            "docs": config.docs_url,
+            "routes": available_routes(&config),
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::NOT_FOUND).into_response());
+    }
+
+    if let Some(e) = err.find::<NotFoundError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::NOT_FOUND.as_u16(),
+            "message": format!("{}: {}", localize("Not Found"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::NOT_FOUND).into_response());
+    }
+
+    if let Some(e) = err.find::<GoneError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::GONE.as_u16(),
+            "message": format!("{}: {}", localize("Gone"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::GONE).into_response());
+    }
+
+    if let Some(e) = err.find::<RateLimitError>() {
+        // Rounded up so a client waiting exactly this long finds a token.
+        let retry_after_secs = e.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::TOO_MANY_REQUESTS.as_u16(),
+            "message": localize("Too many uploads; retry in {} seconds")
+                .replace("{}", &retry_after_secs.to_string()),
+        }));
+        let reply = warp::reply::with_status(json, StatusCode::TOO_MANY_REQUESTS);
+        return Ok(
+            warp::reply::with_header(reply, "retry-after", retry_after_secs.to_string())
+                .into_response(),
+        );
+    }
+
+    if let Some(e) = err.find::<MaintenanceError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+            "message": e.message,
+            "maintenance": true,
+        }));
+        let reply = warp::reply::with_status(json, StatusCode::SERVICE_UNAVAILABLE);
+        return Ok(
+            warp::reply::with_header(reply, "retry-after", e.retry_after_secs.to_string())
+                .into_response(),
+        );
+    }
+
+    if let Some(e) = err.find::<UnavailableError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+            "message": format!("{}: {}", localize("Service unavailable"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::SERVICE_UNAVAILABLE).into_response());
+    }
+
+    if let Some(e) = err.find::<DbError>() {
+        tracing::error!(details = %e.details, "Database error");
+        health.record_failure(config.degrade_after_failures);
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+            "message": format!("{}: {}", localize("Database error"), e.details)
+        }));
+        return Ok(
+            warp::reply::with_status(json, StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+        );
+    }
+
+    if let Some(e) = err.find::<UploadError>() {
+        tracing::error!(details = %e.details, "Upload error");
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::BAD_REQUEST.as_u16(),
+            "message": format!("{}: {}", localize("Upload error"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::BAD_REQUEST).into_response());
+    }
+
+    if let Some(e) = err.find::<FileError>() {
+        tracing::error!(details = %e.details, "File error");
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+            "message": format!("{}: {}", localize("File error"), e.details)
+        }));
+        return Ok(
+            warp::reply::with_status(json, StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+        );
+    }
+
+    if let Some(e) = err.find::<ValidationError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::BAD_REQUEST.as_u16(),
+            "message": format!("{}: {}", localize("Validation error"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::BAD_REQUEST).into_response());
+    }
+
+    if let Some(e) = err.find::<AuthError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::UNAUTHORIZED.as_u16(),
+            "message": format!("{}: {}", localize("Auth error"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::UNAUTHORIZED).into_response());
+    }
+
+    if let Some(e) = err.find::<LockedError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::LOCKED.as_u16(),
+            "message": format!("{}: {}", localize("Locked"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::LOCKED).into_response());
+    }
+
+    if let Some(e) = err.find::<ConflictError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::CONFLICT.as_u16(),
+            "message": format!("{}: {}", localize("Conflict"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::CONFLICT).into_response());
+    }
+
+    if let Some(e) = err.find::<PreconditionFailedError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::PRECONDITION_FAILED.as_u16(),
+            "message": format!("{}: {}", localize("Precondition failed"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::PRECONDITION_FAILED).into_response());
+    }
+
+    if let Some(e) = err.find::<PreconditionRequiredError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::PRECONDITION_REQUIRED.as_u16(),
+            "message": format!("{}: {}", localize("Precondition required"), e.details)
+        }));
+        return Ok(
+            warp::reply::with_status(json, StatusCode::PRECONDITION_REQUIRED).into_response(),
+        );
+    }
+
+    if let Some(e) = err.find::<PayloadTooLargeError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::PAYLOAD_TOO_LARGE.as_u16(),
+            "message": format!("{}: {}", localize("Payload too large"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::PAYLOAD_TOO_LARGE).into_response());
+    }
+
+    if let Some(e) = err.find::<UpstreamError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::BAD_GATEWAY.as_u16(),
+            "message": format!("{}: {}", localize("Upstream error"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::BAD_GATEWAY).into_response());
+    }
+
+    if let Some(e) = err.find::<HookError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+            "message": format!("{}: {}", localize("Post-upload hook error"), e.details)
+        }));
+        return Ok(
+            warp::reply::with_status(json, StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+        );
+    }
+
+    if let Some(e) = err.find::<QueryError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::BAD_REQUEST.as_u16(),
+            "message": format!("{}: {}", localize("Query error"), e.details)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::BAD_REQUEST).into_response());
+    }
+
+    if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::BAD_REQUEST.as_u16(),
+            "message": format!("{}: {}", localize("Invalid body"), e)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::BAD_REQUEST).into_response());
+    }
+
+    if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        let json = warp::reply::json(&json!({
+            "code": StatusCode::BAD_REQUEST.as_u16(),
+            "message": format!("{}: {}", localize("Query error"), e)
+        }));
+        return Ok(warp::reply::with_status(json, StatusCode::BAD_REQUEST).into_response());
+    }
+
+    let json = warp::reply::json(&json!({
+        "code": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+        "message": localize("Internal Server Error")
+    }));
+    Ok(warp::reply::with_status(json, StatusCode::INTERNAL_SERVER_ERROR).into_response())
+}
@@ -0,0 +1,3728 @@
+use std::{io::Write, sync::Arc};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use hyper::{Body, Request, StatusCode, body::HttpBody, service::Service};
+use mod_manager_db::{
+    Config, CorsOrigins, DbConnection, JsonCase, OverlongFieldMode, RateLimit, RetryPolicy,
+    StorageFormat, app_service, backup_database, bind_server, build_routes, init_tracing,
+    open_memory_database, seed_from_dir, setup_db,
+};
+use serde_json::Value;
+use tempfile::TempDir;
+
+const BOUNDARY: &str = "mod-manager-db-test";
+
+/// A configuration that stores mod files in a fresh temporary directory.
+fn test_config() -> (Config, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::from_env();
+    config.mods_dir = dir.path().to_path_buf();
+    config.admin_key = Some("admin".to_string());
+    config.api_key = None;
+    (config, dir)
+}
+
+fn test_db() -> DbConnection {
+    let db = open_memory_database().unwrap();
+    setup_db(&db.get().unwrap()).unwrap();
+    db
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    std::io::Read::read_to_end(&mut GzDecoder::new(data), &mut decoded).unwrap();
+    decoded
+}
+
+/// Builds a `multipart/form-data` body with the given text fields and a
+/// `file` part.
+fn upload_form(fields: &[(&str, &str)], file: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        write!(
+            body,
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        )
+        .unwrap();
+    }
+    write!(
+        body,
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"mod.gz\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n"
+    )
+    .unwrap();
+    body.extend_from_slice(file);
+    write!(body, "\r\n--{BOUNDARY}--\r\n").unwrap();
+    body
+}
+
+fn upload_request(fields: &[(&str, &str)], file: &[u8]) -> warp::test::RequestBuilder {
+    warp::test::request()
+        .method("POST")
+        .path("/upload")
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(upload_form(fields, file))
+}
+
+fn json(body: &[u8]) -> Value {
+    serde_json::from_slice(body).unwrap()
+}
+
+#[tokio::test]
+async fn upload_metadata_download_round_trip() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let file = gzip(b"mod contents");
+
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0"),
+            ("tags", "graphics"),
+        ],
+        &file,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mods = json(response.body());
+    let mods = mods.as_array().unwrap();
+    assert_eq!(mods.len(), 1);
+    assert_eq!(mods[0]["id"], "alpha");
+    assert_eq!(mods[0]["title"], "Alpha");
+    assert_eq!(mods[0]["version"], "1.0.0");
+    assert_eq!(mods[0]["tags"], serde_json::json!(["graphics"]));
+    assert_eq!(mods[0]["compression"], "gzip");
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), file.as_slice());
+}
+
+#[tokio::test]
+async fn metadata_filters_by_tag() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, tag) in [("alpha", "graphics"), ("beta", "audio")] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("tags", tag),
+            ],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = warp::test::request()
+        .path("/metadata?tag=audio")
+        .reply(&routes)
+        .await;
+    let mods = json(response.body());
+    let ids: Vec<_> = mods.as_array().unwrap().iter().map(|m| &m["id"]).collect();
+    assert_eq!(ids, ["beta"]);
+}
+
+#[tokio::test]
+async fn zstd_storage_round_trip() {
+    let (mut config, dir) = test_config();
+    config.storage_format = StorageFormat::Zstd;
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"mod contents"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+    assert!(dir.path().join("alpha.zst").exists());
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let decoded = zstd::decode_all(response.body().as_ref()).unwrap();
+    assert_eq!(decoded, b"mod contents");
+}
+
+#[tokio::test]
+async fn recompress_gzip_to_zstd() {
+    let (config, dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"mod contents"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/admin/recompress?to=zstd")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+    assert_eq!(json(response.body())["recompressed"], 1);
+    assert!(!dir.path().join("alpha.gz").exists());
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body())[0]["compression"], "zstd");
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let decoded = zstd::decode_all(response.body().as_ref()).unwrap();
+    assert_eq!(decoded, b"mod contents");
+}
+
+#[tokio::test]
+async fn upload_requires_api_key_when_configured() {
+    let (mut config, _dir) = test_config();
+    config.api_key = Some("secret".to_string());
+    let routes = build_routes(test_db(), Arc::new(config));
+    let fields = [("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")];
+    let file = gzip(b"mod contents");
+
+    let response = upload_request(&fields, &file).reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = upload_request(&fields, &file)
+        .header("x-api-key", "secret")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn error_messages_follow_accept_language() {
+    let (config, _dir) = test_config();
+    let mut service = app_service(test_db(), Arc::new(config));
+    let request = Request::get("/download/missing")
+        .header("accept-language", "es-ES,es;q=0.9,en;q=0.5")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = json(&body);
+    assert_eq!(body["code"], 404);
+    assert!(
+        body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("No encontrado"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn metadata_batch_returns_requested_mods_in_order() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for id in ["alpha", "beta", "gamma"] {
+        let response = upload_request(
+            &[("id", id), ("title", id), ("version", "1.0.0")],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/metadata/batch")
+        .json(&serde_json::json!({ "ids": ["gamma", "missing", "alpha", "gamma"] }))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mods = json(response.body());
+    let ids: Vec<_> = mods.as_array().unwrap().iter().map(|m| &m["id"]).collect();
+    assert_eq!(ids, ["gamma", "alpha"]);
+}
+
+#[tokio::test]
+async fn oversized_file_is_payload_too_large() {
+    let (mut config, _dir) = test_config();
+    let file = gzip(b"mod contents");
+    config.max_file_bytes = file.len() as u64 - 1;
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &file,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(json(response.body())["code"], 413);
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body()), serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn identical_files_are_stored_once() {
+    let (config, dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let file = gzip(b"shared contents");
+    for id in ["alpha", "beta"] {
+        let response = upload_request(&[("id", id), ("title", id), ("version", "1.0.0")], &file)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+    assert!(dir.path().join("alpha.gz").exists());
+    assert!(!dir.path().join("beta.gz").exists());
+
+    // Deleting the mod that owns the file keeps it for the other one.
+    let response = warp::test::request()
+        .method("DELETE")
+        .path("/delete/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = warp::test::request()
+        .path("/download/beta")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), file.as_slice());
+
+    // So does re-uploading it with different bytes.
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "alpha"), ("version", "2.0.0")],
+        &gzip(b"new contents"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = warp::test::request()
+        .path("/download/beta")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), file.as_slice());
+
+    let response = warp::test::request()
+        .method("DELETE")
+        .path("/delete/beta")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!dir.path().join("alpha.gz").exists());
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), gzip(b"new contents").as_slice());
+}
+
+#[tokio::test]
+async fn events_stream_reports_uploads() {
+    let (config, _dir) = test_config();
+    let mut service = app_service(test_db(), Arc::new(config));
+    let request = Request::get("/events").body(Body::empty()).unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut events = response.into_body();
+
+    let form = upload_form(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"mod contents"),
+    );
+    let request = Request::post("/upload")
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .header("content-length", form.len())
+        .body(Body::from(form))
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let chunk = events.data().await.unwrap().unwrap();
+    let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(chunk.contains("event:upserted"), "{chunk}");
+    assert!(
+        chunk.contains(r#"{"type":"upserted","id":"alpha"}"#),
+        "{chunk}"
+    );
+}
+
+#[tokio::test]
+async fn cors_origin_allowlist() {
+    let (mut config, _dir) = test_config();
+    config.restrict_cors_origins(&["https://launcher.example.com".to_string()]);
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = warp::test::request()
+        .path("/metadata")
+        .header("origin", "https://launcher.example.com")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "https://launcher.example.com"
+    );
+
+    let response = warp::test::request()
+        .method("OPTIONS")
+        .path("/delete/alpha")
+        .header("origin", "https://other.example.com")
+        .header("access-control-request-method", "DELETE")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn setup_db_upgrades_unversioned_database() {
+    let db = open_memory_database().unwrap();
+    let conn = db.get().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE mods (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            version TEXT NOT NULL,
+            thumbnail TEXT NOT NULL,
+            file_path TEXT NOT NULL
+        );
+        INSERT INTO mods VALUES ('alpha', 'Alpha', '1.0.0', '', 'mods/alpha.gz');",
+    )
+    .unwrap();
+
+    setup_db(&conn).unwrap();
+    setup_db(&conn).unwrap();
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap();
+    assert!(version >= 1);
+    drop(conn);
+
+    let (config, _dir) = test_config();
+    let routes = build_routes(db, Arc::new(config));
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mods = json(response.body());
+    assert_eq!(mods[0]["id"], "alpha");
+    assert_eq!(mods[0]["author"], "");
+    assert_eq!(mods[0]["compression"], "gzip");
+}
+
+#[tokio::test]
+async fn export_archive_restores_into_empty_server() {
+    let (config, _dir) = test_config();
+    let source = build_routes(test_db(), Arc::new(config));
+    let file = gzip(b"mod contents");
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0"),
+            ("tags", "graphics"),
+        ],
+        &file,
+    )
+    .reply(&source)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .path("/admin/export")
+        .header("x-admin-key", "admin")
+        .reply(&source)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/x-tar");
+    let archive = response.body().clone();
+
+    let (config, _dir) = test_config();
+    let target = build_routes(test_db(), Arc::new(config));
+    let import = |query: &str| {
+        warp::test::request()
+            .method("POST")
+            .path(&format!("/admin/import{query}"))
+            .header("x-admin-key", "admin")
+            .body(archive.clone())
+    };
+
+    let response = import("").reply(&target).await;
+    assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+    assert_eq!(
+        json(response.body())["imported"],
+        serde_json::json!(["alpha"])
+    );
+
+    let response = warp::test::request().path("/metadata").reply(&target).await;
+    let mods = json(response.body());
+    assert_eq!(mods[0]["title"], "Alpha");
+    assert_eq!(mods[0]["tags"], serde_json::json!(["graphics"]));
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&target)
+        .await;
+    assert_eq!(response.body().as_ref(), file.as_slice());
+
+    let response = import("").reply(&target).await;
+    assert_eq!(
+        json(response.body())["skipped"],
+        serde_json::json!(["alpha"])
+    );
+    let response = import("?overwrite=true").reply(&target).await;
+    assert_eq!(
+        json(response.body())["imported"],
+        serde_json::json!(["alpha"])
+    );
+}
+
+#[tokio::test]
+async fn content_range_upload_assembles_two_chunks() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"old contents"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let file = gzip(b"assembled from two chunks");
+    let (first, second) = file.split_at(file.len() / 2);
+    let put = |start: usize, chunk: &[u8]| {
+        warp::test::request()
+            .method("PUT")
+            .path("/download/alpha")
+            .header(
+                "content-range",
+                format!("bytes {}-{}/{}", start, start + chunk.len() - 1, file.len()),
+            )
+            .body(chunk)
+    };
+
+    let response = put(first.len(), second).reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(json(response.body())["complete"], false);
+    let response = put(0, first).reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+    assert_eq!(json(response.body())["complete"], true);
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), file.as_slice());
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods[0]["size_bytes"], file.len());
+    assert!(mods[0]["sha256"].is_string());
+
+    // A file that isn't compressed is refused once complete.
+    let response = warp::test::request()
+        .method("PUT")
+        .path("/download/alpha")
+        .header("content-range", "bytes 0-4/5")
+        .body("plain")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), file.as_slice());
+}
+
+#[tokio::test]
+async fn gzip_metadata_cache_matches_fresh_listing_and_busts_on_upload() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let gzipped_listing = || async {
+        let response = warp::test::request()
+            .path("/metadata")
+            .header("accept-encoding", "gzip")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        response.body().clone()
+    };
+    let fresh_listing = || async {
+        let response = warp::test::request().path("/metadata").reply(&routes).await;
+        json(response.body())
+    };
+    let upload = |id: &'static str, contents: &'static [u8]| {
+        upload_request(
+            &[("id", id), ("title", id), ("version", "1.0.0")],
+            &gzip(contents),
+        )
+        .reply(&routes)
+    };
+
+    assert_eq!(
+        upload("alpha", b"alpha").await.status(),
+        StatusCode::CREATED
+    );
+    let cached = gzipped_listing().await;
+    assert_eq!(gzipped_listing().await, cached);
+    assert_eq!(json(&gunzip(&cached)), fresh_listing().await);
+
+    assert_eq!(upload("beta", b"beta").await.status(), StatusCode::CREATED);
+    let listing = json(&gunzip(&gzipped_listing().await));
+    assert_eq!(listing.as_array().unwrap().len(), 2);
+    assert_eq!(listing, fresh_listing().await);
+
+    // So does completing a content-range upload.
+    let file = gzip(b"new alpha");
+    let response = warp::test::request()
+        .method("PUT")
+        .path("/download/alpha")
+        .header(
+            "content-range",
+            format!("bytes 0-{}/{}", file.len() - 1, file.len()),
+        )
+        .body(file.clone())
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listing = json(&gunzip(&gzipped_listing().await));
+    assert_eq!(listing, fresh_listing().await);
+    assert_eq!(listing[0]["size_bytes"], file.len());
+}
+
+#[tokio::test]
+async fn lazily_filled_sizes_reach_the_cached_listing() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let file = gzip(b"mod contents");
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &file,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    db.get()
+        .unwrap()
+        .execute("UPDATE mods SET size_bytes = NULL", [])
+        .unwrap();
+    let cached_size = || async {
+        let response = warp::test::request()
+            .path("/metadata")
+            .header("accept-encoding", "gzip")
+            .reply(&routes)
+            .await;
+        json(&gunzip(response.body()))[0]["size_bytes"].clone()
+    };
+    assert_eq!(cached_size().await, Value::Null);
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/update-size")
+        .json(&serde_json::json!({ "mods": [{ "id": "alpha", "have_version": "0.9.0" }] }))
+        .reply(&routes)
+        .await;
+    assert_eq!(json(response.body())["total_bytes"], file.len());
+    assert_eq!(cached_size().await, file.len());
+}
+
+#[test]
+fn setup_db_replays_only_pending_migrations() {
+    let db = test_db();
+    let conn = db.get().unwrap();
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap();
+    let table_exists = |name: &str| -> bool {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = ?1)",
+            [name],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+
+    // The search index is the third to last migration; reservations came
+    // earlier.
+    conn.execute_batch(&format!(
+        "DROP TABLE mods_fts; DROP TABLE reservations; PRAGMA user_version = {};",
+        version - 3
+    ))
+    .unwrap();
+    setup_db(&conn).unwrap();
+
+    assert!(table_exists("mods_fts"));
+    assert!(!table_exists("reservations"));
+    let upgraded: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(upgraded, version);
+}
+
+/// Rebuilds an export archive with `catalog` and the given files.
+fn catalog_archive(catalog: &Value, files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let catalog = serde_json::to_vec(catalog).unwrap();
+    let entries =
+        std::iter::once(("catalog.json", catalog.as_slice())).chain(files.iter().copied());
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[tokio::test]
+async fn import_overwrite_keeps_live_files_until_committed() {
+    let (config, _source_dir) = test_config();
+    let source = build_routes(test_db(), Arc::new(config));
+    let exported_file = gzip(b"exported");
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &exported_file,
+    )
+    .reply(&source)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = warp::test::request()
+        .path("/admin/export")
+        .header("x-admin-key", "admin")
+        .reply(&source)
+        .await;
+    let archive = response.body().clone();
+
+    let (mut config, dir) = test_config();
+    config.max_import_bytes = archive.len() as u64 + 1024;
+    let target = build_routes(test_db(), Arc::new(config));
+    let live_file = gzip(b"live");
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &live_file,
+    )
+    .reply(&target)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let import = |body: Vec<u8>| {
+        warp::test::request()
+            .method("POST")
+            .path("/admin/import?overwrite=true")
+            .header("x-admin-key", "admin")
+            .body(body)
+            .reply(&target)
+    };
+    let download = || async {
+        warp::test::request()
+            .path("/download/alpha")
+            .reply(&target)
+            .await
+            .body()
+            .clone()
+    };
+
+    // An uncompressed file fails the import and leaves the live file alone.
+    let mut catalog = json(
+        &tar::Archive::new(archive.as_ref())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .find_map(|mut entry| {
+                let is_catalog = entry.path().unwrap().to_str() == Some("catalog.json");
+                is_catalog.then(|| {
+                    let mut data = Vec::new();
+                    std::io::Read::read_to_end(&mut entry, &mut data).unwrap();
+                    data
+                })
+            })
+            .unwrap(),
+    );
+    catalog[0]["sha256"] = Value::Null;
+    let response = import(catalog_archive(&catalog, &[("files/alpha.gz", b"plain")])).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(download().await.as_ref(), live_file.as_slice());
+
+    let response = import(vec![0; archive.len() + 2048]).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // The restored file gets a fresh name and the replaced one goes once
+    // nothing points at it.
+    let response = import(archive.to_vec()).await;
+    assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+    assert_eq!(download().await.as_ref(), exported_file.as_slice());
+    assert!(!dir.path().join("alpha.gz").exists());
+    assert!(dir.path().join("alpha-1.gz").exists());
+}
+
+#[tokio::test]
+async fn quarantined_upload_is_listed_once_approved() {
+    let (mut config, _dir) = test_config();
+    config.quarantine_uploads = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+    let file = gzip(b"held contents");
+
+    let response = upload_request(
+        &[("id", "held"), ("title", "Held"), ("version", "1.0.0")],
+        &file,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body()), serde_json::json!([]));
+    let response = warp::test::request()
+        .path("/download/held")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = warp::test::request()
+        .path("/search/full?q=held")
+        .reply(&routes)
+        .await;
+    assert_eq!(json(response.body()), serde_json::json!([]));
+    let response = warp::test::request()
+        .path("/mods?q=held")
+        .reply(&routes)
+        .await;
+    assert_eq!(json(response.body())["total"], 0);
+    for path in ["/readme/held", "/thumbnail/held", "/mods/held/versions"] {
+        let response = warp::test::request().path(path).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+    }
+    let modpack_check = || {
+        warp::test::request()
+            .method("POST")
+            .path("/modpack/check")
+            .json(&serde_json::json!({ "mods": [{ "id": "held", "version": "0.1.0" }] }))
+            .reply(&routes)
+    };
+    let response = modpack_check().await;
+    assert_eq!(json(response.body())["mods"][0]["status"], "unknown");
+
+    let response = warp::test::request()
+        .path("/admin/pending")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = warp::test::request()
+        .path("/admin/pending")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    let pending = json(response.body());
+    assert_eq!(pending[0]["id"], "held");
+    assert_eq!(pending[0]["scan"]["status"], "complete");
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/admin/approve/held")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods.as_array().unwrap().len(), 1);
+    assert_eq!(mods[0]["id"], "held");
+    let response = warp::test::request()
+        .path("/download/held")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), file.as_slice());
+    let response = warp::test::request()
+        .path("/readme/held")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = modpack_check().await;
+    assert_eq!(
+        json(response.body())["mods"][0]["status"],
+        "update_available"
+    );
+    let response = warp::test::request()
+        .header("x-admin-key", "admin")
+        .path("/admin/pending")
+        .reply(&routes)
+        .await;
+    assert_eq!(json(response.body()), serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn rejected_upload_is_removed() {
+    let (mut config, dir) = test_config();
+    config.quarantine_uploads = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = upload_request(
+        &[("id", "spam"), ("title", "Spam"), ("version", "1.0.0")],
+        &gzip(b"spam"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/admin/reject/spam")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    // No tombstone is left, so the id is free again.
+    let response = warp::test::request()
+        .path("/download/spam")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = warp::test::request()
+        .method("POST")
+        .path("/admin/approve/spam")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn rejected_update_keeps_the_approved_revision() {
+    let (mut config, dir) = test_config();
+    config.quarantine_uploads = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+    let approved = gzip(b"approved contents");
+    let moderate = |action: &str| {
+        warp::test::request()
+            .method("POST")
+            .path(&format!("/admin/{}/alpha", action))
+            .header("x-admin-key", "admin")
+            .reply(&routes)
+    };
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &approved,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(moderate("approve").await.status(), StatusCode::OK);
+
+    // The update waits apart while the approved revision stays listed.
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha Two"),
+            ("version", "2.0.0"),
+        ],
+        &gzip(b"held contents"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body())[0]["version"], "1.0.0");
+    let response = warp::test::request()
+        .path("/admin/pending")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(json(response.body())[0]["version"], "2.0.0");
+
+    assert_eq!(moderate("reject").await.status(), StatusCode::OK);
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods.as_array().unwrap().len(), 1);
+    assert_eq!(mods[0]["title"], "Alpha");
+    assert_eq!(mods[0]["version"], "1.0.0");
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), approved.as_slice());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    assert_eq!(moderate("reject").await.status(), StatusCode::NOT_FOUND);
+
+    // Approving an update swaps it in for the listed revision.
+    let update = gzip(b"updated contents");
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha Three"),
+            ("version", "3.0.0"),
+        ],
+        &update,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(moderate("approve").await.status(), StatusCode::OK);
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body())[0]["title"], "Alpha Three");
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), update.as_slice());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn concurrent_refetches_share_one_source_fetch() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let upstream = {
+        let fetches = fetches.clone();
+        warp::path!("mod.gz").then(move || {
+            let fetches = fetches.clone();
+            async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                // Keeps the fetch in flight while the other refetches arrive.
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                gzip(b"fetched contents")
+            }
+        })
+    };
+    let (addr, server) = warp::serve(upstream).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let source_url = format!("http://{addr}/mod.gz");
+    let response = upload_request(
+        &[
+            ("id", "remote"),
+            ("title", "Remote"),
+            ("version", "1.0.0"),
+            ("source_url", &source_url),
+        ],
+        &gzip(b"uploaded contents"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let refetches = (0..5).map(|_| {
+        warp::test::request()
+            .method("POST")
+            .path("/mods/remote/refetch")
+            .header("x-admin-key", "admin")
+            .reply(&routes)
+    });
+    let responses = futures::future::join_all(refetches).await;
+    for response in &responses {
+        assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+        assert_eq!(json(response.body())["changed"], true);
+    }
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    let response = warp::test::request()
+        .path("/download/remote")
+        .reply(&routes)
+        .await;
+    assert_eq!(gunzip(response.body()), b"fetched contents");
+}
+
+#[tokio::test]
+async fn readme_round_trips_and_renders_without_scripts() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let readme = "# Alpha\n\nSome *notes*.\n\n<script>alert(1)</script>";
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0"),
+            ("readme", readme),
+        ],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .path("/readme/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), readme.as_bytes());
+
+    let response = warp::test::request()
+        .path("/readme/alpha?format=html")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/html; charset=utf-8"
+    );
+    let html = String::from_utf8(response.body().to_vec()).unwrap();
+    assert!(html.contains("<h1>Alpha</h1>"), "{html}");
+    assert!(html.contains("<em>notes</em>"), "{html}");
+    assert!(!html.contains("<script"), "{html}");
+}
+
+#[tokio::test]
+async fn disabled_upload_route_is_not_mounted() {
+    let (mut config, _dir) = test_config();
+    config.enable_upload = false;
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let routes_listed = json(response.body())["routes"].clone();
+    assert!(
+        !routes_listed
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("POST /upload"))
+    );
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn setup_reset_requires_confirmation_and_admin_key() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let listed = |routes| async move {
+        let response = warp::test::request().path("/metadata").reply(routes).await;
+        json(response.body()).as_array().unwrap().len()
+    };
+
+    let response = warp::test::request().path("/setup").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(listed(&routes).await, 1);
+
+    let response = warp::test::request()
+        .path("/setup?reset=true")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = warp::test::request()
+        .path("/setup?reset=true&confirm=true")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(listed(&routes).await, 1);
+
+    let response = warp::test::request()
+        .path("/setup?reset=true&confirm=true")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(listed(&routes).await, 0);
+}
+
+/// Writes a shell script that records the arguments it was run with in
+/// `args.txt` beside it, and returns its path and that of the record.
+fn recording_command(dir: &std::path::Path) -> (String, std::path::PathBuf) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = dir.join("hook.sh");
+    let record = dir.join("args.txt");
+    std::fs::write(
+        &script,
+        format!("#!/bin/sh\necho \"$@\" > '{}'\n", record.display()),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    (script.display().to_string(), record)
+}
+
+#[tokio::test]
+async fn post_upload_command_receives_id_and_path() {
+    let (mut config, dir) = test_config();
+    let hook_dir = tempfile::tempdir().unwrap();
+    let (script, record) = recording_command(hook_dir.path());
+    config.post_upload_command = Some(format!("{script} --mod {{id}} {{path}}"));
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let args = std::fs::read_to_string(record).unwrap();
+    let stored = dir.path().join("alpha.gz");
+    assert_eq!(args.trim(), format!("--mod alpha {}", stored.display()));
+}
+
+/// Inserts `count` mods named `mod-0000`, `mod-0001`, … straight into the
+/// database, without files.
+fn seed_mods(db: &DbConnection, count: usize) {
+    db.get()
+        .unwrap()
+        .execute(
+            "WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i + 1 < ?1)
+            INSERT INTO mods (id, title, version, thumbnail, file_path)
+            SELECT printf('mod-%04d', i), printf('Mod %d', i), '1.0.0', '', '' FROM n",
+            [count as i64],
+        )
+        .unwrap();
+}
+
+#[tokio::test]
+async fn streamed_metadata_parses_with_every_row() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    seed_mods(&db, 2000);
+    let routes = build_routes(db, Arc::new(config));
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // Large enough to have been sent in several flushed batches.
+    assert!(response.body().len() > 200_000, "{}", response.body().len());
+    let mods = json(response.body());
+    let mods = mods.as_array().unwrap();
+    assert_eq!(mods.len(), 2000);
+    assert_eq!(mods[1999]["id"], "mod-1999");
+}
+
+#[tokio::test]
+async fn capabilities_reflect_the_configuration() {
+    let (mut config, _dir) = test_config();
+    config.enable_upload = false;
+    config.max_file_bytes = 4096;
+    config.post_upload_command = Some("true".to_string());
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = warp::test::request()
+        .path("/capabilities")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let capabilities = json(response.body());
+    assert_eq!(capabilities["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(capabilities["routes"]["upload"], false);
+    assert_eq!(capabilities["routes"]["metadata"], true);
+    assert_eq!(capabilities["features"]["admin"], true);
+    assert_eq!(capabilities["features"]["post_upload_command"], true);
+    assert_eq!(capabilities["limits"]["max_upload_bytes"], 4096);
+}
+
+#[tokio::test]
+async fn search_ignores_case_and_accents() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, title) in [("poke", "Pokémon Textures"), ("other", "Other")] {
+        let response = upload_request(
+            &[("id", id), ("title", title), ("version", "1.0.0")],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    for query in ["pokemon", "POKEMON", "pok%C3%A9mon"] {
+        let response = warp::test::request()
+            .path(&format!("/metadata?q={query}"))
+            .reply(&routes)
+            .await;
+        let mods = json(response.body());
+        let ids = mods
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|metadata| metadata["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["poke"], "{query}");
+    }
+}
+
+#[tokio::test]
+async fn metadata_export_contains_every_row() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    seed_mods(&db, 25);
+    let routes = build_routes(db, Arc::new(config));
+
+    let response = warp::test::request()
+        .path("/admin/export-metadata?format=json")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = warp::test::request()
+        .path("/admin/export-metadata?format=json")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut ids = json(response.body())
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|metadata| metadata["id"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    ids.sort();
+    let expected = (0..25).map(|i| format!("mod-{i:04}")).collect::<Vec<_>>();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn backup_cycle_writes_a_restorable_copy() {
+    let db = test_db();
+    seed_mods(&db, 3);
+    let backup_dir = tempfile::tempdir().unwrap();
+
+    let mut written = Vec::new();
+    for _ in 0..3 {
+        written.push(backup_database(&db.get().unwrap(), backup_dir.path(), 2).unwrap());
+        // Backups are named by the millisecond they were taken.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+
+    let mut kept = std::fs::read_dir(backup_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    kept.sort();
+    assert_eq!(kept, written[1..]);
+    let backup = rusqlite::Connection::open(&written[2]).unwrap();
+    let count: i64 = backup
+        .query_row("SELECT COUNT(*) FROM mods", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 3);
+}
+
+#[tokio::test]
+async fn merge_patch_clears_description_and_updates_title() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0"),
+            ("description", "Old description"),
+            ("author", "Ann"),
+        ],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .method("PATCH")
+        .path("/metadata/alpha")
+        .header("content-type", "application/merge-patch+json")
+        .body(r#"{"title": "Alpha Remastered", "description": null}"#)
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods[0]["title"], "Alpha Remastered");
+    assert_eq!(mods[0]["description"], "");
+    assert_eq!(mods[0]["author"], "Ann");
+    assert_eq!(mods[0]["version"], "1.0.0");
+}
+
+#[tokio::test]
+async fn full_search_matches_description_only_terms() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, description) in [("alpha", "Adds volumetric clouds"), ("beta", "Adds rain")] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("description", description),
+            ],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = warp::test::request()
+        .path("/search/full?q=volumetric")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mods = json(response.body());
+    assert_eq!(mods.as_array().unwrap().len(), 1);
+    assert_eq!(mods[0]["id"], "alpha");
+}
+
+#[tokio::test]
+async fn full_search_ranks_title_over_author_over_description() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    seed_mods(&db, 5000);
+    {
+        let conn = db.get().unwrap();
+        conn.execute_batch(
+            "UPDATE mods SET title = 'Dragon Armor' WHERE id = 'mod-4000';
+            UPDATE mods SET author = 'Dragon Forge' WHERE id = 'mod-1000';
+            UPDATE mods SET description = 'Armor for a dragon' WHERE id = 'mod-2000';
+            INSERT INTO mods_fts (mod_id, title, author, description, tags)
+            SELECT id, title, author, description, '' FROM mods;",
+        )
+        .unwrap();
+    }
+    let routes = build_routes(db, Arc::new(config));
+
+    let started = std::time::Instant::now();
+    let response = warp::test::request()
+        .path("/search/full?q=dragon")
+        .reply(&routes)
+        .await;
+    let elapsed = started.elapsed();
+    assert_eq!(response.status(), StatusCode::OK);
+    let ids = json(response.body())
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|metadata| metadata["id"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["mod-4000", "mod-1000", "mod-2000"]);
+    assert!(
+        elapsed < std::time::Duration::from_millis(500),
+        "{elapsed:?}"
+    );
+}
+
+#[tokio::test]
+async fn deleted_mod_answers_gone() {
+    let (config, dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .method("DELETE")
+        .path("/delete/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!dir.path().join("alpha.gz").exists());
+
+    for path in ["/download/alpha", "/readme/alpha", "/mods/alpha/versions"] {
+        let response = warp::test::request().path(path).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::GONE, "{path}");
+    }
+    let response = warp::test::request()
+        .method("DELETE")
+        .path("/delete/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = warp::test::request()
+        .path("/download/never-existed")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn overlong_titles_are_rejected_or_truncated_per_mode() {
+    let title = "A title that is too long";
+    for (mode, expected) in [
+        (OverlongFieldMode::Reject, None),
+        (OverlongFieldMode::Truncate, Some("A title…")),
+    ] {
+        let (mut config, dir) = test_config();
+        config.max_title_length = 8;
+        config.overlong_fields = mode;
+        let routes = build_routes(test_db(), Arc::new(config));
+
+        let response = upload_request(
+            &[("id", "alpha"), ("title", title), ("version", "1.0.0")],
+            &gzip(b"alpha"),
+        )
+        .reply(&routes)
+        .await;
+        match expected {
+            None => {
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+                assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+            }
+            Some(truncated) => {
+                assert_eq!(response.status(), StatusCode::CREATED);
+                assert_eq!(json(response.body())["title"], truncated);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn download_redirects_to_the_cdn_when_configured() {
+    let (mut config, _dir) = test_config();
+    config.download_redirect_base = Some("https://cdn.example.com/mods".to_string());
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(
+        response.headers()["location"],
+        "https://cdn.example.com/mods/alpha.gz"
+    );
+    assert!(response.body().is_empty());
+
+    // Kept versions live in the mod's directory, which the URL keeps.
+    let (mut config, _dir) = test_config();
+    config.download_redirect_base = Some("https://cdn.example.com/mods".to_string());
+    config.keep_versions = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0+build.1"),
+        ],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(
+        response.headers()["location"],
+        "https://cdn.example.com/mods/alpha/1.0.0%2Bbuild.1.gz"
+    );
+}
+
+#[tokio::test]
+async fn modpack_check_reports_available_updates() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, version) in [
+        ("alpha", "2.0.0"),
+        ("beta", "1.1.0"),
+        ("gamma", "1.0.0"),
+        ("epsilon", "1.0.0"),
+        ("zeta", "nightly-42"),
+    ] {
+        let response = upload_request(
+            &[("id", id), ("title", id), ("version", version)],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/modpack/check")
+        .json(&serde_json::json!({
+            "mods": [
+                { "id": "alpha", "version": "1.0.0" },
+                { "id": "beta", "version": "1.0.0" },
+                { "id": "gamma", "version": "1.0.0" },
+                { "id": "delta", "version": "1.0.0" },
+                { "id": "epsilon", "version": "1.2.0" },
+                { "id": "zeta", "version": "nightly-41" },
+            ]
+        }))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let check = json(response.body());
+    assert_eq!(
+        check["summary"],
+        serde_json::json!({ "total": 6, "up_to_date": 2, "updates_available": 3, "unknown": 1 })
+    );
+    let statuses = check["mods"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|status| {
+            (
+                status["id"].as_str().unwrap(),
+                status["status"].as_str().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        [
+            ("alpha", "update_available"),
+            ("beta", "update_available"),
+            ("gamma", "up_to_date"),
+            ("delta", "unknown"),
+            // Installed ahead of the server, as after a local build.
+            ("epsilon", "up_to_date"),
+            ("zeta", "update_available"),
+        ]
+    );
+    assert_eq!(check["mods"][0]["latest_version"], "2.0.0");
+}
+
+#[tokio::test]
+async fn audit_log_records_upload_and_delete() {
+    let (mut config, _dir) = test_config();
+    config.audit_log = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = warp::test::request()
+        .method("DELETE")
+        .path("/delete/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = warp::test::request()
+        .path("/admin/audit")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries = json(response.body());
+    let mut actions = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            assert_eq!(entry["target_id"], "alpha");
+            entry["action"].as_str().unwrap().to_string()
+        })
+        .collect::<Vec<_>>();
+    actions.sort();
+    assert_eq!(actions, ["create", "delete"]);
+}
+
+#[tokio::test]
+async fn async_post_upload_command_answers_accepted_then_completes() {
+    let (mut config, _dir) = test_config();
+    let hook_dir = tempfile::tempdir().unwrap();
+    let (script, record) = recording_command(hook_dir.path());
+    config.post_upload_command = Some(format!("{script} {{id}}"));
+    config.post_upload_command_async = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()["location"], "/upload/alpha/status");
+    assert_eq!(json(response.body())["status"], "processing");
+
+    let mut status = Value::Null;
+    for _ in 0..100 {
+        let response = warp::test::request()
+            .path("/upload/alpha/status")
+            .reply(&routes)
+            .await;
+        status = json(response.body());
+        if status["status"] != "processing" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, serde_json::json!({ "status": "complete" }));
+    assert_eq!(std::fs::read_to_string(record).unwrap().trim(), "alpha");
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[tokio::test]
+async fn popular_ranks_downloads_within_the_window() {
+    const DAY: i64 = 24 * 60 * 60;
+    let (config, _dir) = test_config();
+    let db = test_db();
+    seed_mods(&db, 3);
+    {
+        let conn = db.get().unwrap();
+        let now = unix_now();
+        // mod-0000 was busiest a month ago; within the last week mod-0001 leads.
+        let downloads = [
+            ("mod-0000", 30, 10),
+            ("mod-0000", 2, 1),
+            ("mod-0001", 1, 3),
+            ("mod-0001", 6, 1),
+            ("mod-0002", 3, 2),
+        ];
+        for (id, days_ago, count) in downloads {
+            for _ in 0..count {
+                conn.execute(
+                    "INSERT INTO download_log (mod_id, downloaded_at) VALUES (?1, ?2)",
+                    rusqlite::params![id, now - days_ago * DAY],
+                )
+                .unwrap();
+            }
+        }
+    }
+    let routes = build_routes(db, Arc::new(config));
+
+    let ranking = |window: &'static str| {
+        let routes = routes.clone();
+        async move {
+            let response = warp::test::request()
+                .path(&format!("/stats/popular?window={window}"))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            json(response.body())
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| {
+                    (
+                        entry["id"].as_str().unwrap().to_string(),
+                        entry["downloads"].as_u64().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+    let owned = |ranking: &[(&str, u64)]| {
+        ranking
+            .iter()
+            .map(|(id, downloads)| (id.to_string(), *downloads))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ranking("7d").await,
+        owned(&[("mod-0001", 4), ("mod-0002", 2), ("mod-0000", 1)])
+    );
+    assert_eq!(
+        ranking("60d").await,
+        owned(&[("mod-0000", 11), ("mod-0001", 4), ("mod-0002", 2)])
+    );
+    assert_eq!(ranking("12h").await, owned(&[]));
+}
+
+#[tokio::test]
+async fn stalled_request_headers_close_the_connection() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut config, _dir) = test_config();
+    config.header_read_timeout = std::time::Duration::from_millis(200);
+    let (addr, server) = bind_server(([127, 0, 0, 1], 0).into(), test_db(), Arc::new(config));
+    tokio::spawn(server);
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /health HTTP/1.1\r\n").await.unwrap();
+    let started = std::time::Instant::now();
+    let mut received = Vec::new();
+    let read = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        stream.read_to_end(&mut received),
+    )
+    .await
+    .expect("the stalled connection was left open");
+    // Closed without an answer, or reset, once the timeout passes.
+    assert!(read.is_err() || received.is_empty(), "{received:?}");
+    assert!(started.elapsed() >= std::time::Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn related_mods_rank_by_shared_tags_and_author() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, tags, author) in [
+        ("base", "a,b,c", "Ann"),
+        ("two-tags", "a,b", "Bob"),
+        ("tag-and-author", "a", "Ann"),
+        ("one-tag", "c,z", "Cid"),
+        ("unrelated", "z", "Dee"),
+    ] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("tags", tags),
+                ("author", author),
+            ],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = warp::test::request()
+        .path("/mods/base/related")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let related = json(response.body())
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["id"].as_str().unwrap().to_string(),
+                entry["shared_tags"].as_u64().unwrap(),
+                entry["same_author"].as_bool().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        related,
+        [
+            ("two-tags".to_string(), 2, false),
+            ("tag-and-author".to_string(), 1, true),
+            ("one-tag".to_string(), 1, false),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn game_version_filter_excludes_mods_needing_a_newer_build() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, min_game_version) in [("old", "1.2.0"), ("new", "2.0.0"), ("any", "")] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("min_game_version", min_game_version),
+            ],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(
+            response.status(),
+            StatusCode::CREATED,
+            "{:?}",
+            response.body()
+        );
+    }
+
+    let response = warp::test::request()
+        .path("/metadata?game_version=1.5.0")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut ids = json(response.body())
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|metadata| metadata["id"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, ["any", "old"]);
+
+    let response = upload_request(
+        &[
+            ("id", "bad"),
+            ("title", "bad"),
+            ("version", "1.0.0"),
+            ("min_game_version", "not a version"),
+        ],
+        &gzip(b"bad"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unknown_path_points_at_the_docs() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let response = warp::test::request()
+        .path("/no/such/route")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = json(response.body());
+    assert_eq!(body["code"], 404);
+    assert_eq!(body["docs"], "/capabilities");
+    let routes_listed = body["routes"].as_array().unwrap();
+    for route in ["GET /metadata", "POST /upload", "GET /download/{id}"] {
+        assert!(
+            routes_listed.contains(&serde_json::json!(route)),
+            "{route} missing from {routes_listed:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn repeated_idempotency_key_replays_the_first_upload() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+
+    let first = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"first"),
+    )
+    .header("idempotency-key", "retry-1")
+    .reply(&routes)
+    .await;
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert!(first.headers().get("idempotent-replay").is_none());
+
+    let second = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "2.0.0")],
+        &gzip(b"second"),
+    )
+    .header("idempotency-key", "retry-1")
+    .reply(&routes)
+    .await;
+    assert_eq!(second.status(), StatusCode::CREATED);
+    assert_eq!(second.headers()["idempotent-replay"], "true");
+    assert_eq!(second.body(), first.body());
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(gunzip(response.body()), b"first");
+}
+
+#[tokio::test]
+async fn log_stream_follows_new_lines() {
+    // The only test that installs the global subscriber.
+    init_tracing();
+    let (config, _dir) = test_config();
+    let mut service = app_service(test_db(), Arc::new(config));
+    let request = Request::get("/admin/logs/stream")
+        .header("x-admin-key", "admin")
+        .body(Body::empty())
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut logs = response.into_body();
+
+    let form = upload_form(
+        &[("id", "logged"), ("title", "Logged"), ("version", "1.0.0")],
+        &gzip(b"logged"),
+    );
+    let request = Request::post("/upload")
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .header("content-length", form.len())
+        .body(Body::from(form))
+        .unwrap();
+    let response = service.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let mut streamed = String::new();
+    while !streamed.contains("Created mod logged version 1.0.0") {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), logs.data())
+            .await
+            .unwrap_or_else(|_| panic!("no upload line in {streamed}"))
+            .unwrap()
+            .unwrap();
+        streamed.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+}
+
+#[tokio::test]
+async fn unhashed_download_gets_a_weak_etag_that_revalidates() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    // Files stored before checksums were recorded have no hash to tag with.
+    db.get()
+        .unwrap()
+        .execute("UPDATE mods SET sha256 = NULL", [])
+        .unwrap();
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert!(etag.starts_with("W/\""), "{etag}");
+
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .header("if-none-match", &etag)
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert!(response.body().is_empty());
+}
+
+/// Polls `/metadata` until its `x-degraded` header matches `degraded`.
+async fn wait_for_degraded<F>(routes: &F, degraded: bool) -> warp::http::Response<bytes::Bytes>
+where
+    F: warp::Filter + Clone + 'static,
+    F::Extract: warp::Reply + Send,
+{
+    for _ in 0..100 {
+        let response = warp::test::request().path("/metadata").reply(routes).await;
+        if response.headers().contains_key("x-degraded") == degraded {
+            return response;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("degraded mode never became {degraded}");
+}
+
+#[tokio::test]
+async fn database_failure_serves_cached_metadata_until_it_recovers() {
+    let (mut config, _dir) = test_config();
+    config.degrade_after_failures = 2;
+    config.db_probe_interval = std::time::Duration::from_millis(20);
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    // Let a probe snapshot the listing with the upload in it.
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    db.get()
+        .unwrap()
+        .execute("ALTER TABLE mods RENAME TO mods_lost", [])
+        .unwrap();
+    let response = wait_for_degraded(&routes, true).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response.body())[0]["id"], "alpha");
+    let response = upload_request(
+        &[("id", "beta"), ("title", "Beta"), ("version", "1.0.0")],
+        &gzip(b"beta"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    db.get()
+        .unwrap()
+        .execute("ALTER TABLE mods_lost RENAME TO mods", [])
+        .unwrap();
+    let response = wait_for_degraded(&routes, false).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response.body())[0]["id"], "alpha");
+}
+
+/// A gzipped tarball holding the given entries.
+fn mod_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, *data).unwrap();
+    }
+    gzip(&builder.into_inner().unwrap())
+}
+
+#[tokio::test]
+async fn archives_without_the_required_entry_are_rejected() {
+    let (mut config, _dir) = test_config();
+    config.required_archive_entry = Some("mod.json".to_string());
+    let routes = build_routes(test_db(), Arc::new(config));
+    let fields = [("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")];
+
+    let response = upload_request(&fields, &mod_archive(&[("data/alpha.bin", b"alpha")]))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let message = json(response.body())["message"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(message.contains("`mod.json`"), "{message}");
+
+    let response = upload_request(
+        &fields,
+        &mod_archive(&[("./mod.json", b"{}"), ("data/alpha.bin", b"alpha")]),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+}
+
+#[tokio::test]
+async fn manifest_fills_in_fields_the_form_leaves_out() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let manifest = br#"{"title": "Manifest Title", "version": "9.9.9", "author": "manifest"}"#;
+
+    let response = upload_request(
+        &[("id", "alpha"), ("version", "1.0.0")],
+        &mod_archive(&[("mod.json", manifest.as_slice())]),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods[0]["title"], "Manifest Title");
+    assert_eq!(mods[0]["author"], "manifest");
+    // Form fields win over the manifest.
+    assert_eq!(mods[0]["version"], "1.0.0");
+}
+
+#[tokio::test]
+async fn write_routes_refuse_an_origin_reads_allow() {
+    let (mut config, _dir) = test_config();
+    config.cors_read_origins = CorsOrigins::Any;
+    config.cors_write_origins = CorsOrigins::Only(vec!["https://admin.example.com".to_string()]);
+    let routes = build_routes(test_db(), Arc::new(config));
+    let origin = "https://launcher.example.com";
+
+    let response = warp::test::request()
+        .path("/metadata")
+        .header("origin", origin)
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        response
+            .headers()
+            .contains_key("access-control-allow-origin")
+    );
+
+    let response = warp::test::request()
+        .method("OPTIONS")
+        .path("/upload")
+        .header("origin", origin)
+        .header("access-control-request-method", "POST")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .header("origin", origin)
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn latest_by_title_picks_the_highest_version() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, title, version) in [
+        ("shaders-old", "Shaders", "1.9.0"),
+        ("shaders-new", "Shaders", "1.10.0"),
+        ("shaders-beta", "shaders", "1.10.0-beta.1"),
+        ("other", "Other", "5.0.0"),
+    ] {
+        let response = upload_request(
+            &[("id", id), ("title", title), ("version", version)],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = warp::test::request()
+        .path("/mods/latest?title=Shaders")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let latest = json(response.body());
+    assert_eq!(latest["id"], "shaders-new");
+    assert_eq!(latest["version"], "1.10.0");
+
+    let response = warp::test::request()
+        .path("/mods/latest?title=Missing")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn kept_versions_download_individually() {
+    let (mut config, _dir) = test_config();
+    config.keep_versions = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+    for version in ["1.0.0", "2.0.0"] {
+        let title = format!("Alpha {}", version);
+        let readme = format!("Notes for {}", version);
+        let response = upload_request(
+            &[
+                ("id", "alpha"),
+                ("title", &title),
+                ("version", version),
+                ("readme", &readme),
+            ],
+            &gzip(version.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert!(response.status().is_success(), "{:?}", response.body());
+    }
+
+    for (path, contents) in [
+        ("/download/alpha/1.0.0", "1.0.0"),
+        ("/download/alpha/2.0.0", "2.0.0"),
+        ("/download/alpha?version=1.0.0", "1.0.0"),
+        ("/download/alpha", "2.0.0"),
+    ] {
+        let response = warp::test::request().path(path).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+        assert_eq!(gunzip(response.body()), contents.as_bytes(), "{path}");
+    }
+
+    let response = warp::test::request()
+        .path("/download/alpha/3.0.0")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Each version keeps the metadata it was uploaded with.
+    let response = warp::test::request()
+        .path("/mods/alpha/versions")
+        .reply(&routes)
+        .await;
+    let versions = json(response.body());
+    let titles = versions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|version| version["title"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["Alpha 2.0.0", "Alpha 1.0.0"]);
+    let response = warp::test::request()
+        .path("/readme/alpha?version=1.0.0")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), b"Notes for 1.0.0");
+    let response = warp::test::request()
+        .path("/readme/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.body().as_ref(), b"Notes for 2.0.0");
+}
+
+#[tokio::test]
+async fn storage_retry_recovers_from_a_transient_failure() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let policy = RetryPolicy {
+        attempts: 3,
+        base_delay: std::time::Duration::from_millis(1),
+    };
+    let calls = AtomicU32::new(0);
+    let flaky = || async {
+        match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Err(std::io::Error::from(std::io::ErrorKind::TimedOut)),
+            _ => Ok("stored"),
+        }
+    };
+    assert_eq!(policy.run("Flaky put", flaky).await.unwrap(), "stored");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // An operation that keeps failing surfaces its last error once the
+    // attempts run out, and permanent errors are not retried at all.
+    let calls = AtomicU32::new(0);
+    let down = || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Err::<(), _>(std::io::Error::from(std::io::ErrorKind::TimedOut))
+    };
+    let error = policy.run("Failing put", down).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    let calls = AtomicU32::new(0);
+    let missing = || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))
+    };
+    assert!(policy.run("Missing get", missing).await.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn verify_files_sorts_matching_outdated_and_unknown() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let mut checksums = Vec::new();
+    for id in ["alpha", "beta"] {
+        let response = upload_request(
+            &[("id", id), ("title", id), ("version", "1.0.0")],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        checksums.push(
+            json(response.body())["sha256"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        );
+    }
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/verify-files")
+        .json(&serde_json::json!({ "files": [
+            { "id": "alpha", "checksum": checksums[0].to_uppercase() },
+            { "id": "beta", "checksum": checksums[0] },
+            { "id": "gamma", "checksum": checksums[1] },
+        ] }))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json(response.body());
+    assert_eq!(
+        report["summary"],
+        serde_json::json!({ "total": 3, "match": 1, "outdated": 1, "unknown": 1 })
+    );
+    let files = report["files"].as_array().unwrap();
+    assert_eq!(files[0]["status"], "match");
+    assert_eq!(files[1]["status"], "outdated");
+    assert_eq!(files[1]["expected_checksum"], checksums[1].as_str());
+    assert_eq!(files[2]["status"], "unknown");
+    assert_eq!(files[2]["expected_checksum"], Value::Null);
+}
+
+#[tokio::test]
+async fn streamed_metadata_uses_chunked_framing() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (config, _dir) = test_config();
+    let db = test_db();
+    seed_mods(&db, 2000);
+    let (addr, server) = bind_server(([127, 0, 0, 1], 0).into(), db, Arc::new(config));
+    tokio::spawn(server);
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metadata HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    let split = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = std::str::from_utf8(&received[..split])
+        .unwrap()
+        .to_ascii_lowercase();
+    assert!(head.starts_with("http/1.1 200"), "{head}");
+    assert!(head.contains("transfer-encoding: chunked"), "{head}");
+    assert!(!head.contains("content-length"), "{head}");
+
+    let mut rest = &received[split + 4..];
+    let mut chunks = 0;
+    let mut body = Vec::new();
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size =
+            usize::from_str_radix(std::str::from_utf8(&rest[..line_end]).unwrap(), 16).unwrap();
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            break;
+        }
+        body.extend_from_slice(&rest[..size]);
+        rest = &rest[size + 2..];
+        chunks += 1;
+    }
+    // Flushed in batches rather than buffered into one frame.
+    assert!(chunks > 1, "{chunks}");
+    assert_eq!(json(&body).as_array().unwrap().len(), 2000);
+}
+
+#[tokio::test]
+async fn reserved_id_only_accepts_the_holders_uploads_until_expiry() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let fields = [("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")];
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/mods/alpha/reserve")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = json(response.body())["token"].as_str().unwrap().to_string();
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/mods/alpha/reserve")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = upload_request(&fields, &gzip(b"racer"))
+        .header("x-reservation-token", "someone-else")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = upload_request(&fields, &gzip(b"holder"))
+        .header("x-reservation-token", &token)
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    db.get()
+        .unwrap()
+        .execute("UPDATE reservations SET expires_at = 0", [])
+        .unwrap();
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "2.0.0")],
+        &gzip(b"anyone"),
+    )
+    .reply(&routes)
+    .await;
+    assert!(response.status().is_success(), "{:?}", response.body());
+}
+
+#[tokio::test]
+async fn uploads_with_too_many_parts_are_rejected() {
+    let (mut config, _dir) = test_config();
+    config.max_multipart_parts = 8;
+    let routes = build_routes(test_db(), Arc::new(config));
+    let mut fields = vec![("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")];
+    fields.extend(std::iter::repeat_n(("padding", "x"), 1000));
+
+    let response = upload_request(&fields, &gzip(b"alpha"))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let message = json(response.body())["message"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(message.contains('8'), "{message}");
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body()), serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn msgpack_metadata_decodes_to_the_json_listing() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0"),
+            ("tags", "graphics,ui"),
+        ],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let listing = json(
+        warp::test::request()
+            .path("/metadata")
+            .reply(&routes)
+            .await
+            .body(),
+    );
+    let response = warp::test::request()
+        .path("/metadata")
+        .header("accept", "application/msgpack")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/msgpack");
+    let decoded: Value = rmp_serde::from_slice(response.body()).unwrap();
+    assert_eq!(decoded, listing);
+    assert_eq!(decoded[0]["tags"], serde_json::json!(["graphics", "ui"]));
+}
+
+#[tokio::test]
+async fn update_size_sums_the_outdated_files() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let mut sizes = std::collections::HashMap::new();
+    for (id, contents) in [
+        ("alpha", b"alpha".repeat(100)),
+        ("beta", b"beta".repeat(10)),
+        ("gamma", b"gamma".repeat(1000)),
+    ] {
+        let response = upload_request(
+            &[("id", id), ("title", id), ("version", "2.0.0")],
+            &gzip(&contents),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        sizes.insert(id, json(response.body())["size_bytes"].as_u64().unwrap());
+    }
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/update-size")
+        .json(&serde_json::json!({ "mods": [
+            { "id": "alpha", "have_version": "1.0.0" },
+            { "id": "beta", "have_version": "2.0.0" },
+            { "id": "gamma", "have_version": null },
+            { "id": "missing", "have_version": "1.0.0" },
+        ] }))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json(response.body());
+    assert_eq!(report["total_bytes"], sizes["alpha"] + sizes["gamma"]);
+    let statuses: Vec<_> = report["mods"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["status"].as_str().unwrap(),
+                entry["bytes"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("update", sizes["alpha"]),
+            ("current", 0),
+            ("update", sizes["gamma"]),
+            ("unknown", 0),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn protected_mod_refuses_deletion_until_unprotected() {
+    let (config, dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "base"), ("title", "Base"), ("version", "1.0.0")],
+        &gzip(b"base"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let protect = |protected: bool| {
+        warp::test::request()
+            .method("PUT")
+            .path("/admin/mods/base/protected")
+            .header("x-admin-key", "admin")
+            .json(&serde_json::json!({ "protected": protected }))
+    };
+    let delete = || warp::test::request().method("DELETE").path("/delete/base");
+
+    assert_eq!(protect(true).reply(&routes).await.status(), StatusCode::OK);
+    let response = delete().reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::LOCKED);
+    assert!(dir.path().join("base.gz").exists());
+
+    assert_eq!(protect(false).reply(&routes).await.status(), StatusCode::OK);
+    assert_eq!(delete().reply(&routes).await.status(), StatusCode::OK);
+    assert!(!dir.path().join("base.gz").exists());
+}
+
+#[tokio::test]
+async fn maintenance_answers_unavailable_except_for_health_and_admins() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = warp::test::request()
+        .method("PUT")
+        .path("/admin/maintenance")
+        .header("x-admin-key", "admin")
+        .json(&serde_json::json!({
+            "enabled": true,
+            "message": "Back after the migration.",
+            "retry_after_secs": 600,
+        }))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "600");
+    let body = json(response.body());
+    assert_eq!(body["message"], "Back after the migration.");
+    assert_eq!(body["maintenance"], true);
+
+    let response = warp::test::request().path("/health").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = warp::test::request()
+        .path("/metadata")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// An upload whose form also carries `thumbnail` as a binary part.
+fn thumbnail_upload_request(
+    fields: &[(&str, &str)],
+    thumbnail: &[u8],
+    file: &[u8],
+) -> warp::test::RequestBuilder {
+    let mut body = Vec::new();
+    write!(
+        body,
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"thumbnail\"; filename=\"thumb\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n"
+    )
+    .unwrap();
+    body.extend_from_slice(thumbnail);
+    body.extend_from_slice(b"\r\n");
+    body.extend_from_slice(&upload_form(fields, file));
+    upload_request(&[], &[]).body(body)
+}
+
+#[tokio::test]
+async fn jpeg_thumbnail_is_served_as_jpeg() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    // The start of a JFIF file, which is all the type is sniffed from.
+    let jpeg = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00\xff\xd9";
+    let response = thumbnail_upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        jpeg,
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+
+    // The second request is answered from the stored type.
+    for _ in 0..2 {
+        let response = warp::test::request()
+            .path("/thumbnail/alpha")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/jpeg");
+        assert_eq!(response.body().as_ref(), jpeg.as_slice());
+    }
+}
+
+#[tokio::test]
+async fn missing_thumbnails_lists_empty_and_unreadable_ones() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let response = thumbnail_upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        b"\xff\xd8\xff\xe0",
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    for id in ["beta", "gamma"] {
+        let response = upload_request(
+            &[("id", id), ("title", id), ("version", "1.0.0")],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+    db.get()
+        .unwrap()
+        .execute(
+            "UPDATE mods SET thumbnail = 'not base64!' WHERE id = 'gamma'",
+            [],
+        )
+        .unwrap();
+
+    let response = warp::test::request()
+        .path("/admin/missing-thumbnails")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = warp::test::request()
+        .path("/admin/missing-thumbnails")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let missing = json(response.body());
+    let missing: Vec<_> = missing
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["id"].as_str().unwrap(),
+                entry["reason"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(missing, [("beta", "empty"), ("gamma", "unreadable")]);
+}
+
+#[tokio::test]
+async fn raw_uploads_are_gzipped_when_auto_gzip_is_on() {
+    let fields = [("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")];
+    let raw = b"plain mod contents".as_slice();
+
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(&fields, raw).reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let (mut config, dir) = test_config();
+    config.auto_gzip = true;
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let response = upload_request(&fields, raw).reply(&routes).await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+    let stored = std::fs::read(dir.path().join("alpha.gz")).unwrap();
+    assert_eq!(gunzip(&stored), raw);
+    let server_gzipped: bool = db
+        .get()
+        .unwrap()
+        .query_row(
+            "SELECT server_gzipped FROM mods WHERE id = 'alpha'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(server_gzipped);
+}
+
+#[tokio::test]
+async fn author_stats_total_mods_downloads_and_bytes() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let mut total_bytes = 0;
+    for (id, author) in [("alpha", "Ann Lee"), ("beta", "Ann Lee"), ("gamma", "Bo")] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("author", author),
+            ],
+            &gzip(id.repeat(50).as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        if author == "Ann Lee" {
+            total_bytes += json(response.body())["size_bytes"].as_u64().unwrap();
+        }
+    }
+    for path in [
+        "/download/alpha",
+        "/download/alpha",
+        "/download/beta",
+        "/download/gamma",
+    ] {
+        let response = warp::test::request().path(path).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = warp::test::request()
+        .path("/authors/Ann%20Lee/stats")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json(response.body()),
+        serde_json::json!({
+            "author": "Ann Lee",
+            "mods": 2,
+            "downloads": 3,
+            "total_bytes": total_bytes,
+        })
+    );
+    let response = warp::test::request()
+        .path("/authors/Nobody/stats")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn exceeding_the_upload_rate_answers_with_retry_after() {
+    let (mut config, _dir) = test_config();
+    config.upload_rate = Some(RateLimit {
+        per_second: 1.0 / 60.0,
+        burst: 2.0,
+    });
+    let routes = build_routes(test_db(), Arc::new(config));
+    let upload = |version: &str| {
+        upload_request(
+            &[("id", "alpha"), ("title", "Alpha"), ("version", version)],
+            &gzip(version.as_bytes()),
+        )
+    };
+
+    for version in ["1.0.0", "1.0.1"] {
+        let response = upload(version).reply(&routes).await;
+        assert!(response.status().is_success(), "{:?}", response.body());
+    }
+    let response = upload("1.0.2").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    // At one upload a minute, the emptied bucket refills a token in about
+    // a minute.
+    let retry_after: u64 = response.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((55..=60).contains(&retry_after), "{retry_after}");
+}
+
+#[tokio::test]
+async fn recompute_sizes_fills_in_missing_sizes() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let size = json(response.body())["size_bytes"].as_u64().unwrap();
+    seed_mods(&db, 1);
+    // Rows from before sizes were recorded, one of them without a file.
+    db.get()
+        .unwrap()
+        .execute(
+            "UPDATE mods SET size_bytes = NULL, file_path = CASE id
+                WHEN 'mod-0000' THEN 'mods/gone.gz' ELSE file_path END",
+            [],
+        )
+        .unwrap();
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/admin/recompute-sizes")
+        .header("x-admin-key", "admin")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json(response.body()),
+        serde_json::json!({ "checked": 2, "fixed": 1, "missing": ["mod-0000"] })
+    );
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods[0]["id"], "alpha");
+    assert_eq!(mods[0]["size_bytes"], size);
+}
+
+#[tokio::test]
+async fn tag_mode_selects_all_or_any_of_the_tags() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, tags) in [
+        ("alpha", "graphics,ui"),
+        ("beta", "graphics"),
+        ("gamma", "audio,ui"),
+        ("delta", "gameplay"),
+    ] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("tags", tags),
+            ],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    for (query, expected) in [
+        ("tags=graphics,ui", vec!["alpha"]),
+        ("tags=graphics,ui&tag_mode=all", vec!["alpha"]),
+        (
+            "tags=graphics,ui&tag_mode=any",
+            vec!["alpha", "beta", "gamma"],
+        ),
+        (
+            "tags=graphics&tag=audio&tag_mode=any",
+            vec!["alpha", "beta", "gamma"],
+        ),
+        ("tags=graphics,audio", vec![]),
+    ] {
+        let response = warp::test::request()
+            .path(&format!("/metadata?{query}"))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK, "{query}");
+        let mods = json(response.body());
+        let ids: Vec<_> = mods.as_array().unwrap().iter().map(|m| &m["id"]).collect();
+        assert_eq!(ids, expected, "{query}");
+    }
+
+    let response = warp::test::request()
+        .path("/metadata?tags=ui&tag_mode=some")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn throttled_download_takes_at_least_size_over_rate() {
+    let (mut config, _dir) = test_config();
+    config.download_bytes_per_sec = Some(80_000);
+    let routes = build_routes(test_db(), Arc::new(config));
+    // Stored, not deflated, so the file stays about 40 KB.
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::none());
+    encoder.write_all(&[7; 40_000]).unwrap();
+    let file = encoder.finish().unwrap();
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &file,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let started = std::time::Instant::now();
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    let elapsed = started.elapsed();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), file.as_slice());
+    let minimum = std::time::Duration::from_secs_f64(file.len() as f64 / 80_000.0);
+    assert!(elapsed >= minimum, "{elapsed:?} < {minimum:?}");
+}
+
+#[tokio::test]
+async fn mods_combines_search_filters_sort_and_paging() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    for (id, title, tags, author, downloads) in [
+        ("sunny", "Sunny Shader", "graphics", "Ann", 5),
+        ("dark", "Dark Shader", "graphics,ui", "Ann", 9),
+        ("pack", "Shader Pack", "graphics", "ann", 1),
+        ("lite", "Shader Lite", "graphics", "Bo", 20),
+        ("sounds", "Shader Sounds", "audio", "Ann", 30),
+        ("textures", "Textures", "graphics", "Ann", 50),
+    ] {
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", title),
+                ("version", "1.0.0"),
+                ("tags", tags),
+                ("author", author),
+            ],
+            &gzip(id.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        db.get()
+            .unwrap()
+            .execute(
+                "UPDATE mods SET downloads = ?1 WHERE id = ?2",
+                rusqlite::params![downloads, id],
+            )
+            .unwrap();
+    }
+
+    let response = warp::test::request()
+        .path("/mods?q=shader&tag=graphics&author=ANN&sort=downloads&order=desc&limit=2&offset=1")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = json(response.body());
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["limit"], 2);
+    assert_eq!(page["offset"], 1);
+    let ids: Vec<_> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| &m["id"])
+        .collect();
+    assert_eq!(ids, ["sunny", "pack"]);
+
+    let response = warp::test::request()
+        .path("/mods?sort=mtime")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn source_url_round_trips_and_malformed_ones_are_rejected() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let source = "https://example.com/mods/alpha.gz";
+    let response = upload_request(
+        &[
+            ("id", "alpha"),
+            ("title", "Alpha"),
+            ("version", "1.0.0"),
+            ("source_url", source),
+        ],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body())[0]["source_url"], source);
+
+    for malformed in ["not a url", "ftp://example.com/alpha.gz", "/mods/alpha.gz"] {
+        let response = upload_request(
+            &[
+                ("id", "beta"),
+                ("title", "Beta"),
+                ("version", "1.0.0"),
+                ("source_url", malformed),
+            ],
+            &gzip(b"beta"),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{malformed}");
+    }
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body()).as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn file_part_before_the_id_is_stored_under_the_id() {
+    let (config, dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let file = gzip(b"file first");
+    let mut body = Vec::new();
+    write!(
+        body,
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"mod.gz\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n"
+    )
+    .unwrap();
+    body.extend_from_slice(&file);
+    body.extend_from_slice(b"\r\n");
+    for (name, value) in [("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")] {
+        write!(
+            body,
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        )
+        .unwrap();
+    }
+    write!(body, "--{BOUNDARY}--\r\n").unwrap();
+
+    let response = upload_request(&[], &[]).body(body).reply(&routes).await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+    assert_eq!(std::fs::read(dir.path().join("alpha.gz")).unwrap(), file);
+    assert!(!dir.path().join(".gz").exists());
+}
+
+#[tokio::test]
+async fn refetch_replaces_the_file_from_its_source() {
+    use warp::Filter;
+
+    let upstream = warp::path!("mod.gz")
+        .map(|| gzip(b"upstream contents"))
+        .or(warp::path!("broken").map(|| {
+            warp::reply::with_status("down", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }));
+    let (addr, server) = warp::serve(upstream).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let mut uploaded = Vec::new();
+    for (id, path) in [("remote", "mod.gz"), ("flaky", "broken")] {
+        let source_url = format!("http://{addr}/{path}");
+        let response = upload_request(
+            &[
+                ("id", id),
+                ("title", id),
+                ("version", "1.0.0"),
+                ("source_url", &source_url),
+            ],
+            &gzip(b"uploaded contents"),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        uploaded.push(json(response.body())["sha256"].clone());
+    }
+    let refetch = |id: &str| {
+        warp::test::request()
+            .method("POST")
+            .path(&format!("/mods/{id}/refetch"))
+            .header("x-admin-key", "admin")
+    };
+
+    let response = refetch("remote").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::OK, "{:?}", response.body());
+    let refetched = json(response.body());
+    assert_eq!(refetched["changed"], true);
+    assert_ne!(refetched["sha256"], uploaded[0]);
+    let response = warp::test::request()
+        .path("/download/remote")
+        .reply(&routes)
+        .await;
+    assert_eq!(gunzip(response.body()), b"upstream contents");
+    let response = refetch("remote").reply(&routes).await;
+    assert_eq!(json(response.body())["changed"], false);
+
+    let response = refetch("flaky").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let response = warp::test::request()
+        .path("/download/flaky")
+        .reply(&routes)
+        .await;
+    assert_eq!(gunzip(response.body()), b"uploaded contents");
+}
+
+#[tokio::test]
+async fn stale_if_match_is_refused_with_precondition_failed() {
+    let (mut config, _dir) = test_config();
+    config.require_if_match = true;
+    let routes = build_routes(test_db(), Arc::new(config));
+    let upload = |version: &str| {
+        upload_request(
+            &[("id", "alpha"), ("title", "Alpha"), ("version", version)],
+            &gzip(version.as_bytes()),
+        )
+    };
+    let response = upload("1.0.0").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let first = response.headers()["etag"].to_str().unwrap().to_string();
+
+    let response = upload("1.1.0").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+    let response = upload("1.1.0")
+        .header("if-match", &first)
+        .reply(&routes)
+        .await;
+    assert!(response.status().is_success(), "{:?}", response.body());
+
+    // A second uploader still holding the first ETag would lose that update.
+    let response = upload("1.2.0")
+        .header("if-match", &first)
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(gunzip(response.body()), b"1.1.0");
+}
+
+#[tokio::test]
+async fn json_case_names_fields_in_snake_or_camel_case() {
+    for (case, present, absent) in [
+        (JsonCase::Snake, ["file_path", "size_bytes"], "filePath"),
+        (JsonCase::Camel, ["filePath", "sizeBytes"], "file_path"),
+    ] {
+        let (mut config, _dir) = test_config();
+        config.json_case = case;
+        let routes = build_routes(test_db(), Arc::new(config));
+        let response = upload_request(
+            &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+            &gzip(b"alpha"),
+        )
+        .reply(&routes)
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = warp::test::request().path("/metadata").reply(&routes).await;
+        let mods = json(response.body());
+        let fields = mods[0].as_object().unwrap();
+        for field in present {
+            assert!(fields.contains_key(field), "{case:?}: {fields:?}");
+        }
+        assert!(!fields.contains_key(absent), "{case:?}: {fields:?}");
+        assert_eq!(fields["id"], "alpha");
+    }
+}
+
+#[tokio::test]
+async fn create_only_upload_refuses_an_existing_id() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let upload = |contents: &[u8]| {
+        upload_request(
+            &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+            &gzip(contents),
+        )
+        .path("/upload?if_not_exists=true")
+    };
+
+    let response = upload(b"first").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = upload(b"second").reply(&routes).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(gunzip(response.body()), b"first");
+}
+
+#[tokio::test]
+async fn change_feed_pages_through_every_mutation() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    for (id, version) in [("alpha", "1.0.0"), ("beta", "1.0.0"), ("alpha", "2.0.0")] {
+        let response = upload_request(
+            &[("id", id), ("title", id), ("version", version)],
+            &gzip(version.as_bytes()),
+        )
+        .reply(&routes)
+        .await;
+        assert!(response.status().is_success(), "{:?}", response.body());
+    }
+    let response = warp::test::request()
+        .method("DELETE")
+        .path("/delete/beta")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut cursor = 0;
+    let mut seen = Vec::new();
+    let mut pages = 0;
+    loop {
+        let response = warp::test::request()
+            .path(&format!("/changes?after={cursor}&limit=2"))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = json(response.body());
+        for change in page["changes"].as_array().unwrap() {
+            assert!(change["cursor"].as_i64().unwrap() > cursor);
+            seen.push(format!(
+                "{} {}",
+                change["action"].as_str().unwrap(),
+                change["id"].as_str().unwrap()
+            ));
+        }
+        cursor = page["next_cursor"].as_i64().unwrap();
+        pages += 1;
+        if page["has_more"] == false {
+            break;
+        }
+    }
+    assert_eq!(pages, 2);
+    assert_eq!(
+        seen,
+        ["create alpha", "create beta", "update alpha", "delete beta"]
+    );
+
+    let response = warp::test::request()
+        .path(&format!("/changes?after={cursor}"))
+        .reply(&routes)
+        .await;
+    let page = json(response.body());
+    assert_eq!(page["changes"], serde_json::json!([]));
+    assert_eq!(page["next_cursor"], cursor);
+}
+
+/// Encodes 8-bit RGBA pixels as a PNG without filtering or compression, which
+/// leaves an optimizer plenty to do.
+fn raw_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let mut crc = flate2::Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    let mut scanlines = Vec::new();
+    for row in rgba.chunks(width as usize * 4) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Compression::none());
+    encoder.write_all(&scanlines).unwrap();
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &encoder.finish().unwrap());
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Decodes a non-interlaced 8-bit RGBA PNG to its pixels.
+fn decode_rgba_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+    let mut rest = &png[8..];
+    let (mut width, mut height, mut idat) = (0, 0, Vec::new());
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        match kind {
+            b"IHDR" => {
+                width = u32::from_be_bytes(data[..4].try_into().unwrap());
+                height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+                assert_eq!(&data[8..13], &[8, 6, 0, 0, 0], "not plain RGBA8");
+            }
+            b"IDAT" => idat.extend_from_slice(data),
+            _ => {}
+        }
+        rest = &rest[12 + len..];
+    }
+    let mut scanlines = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::ZlibDecoder::new(idat.as_slice()),
+        &mut scanlines,
+    )
+    .unwrap();
+
+    let stride = width as usize * 4;
+    let mut pixels = vec![0u8; stride * height as usize];
+    for (y, line) in scanlines.chunks(stride + 1).enumerate() {
+        for x in 0..stride {
+            let a = if x >= 4 {
+                pixels[y * stride + x - 4]
+            } else {
+                0
+            };
+            let b = if y > 0 {
+                pixels[(y - 1) * stride + x]
+            } else {
+                0
+            };
+            let c = if x >= 4 && y > 0 {
+                pixels[(y - 1) * stride + x - 4]
+            } else {
+                0
+            };
+            let predicted = match line[0] {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => {
+                    let p = a as i16 + b as i16 - c as i16;
+                    let (pa, pb, pc) = (
+                        (p - a as i16).abs(),
+                        (p - b as i16).abs(),
+                        (p - c as i16).abs(),
+                    );
+                    if pa <= pb && pa <= pc {
+                        a
+                    } else if pb <= pc {
+                        b
+                    } else {
+                        c
+                    }
+                }
+                filter => panic!("unknown filter {filter}"),
+            };
+            pixels[y * stride + x] = line[1 + x].wrapping_add(predicted);
+        }
+    }
+    (width, height, pixels)
+}
+
+#[tokio::test]
+async fn optimized_thumbnail_is_no_larger_and_decodes_identically() {
+    let (mut config, _dir) = test_config();
+    config.optimize_thumbnails = true;
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    // Distinct colors and varying alpha, so the optimizer can't change the
+    // color type and the pixels compare byte for byte.
+    let pixels: Vec<u8> = (0..32u32 * 32)
+        .flat_map(|i| {
+            let (x, y) = ((i % 32) as u8, (i / 32) as u8);
+            [x * 8, y * 8, x ^ y, 64 + x + y]
+        })
+        .collect();
+    let original = raw_png(32, 32, &pixels);
+    let response = thumbnail_upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &original,
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::CREATED,
+        "{:?}",
+        response.body()
+    );
+
+    let response = warp::test::request()
+        .path("/thumbnail/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.headers()["content-type"], "image/png");
+    let stored = response.body().to_vec();
+    assert!(stored.len() <= original.len());
+    assert_ne!(stored, original, "the uncompressed PNG was not optimized");
+    assert_eq!(decode_rgba_png(&stored), (32, 32, pixels));
+
+    let sizes: (i64, i64) = db
+        .get()
+        .unwrap()
+        .query_row(
+            "SELECT thumbnail_original_bytes, thumbnail_bytes FROM mods WHERE id = 'alpha'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(sizes, (original.len() as i64, stored.len() as i64));
+}
+
+#[tokio::test]
+async fn sources_list_the_local_url_and_every_mirror() {
+    let (mut config, _dir) = test_config();
+    config.download_mirrors = vec![
+        "https://eu.example.com/download/{id}".to_string(),
+        "https://cdn.example.com/files/{id}.gz".to_string(),
+    ];
+    let routes = build_routes(test_db(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = warp::test::request()
+        .path("/mods/alpha/sources")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json(response.body()),
+        serde_json::json!({
+            "id": "alpha",
+            "sources": [
+                { "url": "/download/alpha", "kind": "local" },
+                { "url": "https://eu.example.com/download/alpha", "kind": "mirror" },
+                { "url": "https://cdn.example.com/files/alpha.gz", "kind": "mirror" },
+            ],
+        })
+    );
+    let response = warp::test::request()
+        .path("/mods/missing/sources")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn multiple_ranges_come_back_as_byteranges_parts() {
+    let (config, _dir) = test_config();
+    let routes = build_routes(test_db(), Arc::new(config));
+    let file = gzip(&b"0123456789".repeat(10));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &file,
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Unsorted and overlapping, so the first two are merged.
+    let response = warp::test::request()
+        .path("/download/alpha")
+        .header("range", "bytes=20-23,0-3,2-5")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = response.headers()["content-type"].to_str().unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap_or_else(|| panic!("{content_type}"));
+
+    let body = response.body().as_ref();
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest
+        .windows(delimiter.len())
+        .position(|w| w == delimiter.as_bytes())
+    {
+        rest = &rest[start + delimiter.len()..];
+        if rest.starts_with(b"--") {
+            break;
+        }
+        let head_end = rest.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = std::str::from_utf8(&rest[..head_end])
+            .unwrap()
+            .to_ascii_lowercase();
+        let range = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-range: bytes "))
+            .unwrap()
+            .to_string();
+        let (span, _) = range.split_once('/').unwrap();
+        let (first, last) = span.split_once('-').unwrap();
+        let (first, last): (usize, usize) = (first.parse().unwrap(), last.parse().unwrap());
+        let data = &rest[head_end + 4..head_end + 4 + last - first + 1];
+        parts.push((range, data.to_vec()));
+    }
+    assert_eq!(
+        parts,
+        [
+            (format!("0-5/{}", file.len()), file[0..6].to_vec()),
+            (format!("20-23/{}", file.len()), file[20..24].to_vec()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn seed_directory_bundles_are_imported_once() {
+    let (config, _dir) = test_config();
+    let seed = tempfile::tempdir().unwrap();
+    std::fs::write(
+        seed.path().join("alpha.tar.gz"),
+        mod_archive(&[(
+            "mod.json",
+            br#"{"title": "Seeded Alpha", "version": "1.2.0", "author": "seed"}"#,
+        )]),
+    )
+    .unwrap();
+    std::fs::write(seed.path().join("alpha.png"), b"\x89PNG\r\n\x1a\nseed").unwrap();
+    std::fs::write(seed.path().join("notes.txt"), b"not a bundle").unwrap();
+    let db = test_db();
+
+    seed_from_dir(&db, &config, seed.path()).await.unwrap();
+    let routes = build_routes(db.clone(), Arc::new(config.clone()));
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    let mods = json(response.body());
+    assert_eq!(mods.as_array().unwrap().len(), 1);
+    assert_eq!(mods[0]["id"], "alpha");
+    assert_eq!(mods[0]["title"], "Seeded Alpha");
+    assert_eq!(mods[0]["version"], "1.2.0");
+    let response = warp::test::request()
+        .path("/thumbnail/alpha")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.headers()["content-type"], "image/png");
+
+    // A restart with the directory still configured leaves the mod alone.
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "2.0.0")],
+        &gzip(b"uploaded"),
+    )
+    .reply(&routes)
+    .await;
+    assert!(response.status().is_success());
+    seed_from_dir(&db, &config, seed.path()).await.unwrap();
+    let response = warp::test::request().path("/metadata").reply(&routes).await;
+    assert_eq!(json(response.body())[0]["version"], "2.0.0");
+}
+
+#[tokio::test]
+async fn analytics_csv_lists_every_day_in_the_range() {
+    let (config, _dir) = test_config();
+    let db = test_db();
+    let routes = build_routes(db.clone(), Arc::new(config));
+    let response = upload_request(
+        &[("id", "alpha"), ("title", "Alpha"), ("version", "1.0.0")],
+        &gzip(b"alpha"),
+    )
+    .reply(&routes)
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    // Noon UTC on 2024-02-28, twice on 2024-02-29, 2024-03-02, and
+    // 2024-03-03, the last just outside the range.
+    db.get()
+        .unwrap()
+        .execute_batch(
+            "INSERT INTO download_log (mod_id, downloaded_at) VALUES
+                ('alpha', 1709121600), ('alpha', 1709208000), ('alpha', 1709208001),
+                ('alpha', 1709380800), ('alpha', 1709467200), ('beta', 1709121600)",
+        )
+        .unwrap();
+
+    let response = warp::test::request()
+        .path("/mods/alpha/analytics.csv?from=2024-02-27&to=2024-03-02")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/csv")
+    );
+    assert!(
+        response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .contains("alpha-downloads.csv")
+    );
+    assert_eq!(
+        std::str::from_utf8(response.body())
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>(),
+        [
+            "date,downloads",
+            "2024-02-27,0",
+            "2024-02-28,1",
+            "2024-02-29,2",
+            "2024-03-01,0",
+            "2024-03-02,1",
+        ]
+    );
+
+    let response = warp::test::request()
+        .path("/mods/alpha/analytics.csv?from=2024-03-02&to=2024-02-27")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Years out of range are refused rather than overflowing.
+    for query in [
+        "from=9223372036854775807-01-01&to=9223372036854775807-01-02",
+        "from=1969-12-31&to=1970-01-01",
+        "to=10000-01-01",
+    ] {
+        let response = warp::test::request()
+            .path(&format!("/mods/alpha/analytics.csv?{}", query))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}